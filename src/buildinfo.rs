@@ -0,0 +1,627 @@
+//! Reader of the build information that `go build` embeds in every binary, the one
+//! `go version -m` and `runtime/debug.ReadBuildInfo` show, without running the binary.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{
+    errors::{PackError, PackResult},
+    target::GoTarget,
+};
+
+/// Name of the build report in the target directory and, with `--embed-build-info`, in the packages.
+pub(crate) const BUILD_INFO_FILE_NAME: &str = "build-info.json";
+
+/// Start of the build information blob, which is aligned on 16 bytes.
+const BUILDINFO_MAGIC: &[u8] = b"\xff Go buildinf:";
+const BUILDINFO_ALIGN: usize = 16;
+const BUILDINFO_HEADER_SIZE: usize = 32;
+/// Set in the flags of the header when the strings follow it inline (Go 1.18 and later).
+const FLAGS_VERSION_INLINE: u8 = 0x2;
+/// Length of the sentinels Go puts around the module information.
+const MODINFO_SENTINEL_SIZE: usize = 16;
+
+/// A Go module, as listed in the build information.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct Module {
+    pub path: String,
+    pub version: String,
+    /// Checksum of the module, as in `go.sum`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sum: Option<String>,
+    /// The module replacing this one with a `replace` directive.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replace: Option<Box<Module>>,
+}
+
+/// The build information of a Go binary.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct BuildInfo {
+    /// Version of the Go toolchain, e.g. `go1.24.2`.
+    pub go_version: String,
+    /// Package path of the main package.
+    pub path: String,
+    /// The main module.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub main: Option<Module>,
+    /// Every dependency module linked into the binary.
+    pub deps: Vec<Module>,
+    /// Build settings such as `-ldflags`, `CGO_ENABLED`, `GOARCH`, `GOAMD64` or `vcs.revision`.
+    pub settings: BTreeMap<String, String>,
+}
+
+/// Content of a `build-info.json` file.
+#[derive(Debug, Serialize)]
+struct BuildReport<'a> {
+    target: String,
+    binary: String,
+    #[serde(flatten)]
+    build_info: &'a BuildInfo,
+}
+
+/// Read the build information of the Go binary at `path`.
+/// `None` is returned if the binary has none, e.g. if it was not built by Go.
+pub(crate) fn read_build_info(path: &Path) -> PackResult<Option<BuildInfo>> {
+    let data = std::fs::read(path).map_err(|_| PackError::ReadFailed(path.to_path_buf()))?;
+    parse_build_info(&data)
+        .map_err(|reason| PackError::BuildInfoInvalid(path.to_path_buf(), reason))
+}
+
+/// Write the build information of the Go binary at `binary` for `target` to `build-info.json` in `dest_dir`.
+/// The path of the file is returned.
+pub(crate) fn write_build_info(
+    target: &GoTarget,
+    binary: &Path,
+    build_info: &BuildInfo,
+    dest_dir: &Path,
+) -> PackResult<PathBuf> {
+    log::debug!(
+        "[{target}] Built with {} and {} dependency modules",
+        build_info.go_version,
+        build_info.deps.len()
+    );
+
+    let report = BuildReport {
+        target: target.to_string(),
+        binary: binary.file_name().unwrap().to_string_lossy().into_owned(),
+        build_info,
+    };
+    let json_path = dest_dir.join(BUILD_INFO_FILE_NAME);
+    let json = serde_json::to_string_pretty(&report)
+        .map_err(|e| PackError::BuildInfoInvalid(binary.to_path_buf(), e.to_string()))?;
+    std::fs::write(&json_path, json + "\n")
+        .map_err(|_| PackError::CreateFailed(json_path.clone()))?;
+    Ok(json_path)
+}
+
+/// Read the modules required by `go.mod` in `module_dir`, with their checksums from `go.sum`.
+///
+/// Unlike the build information of a binary, this lists every required module, including those
+/// that are not linked into the binary, and neither the Go version nor build settings are known.
+pub(crate) fn read_go_mod(module_dir: &Path) -> PackResult<BuildInfo> {
+    let go_mod_path = module_dir.join("go.mod");
+    let go_mod = std::fs::read_to_string(&go_mod_path)
+        .map_err(|_| PackError::ReadFailed(go_mod_path.clone()))?;
+    let go_sum_path = module_dir.join("go.sum");
+    let go_sum = if go_sum_path.is_file() {
+        std::fs::read_to_string(&go_sum_path)
+            .map_err(|_| PackError::ReadFailed(go_sum_path.clone()))?
+    } else {
+        String::new()
+    };
+    parse_go_mod(&go_mod, &go_sum)
+        .map_err(|reason| PackError::BuildInfoInvalid(go_mod_path, reason))
+}
+
+fn parse_build_info(data: &[u8]) -> Result<Option<BuildInfo>, String> {
+    let Some(start) = (0..data.len().saturating_sub(BUILDINFO_HEADER_SIZE))
+        .step_by(BUILDINFO_ALIGN)
+        .find(|&offset| data[offset..].starts_with(BUILDINFO_MAGIC))
+    else {
+        return Ok(None);
+    };
+    let flags = data[start + BUILDINFO_MAGIC.len() + 1];
+    if flags & FLAGS_VERSION_INLINE == 0 {
+        return Err("build info of Go 1.17 or earlier is not supported".to_string());
+    }
+
+    let mut rest = &data[start + BUILDINFO_HEADER_SIZE..];
+    let go_version = utf8(read_bytes(&mut rest)?)?;
+    let modinfo = read_bytes(&mut rest)?;
+    // The module information is wrapped in sentinels, unless it is empty
+    let modinfo = if modinfo.len() > 2 * MODINFO_SENTINEL_SIZE {
+        utf8(&modinfo[MODINFO_SENTINEL_SIZE..modinfo.len() - MODINFO_SENTINEL_SIZE])?
+    } else {
+        ""
+    };
+
+    let mut build_info = parse_modinfo(modinfo)?;
+    build_info.go_version = go_version.to_string();
+    Ok(Some(build_info))
+}
+
+/// Read bytes prefixed with their length as an unsigned varint.
+fn read_bytes<'a>(data: &mut &'a [u8]) -> Result<&'a [u8], String> {
+    let mut length: usize = 0;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = data.split_first().ok_or("truncated build info")?;
+        *data = rest;
+        if shift >= usize::BITS {
+            return Err("invalid string length in build info".to_string());
+        }
+        length |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    if length > data.len() {
+        return Err("truncated build info".to_string());
+    }
+    let (bytes, rest) = data.split_at(length);
+    *data = rest;
+    Ok(bytes)
+}
+
+fn utf8(bytes: &[u8]) -> Result<&str, String> {
+    std::str::from_utf8(bytes).map_err(|_| "build info is not valid UTF-8".to_string())
+}
+
+/// Parse the module information, the lines `path`, `mod`, `dep`, `=>` and `build` separated by tabs.
+fn parse_modinfo(modinfo: &str) -> Result<BuildInfo, String> {
+    let mut build_info = BuildInfo {
+        go_version: String::new(),
+        path: String::new(),
+        main: None,
+        deps: Vec::new(),
+        settings: BTreeMap::new(),
+    };
+    let module = |fields: &[&str]| match fields {
+        [path, version, rest @ ..] => Ok(Module {
+            path: path.to_string(),
+            version: version.to_string(),
+            sum: rest
+                .first()
+                .filter(|sum| !sum.is_empty())
+                .map(ToString::to_string),
+            replace: None,
+        }),
+        _ => Err(format!(
+            "invalid module `{}` in build info",
+            fields.join(" ")
+        )),
+    };
+
+    for line in modinfo.lines().filter(|line| !line.is_empty()) {
+        let (kind, value) = line.split_once('\t').unwrap_or((line, ""));
+        let fields: Vec<&str> = value.split('\t').collect();
+        match kind {
+            "path" => build_info.path = value.to_string(),
+            "mod" => build_info.main = Some(module(&fields)?),
+            "dep" => build_info.deps.push(module(&fields)?),
+            "=>" => {
+                // Replaces the module on the line before
+                let replaced = build_info
+                    .deps
+                    .last_mut()
+                    .or(build_info.main.as_mut())
+                    .ok_or("replacement without a module in build info")?;
+                replaced.replace = Some(Box::new(module(&fields)?));
+            }
+            "build" => {
+                let (key, value) = split_setting(value)?;
+                build_info.settings.insert(key, value);
+            }
+            _ => log::debug!("Ignoring unknown build info line `{line}`"),
+        }
+    }
+    Ok(build_info)
+}
+
+/// Parse the `module`, `require` and `replace` directives of `go_mod`, looking up the checksums in `go_sum`.
+fn parse_go_mod(go_mod: &str, go_sum: &str) -> Result<BuildInfo, String> {
+    let mut build_info = BuildInfo {
+        go_version: String::new(),
+        path: String::new(),
+        main: None,
+        deps: Vec::new(),
+        settings: BTreeMap::new(),
+    };
+    let sum = |path: &str, version: &str| {
+        go_sum.lines().find_map(
+            |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [p, v, sum] if p == path && v == version => Some(sum.to_string()),
+                _ => None,
+            },
+        )
+    };
+    let module = |path: &str, version: &str| Module {
+        path: path.to_string(),
+        version: version.to_string(),
+        sum: sum(path, version),
+        replace: None,
+    };
+
+    // Replacements apply to every require directive, wherever they are
+    let mut replacements = Vec::new();
+    let mut block = None;
+    for line in go_mod.lines() {
+        let line = line.split("//").next().unwrap_or_default().trim();
+        let (directive, args) = match block {
+            Some(_) if line == ")" => {
+                block = None;
+                continue;
+            }
+            Some(directive) => (directive, line),
+            None => {
+                let (directive, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+                let args = args.trim();
+                if args == "(" {
+                    block = Some(directive);
+                    continue;
+                }
+                (directive, args)
+            }
+        };
+        let fields: Vec<&str> = args.split_whitespace().collect();
+        let invalid = || format!("invalid `{directive}` directive `{args}` in go.mod");
+        match (directive, &fields[..]) {
+            (_, []) => {}
+            ("module", [path]) => {
+                build_info.path = path.to_string();
+                build_info.main = Some(Module {
+                    path: path.to_string(),
+                    version: "(devel)".to_string(),
+                    sum: None,
+                    replace: None,
+                });
+            }
+            ("require", [path, version]) => build_info.deps.push(module(path, version)),
+            ("replace", [old @ .., "=>", new_path, new_version]) if old.len() <= 2 => {
+                replacements.push((old.to_vec(), module(new_path, new_version)));
+            }
+            ("replace", [old @ .., "=>", new_path]) if old.len() <= 2 => {
+                replacements.push((old.to_vec(), module(new_path, "")));
+            }
+            ("module" | "require" | "replace", _) => return Err(invalid()),
+            _ => {}
+        }
+    }
+
+    for dep in &mut build_info.deps {
+        // A replacement of a single version takes precedence over one of every version
+        let replacement = replacements
+            .iter()
+            .filter(|(old, _)| old[0] == dep.path && old.get(1).is_none_or(|v| *v == dep.version))
+            .max_by_key(|(old, _)| old.len());
+        if let Some((_, new)) = replacement {
+            dep.replace = Some(Box::new(new.clone()));
+        }
+    }
+    Ok(build_info)
+}
+
+/// Split a `key=value` build setting. Keys and values containing special characters are quoted.
+fn split_setting(setting: &str) -> Result<(String, String), String> {
+    let invalid = || format!("invalid build setting `{setting}`");
+    let (key, value) = if setting.starts_with('"') {
+        let (key, rest) = unquote_prefix(setting).ok_or_else(invalid)?;
+        (key, rest.strip_prefix('=').ok_or_else(invalid)?)
+    } else {
+        let (key, value) = setting.split_once('=').ok_or_else(invalid)?;
+        (key.to_string(), value)
+    };
+    let value = if value.starts_with('"') {
+        match unquote_prefix(value) {
+            Some((value, "")) => value,
+            _ => return Err(invalid()),
+        }
+    } else {
+        value.to_string()
+    };
+    Ok((key, value))
+}
+
+/// Unquote the Go string literal at the start of `quoted`, as written by `strconv.Quote`.
+/// The unquoted string and the text after the literal are returned.
+fn unquote_prefix(quoted: &str) -> Option<(String, &str)> {
+    let mut chars = quoted.strip_prefix('"')?.char_indices();
+    let mut unquoted = String::new();
+    let hex = |chars: &mut std::str::CharIndices, digits: usize| {
+        let code = (0..digits)
+            .map(|_| chars.next().and_then(|(_, c)| c.to_digit(16)))
+            .try_fold(0u32, |code, digit| Some(code * 16 + digit?))?;
+        char::from_u32(code)
+    };
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Some((unquoted, &quoted[index + 2..])),
+            '\\' => unquoted.push(match chars.next()?.1 {
+                'a' => '\x07',
+                'b' => '\x08',
+                'f' => '\x0c',
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                'v' => '\x0b',
+                'x' => hex(&mut chars, 2)?,
+                'u' => hex(&mut chars, 4)?,
+                'U' => hex(&mut chars, 8)?,
+                escaped => escaped,
+            }),
+            c => unquoted.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODINFO: &str = "path\tgithub.com/xtls/xray-core/main\n\
+        mod\tgithub.com/xtls/xray-core\tv1.8.24\th1:main=\n\
+        dep\tgithub.com/miekg/dns\tv1.1.62\th1:dns=\n\
+        dep\tgolang.org/x/net\tv0.30.0\n\
+        =>\tgithub.com/fork/net\tv0.31.0\th1:fork=\n\
+        dep\tgithub.com/local/module\tv1.0.0\n\
+        =>\t../module\t(devel)\t\n\
+        build\t-buildmode=exe\n\
+        build\t-ldflags=\"-s -w -buildid=\"\n\
+        build\tCGO_ENABLED=0\n\
+        build\tGOAMD64=v3\n\
+        build\tvcs.modified=false\n";
+
+    fn module(path: &str, version: &str, sum: Option<&str>) -> Module {
+        Module {
+            path: path.to_string(),
+            version: version.to_string(),
+            sum: sum.map(ToString::to_string),
+            replace: None,
+        }
+    }
+
+    /// A build information blob of Go 1.18 or later.
+    fn blob(go_version: &str, modinfo: &str) -> Vec<u8> {
+        let mut data = vec![0; 48];
+        data.extend(BUILDINFO_MAGIC);
+        data.extend([8, FLAGS_VERSION_INLINE]);
+        data.resize(48 + BUILDINFO_HEADER_SIZE, 0);
+        data.push(go_version.len() as u8);
+        data.extend(go_version.as_bytes());
+        let modinfo = [
+            &[0xaa; MODINFO_SENTINEL_SIZE],
+            modinfo.as_bytes(),
+            &[0xbb; 16],
+        ]
+        .concat();
+        // Longer than 127 bytes, so the length takes two bytes
+        data.extend([
+            (modinfo.len() as u8 & 0x7f) | 0x80,
+            (modinfo.len() >> 7) as u8,
+        ]);
+        data.extend(modinfo);
+        data.extend([0; 64]);
+        data
+    }
+
+    #[test]
+    fn parses_modinfo() {
+        let build_info = parse_modinfo(MODINFO).unwrap();
+        assert_eq!(build_info.path, "github.com/xtls/xray-core/main");
+        assert_eq!(
+            build_info.main,
+            Some(module(
+                "github.com/xtls/xray-core",
+                "v1.8.24",
+                Some("h1:main=")
+            ))
+        );
+        assert_eq!(
+            build_info.deps,
+            [
+                module("github.com/miekg/dns", "v1.1.62", Some("h1:dns=")),
+                Module {
+                    replace: Some(Box::new(module(
+                        "github.com/fork/net",
+                        "v0.31.0",
+                        Some("h1:fork=")
+                    ))),
+                    ..module("golang.org/x/net", "v0.30.0", None)
+                },
+                Module {
+                    replace: Some(Box::new(module("../module", "(devel)", None))),
+                    ..module("github.com/local/module", "v1.0.0", None)
+                },
+            ]
+        );
+        assert_eq!(
+            build_info.settings,
+            BTreeMap::from(
+                [
+                    ("-buildmode", "exe"),
+                    ("-ldflags", "-s -w -buildid="),
+                    ("CGO_ENABLED", "0"),
+                    ("GOAMD64", "v3"),
+                    ("vcs.modified", "false"),
+                ]
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_modinfo() {
+        for modinfo in [
+            "mod\tgithub.com/xtls/xray-core",
+            "=>\tgithub.com/fork/net\tv0.31.0",
+            "build\tCGO_ENABLED",
+        ] {
+            assert!(parse_modinfo(modinfo).is_err(), "{modinfo}");
+        }
+        // Unknown lines are skipped
+        assert_eq!(parse_modinfo("future\tline").unwrap().path, "");
+    }
+
+    #[test]
+    fn splits_quoted_settings() {
+        for (setting, key, value) in [
+            ("GOOS=linux", "GOOS", "linux"),
+            ("-tags=", "-tags", ""),
+            (
+                "-ldflags=\"-X main.version=1 -s\"",
+                "-ldflags",
+                "-X main.version=1 -s",
+            ),
+            (
+                "-gcflags=\"all=-l \\\"-N\\\"\"",
+                "-gcflags",
+                "all=-l \"-N\"",
+            ),
+            ("\"key with=sign\"=value", "key with=sign", "value"),
+            (
+                "\"tab\\tkey\"=\"new\\nline \\u00e9\\x41\"",
+                "tab\tkey",
+                "new\nline éA",
+            ),
+        ] {
+            assert_eq!(
+                split_setting(setting),
+                Ok((key.to_string(), value.to_string())),
+                "{setting}"
+            );
+        }
+        for setting in [
+            "no-separator",
+            "\"unterminated=value",
+            "\"key\"value",
+            "key=\"value\" trailing",
+            "key=\"bad \\u00zz escape\"",
+        ] {
+            assert!(split_setting(setting).is_err(), "{setting}");
+        }
+    }
+
+    #[test]
+    fn unquotes_prefixes() {
+        assert_eq!(
+            unquote_prefix("\"a\\\\b\"=rest"),
+            Some(("a\\b".to_string(), "=rest"))
+        );
+        assert_eq!(unquote_prefix("\"\""), Some((String::new(), "")));
+        assert_eq!(unquote_prefix("\"é\"x"), Some(("é".to_string(), "x")));
+        assert_eq!(unquote_prefix("not quoted"), None);
+        assert_eq!(unquote_prefix("\"trailing backslash\\"), None);
+    }
+
+    #[test]
+    fn reads_length_prefixed_bytes() {
+        let mut data: &[u8] = &[3, b'a', b'b', b'c', 0x81, 0x01, 0xff];
+        assert_eq!(read_bytes(&mut data), Ok(&b"abc"[..]));
+        // 0x81 0x01 is 129, more than what is left
+        assert!(read_bytes(&mut data).is_err());
+
+        let long = [[0x80, 0x01].as_slice(), &[b'x'; 128]].concat();
+        let mut data = long.as_slice();
+        assert_eq!(read_bytes(&mut data).map(<[u8]>::len), Ok(128));
+        assert!(data.is_empty());
+
+        for invalid in [&[][..], &[0x80], &[0xff; 11]] {
+            let mut data = invalid;
+            assert!(read_bytes(&mut data).is_err(), "{invalid:02x?}");
+        }
+    }
+
+    #[test]
+    fn parses_build_info_blobs() {
+        let build_info = parse_build_info(&blob("go1.24.2", MODINFO))
+            .unwrap()
+            .unwrap();
+        assert_eq!(build_info.go_version, "go1.24.2");
+        assert_eq!(build_info.path, "github.com/xtls/xray-core/main");
+        assert_eq!(build_info.deps.len(), 3);
+        assert_eq!(build_info.settings["GOAMD64"], "v3");
+
+        assert_eq!(parse_build_info(b"\x7fELF no build info"), Ok(None));
+
+        let mut old = blob("go1.17", MODINFO);
+        old[48 + BUILDINFO_MAGIC.len() + 1] = 0;
+        assert!(parse_build_info(&old).is_err());
+
+        let data = blob("go1.24.2", MODINFO);
+        assert!(parse_build_info(&data[..data.len() - 100]).is_err());
+    }
+
+    #[test]
+    fn parses_go_mod() {
+        let go_mod = "module github.com/xtls/xray-core // the core\n\
+            \n\
+            go 1.24\n\
+            \n\
+            require github.com/miekg/dns v1.1.62\n\
+            require (\n\
+            \tgolang.org/x/net v0.30.0\n\
+            \tgithub.com/local/module v1.0.0 // indirect\n\
+            \tgithub.com/old/module v1.2.0\n\
+            )\n\
+            \n\
+            replace golang.org/x/net => github.com/fork/net v0.31.0\n\
+            replace (\n\
+            \tgithub.com/local/module => ../module\n\
+            \tgithub.com/old/module v1.1.0 => github.com/never/used v1.0.0\n\
+            \tgithub.com/old/module v1.2.0 => github.com/old/fixed v1.2.1\n\
+            \tgithub.com/old/module => github.com/old/any v9.9.9\n\
+            )\n";
+        let go_sum = "github.com/miekg/dns v1.1.62 h1:dns=\n\
+            github.com/miekg/dns v1.1.62/go.mod h1:dnsmod=\n\
+            github.com/fork/net v0.31.0 h1:fork=\n";
+        let build_info = parse_go_mod(go_mod, go_sum).unwrap();
+        assert_eq!(build_info.path, "github.com/xtls/xray-core");
+        assert_eq!(
+            build_info.main,
+            Some(module("github.com/xtls/xray-core", "(devel)", None))
+        );
+        let replaced = |path, version, new: Module| Module {
+            replace: Some(Box::new(new)),
+            ..module(path, version, None)
+        };
+        assert_eq!(
+            build_info.deps,
+            [
+                module("github.com/miekg/dns", "v1.1.62", Some("h1:dns=")),
+                replaced(
+                    "golang.org/x/net",
+                    "v0.30.0",
+                    module("github.com/fork/net", "v0.31.0", Some("h1:fork="))
+                ),
+                replaced(
+                    "github.com/local/module",
+                    "v1.0.0",
+                    module("../module", "", None)
+                ),
+                replaced(
+                    "github.com/old/module",
+                    "v1.2.0",
+                    module("github.com/old/fixed", "v1.2.1", None)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_go_mod() {
+        for go_mod in [
+            "module a b",
+            "require github.com/miekg/dns",
+            "require (\n\tgithub.com/miekg/dns v1 extra\n)",
+            "replace a => b c d",
+        ] {
+            assert!(parse_go_mod(go_mod, "").is_err(), "{go_mod}");
+        }
+    }
+}
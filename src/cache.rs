@@ -0,0 +1,424 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    checksum::{sha256_file, to_hex},
+    errors::{PackError, PackResult},
+};
+
+/// Serializes read-modify-write cycles of the index within this process.
+static INDEX_LOCK: Mutex<()> = Mutex::new(());
+
+const INDEX_FILE_NAME: &str = "index.json";
+const BLOBS_DIR_NAME: &str = "blobs";
+/// Prefix of the partial downloads in the blobs directory.
+const TEMP_PREFIX: &str = ".download-";
+/// Partial downloads are left alone by [`Cache::prune`] until they have not been written to for this long,
+/// since another run may still be downloading them.
+const TEMP_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A downloaded file in the [`Cache`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    /// URL the file was downloaded from.
+    pub url: String,
+    /// SHA256 checksum of the file as a lowercase hex string. The file is stored under this name.
+    pub sha256: String,
+    pub size: u64,
+    /// When the file was downloaded or last confirmed to match upstream, in seconds since the Unix epoch.
+    pub fetched_at: i64,
+}
+impl CacheEntry {
+    /// Time since [`CacheEntry::fetched_at`].
+    pub fn age(&self) -> Duration {
+        Duration::from_secs((now() - self.fetched_at).max(0) as u64)
+    }
+}
+
+/// Content-addressed cache of downloaded files.
+///
+/// Files are stored under `blobs/<sha256>`, and `index.json` maps every URL to the checksum of its content.
+/// An entry younger than the TTL is used without any network access. An older entry is used
+/// only if the upstream checksum still matches it.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+impl Cache {
+    /// Entries are reused without asking upstream for one day by default.
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+    pub fn new(dir: impl Into<PathBuf>, ttl: Duration) -> Self {
+        Cache {
+            dir: dir.into(),
+            ttl,
+        }
+    }
+
+    /// `$XDG_CACHE_HOME/xray-pack` or its platform equivalent.
+    pub fn default_dir() -> Option<PathBuf> {
+        let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+        Some(cache_dir.join("xray-pack"))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// All entries, ordered by URL.
+    pub fn entries(&self) -> PackResult<Vec<CacheEntry>> {
+        Ok(self.read_index()?.into_values().collect())
+    }
+
+    /// Whether the entry is younger than the TTL.
+    pub fn is_fresh(&self, entry: &CacheEntry) -> bool {
+        entry.age() < self.ttl
+    }
+
+    /// Remove the entries older than the TTL, and files that no entry refers to.
+    /// Partial downloads are only removed once they are abandoned. The removed entries are returned.
+    pub fn prune(&self) -> PackResult<Vec<CacheEntry>> {
+        let _lock = INDEX_LOCK.lock().unwrap();
+        let (kept, removed): (BTreeMap<_, _>, BTreeMap<_, _>) = self
+            .read_index()?
+            .into_iter()
+            .partition(|(_, entry)| self.is_fresh(entry));
+        self.write_index(&kept)?;
+
+        let blobs_dir = self.dir.join(BLOBS_DIR_NAME);
+        if let Ok(blobs) = std::fs::read_dir(&blobs_dir) {
+            for blob in blobs.flatten() {
+                let name = blob.file_name().to_string_lossy().into_owned();
+                if name.starts_with(TEMP_PREFIX) && !is_abandoned(&blob.path()) {
+                    continue;
+                }
+                if !kept.values().any(|entry| entry.sha256 == name) {
+                    log::debug!("Removing {}", blob.path().display());
+                    std::fs::remove_file(blob.path())
+                        .map_err(|_| PackError::DeleteFailed(blob.path()))?;
+                }
+            }
+        }
+
+        Ok(removed.into_values().collect())
+    }
+
+    /// Remove the whole cache directory.
+    pub fn clear(&self) -> PackResult<()> {
+        let _lock = INDEX_LOCK.lock().unwrap();
+        if self.dir.exists() {
+            std::fs::remove_dir_all(&self.dir)
+                .map_err(|_| PackError::DeleteFailed(self.dir.clone()))?;
+        }
+        Ok(())
+    }
+
+    /// The entry of `url`, if its file is still present and intact.
+    pub(crate) fn lookup(&self, url: &str) -> PackResult<Option<CacheEntry>> {
+        let Some(entry) = self.read_index()?.remove(url) else {
+            return Ok(None);
+        };
+        let blob = self.blob_path(&entry.sha256);
+        if blob.is_file() && sha256_file(&blob)? == entry.sha256 {
+            Ok(Some(entry))
+        } else {
+            log::warn!("Cached file of {url} is missing or corrupted, ignoring it");
+            Ok(None)
+        }
+    }
+
+    /// Copy the cached file of `entry` to `dest`.
+    pub(crate) fn copy_to(&self, entry: &CacheEntry, dest: &Path) -> PackResult<()> {
+        let blob = self.blob_path(&entry.sha256);
+        std::fs::copy(&blob, dest).map_err(|_| PackError::CopyFailed(blob, dest.to_path_buf()))?;
+        Ok(())
+    }
+
+    /// Mark the entry of `url` as confirmed to match upstream now.
+    pub(crate) fn touch(&self, url: &str) -> PackResult<()> {
+        let _lock = INDEX_LOCK.lock().unwrap();
+        let mut index = self.read_index()?;
+        if let Some(entry) = index.get_mut(url) {
+            entry.fetched_at = now();
+        }
+        self.write_index(&index)
+    }
+
+    /// A path in the cache directory to download `url` to before it is stored with [`Cache::store`].
+    /// It only depends on `url`, so that a later run resumes an interrupted download.
+    pub(crate) fn temp_path(&self, url: &str) -> PackResult<PathBuf> {
+        let blobs_dir = self.dir.join(BLOBS_DIR_NAME);
+        std::fs::create_dir_all(&blobs_dir)
+            .map_err(|_| PackError::CreateFailed(blobs_dir.clone()))?;
+        Ok(blobs_dir.join(format!(
+            "{TEMP_PREFIX}{}",
+            to_hex(&Sha256::digest(url.as_bytes()))
+        )))
+    }
+
+    /// Move a verified download into the cache as the content of `url`.
+    pub(crate) fn store(&self, url: &str, file: &Path, sha256: &str) -> PackResult<CacheEntry> {
+        let blob = self.blob_path(sha256);
+        let size = std::fs::metadata(file)
+            .map_err(|_| PackError::ReadFailed(file.to_path_buf()))?
+            .len();
+        std::fs::rename(file, &blob)
+            .map_err(|_| PackError::CopyFailed(file.to_path_buf(), blob.clone()))?;
+
+        let entry = CacheEntry {
+            url: url.to_string(),
+            sha256: sha256.to_string(),
+            size,
+            fetched_at: now(),
+        };
+        let _lock = INDEX_LOCK.lock().unwrap();
+        let mut index = self.read_index()?;
+        index.insert(url.to_string(), entry.clone());
+        self.write_index(&index)?;
+        log::debug!("Cached {url} as {}", blob.display());
+
+        Ok(entry)
+    }
+
+    fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join(BLOBS_DIR_NAME).join(sha256)
+    }
+
+    fn read_index(&self) -> PackResult<BTreeMap<String, CacheEntry>> {
+        let path = self.dir.join(INDEX_FILE_NAME);
+        if !path.is_file() {
+            return Ok(BTreeMap::new());
+        }
+        let content = std::fs::read(&path).map_err(|_| PackError::ReadFailed(path.clone()))?;
+        match serde_json::from_slice::<BTreeMap<String, CacheEntry>>(&content) {
+            Ok(mut index) => {
+                // The checksum is used as a file name, so anything else must not get near the file system
+                index.retain(|url, entry| {
+                    let valid = entry.sha256.len() == 64
+                        && entry
+                            .sha256
+                            .chars()
+                            .all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase());
+                    if !valid {
+                        log::warn!("Ignoring the cache entry of {url} with invalid checksum");
+                    }
+                    valid
+                });
+                Ok(index)
+            }
+            Err(e) => {
+                log::warn!("Ignoring corrupted cache index {}: {e}", path.display());
+                Ok(BTreeMap::new())
+            }
+        }
+    }
+
+    /// Replace the index atomically, so that a crash never leaves a partial index behind.
+    fn write_index(&self, index: &BTreeMap<String, CacheEntry>) -> PackResult<()> {
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|_| PackError::CreateFailed(self.dir.clone()))?;
+        let path = self.dir.join(INDEX_FILE_NAME);
+        let temp_path = self
+            .dir
+            .join(format!("{INDEX_FILE_NAME}.{}", std::process::id()));
+        let content = serde_json::to_vec_pretty(index).expect("the index is always serializable");
+        std::fs::write(&temp_path, content)
+            .map_err(|_| PackError::CreateFailed(temp_path.clone()))?;
+        std::fs::rename(&temp_path, &path).map_err(|_| PackError::CreateFailed(path.clone()))?;
+        Ok(())
+    }
+}
+
+/// Whether the partial download at `path` has not been written to for [`TEMP_MAX_AGE`].
+fn is_abandoned(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age >= TEMP_MAX_AGE)
+}
+
+/// Current time in seconds since the Unix epoch.
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT: &[u8] = b"geoip";
+
+    /// Store `CONTENT` as the content of `url`.
+    fn store(cache: &Cache, url: &str) -> CacheEntry {
+        let temp = cache.temp_path(url).unwrap();
+        std::fs::write(&temp, CONTENT).unwrap();
+        cache
+            .store(url, &temp, &sha256_file(&temp).unwrap())
+            .unwrap()
+    }
+
+    /// Pretend that the entry of `url` was fetched `age` ago.
+    fn age(cache: &Cache, url: &str, age: Duration) {
+        let mut index = cache.read_index().unwrap();
+        index.get_mut(url).unwrap().fetched_at = now() - age.as_secs() as i64;
+        cache.write_index(&index).unwrap();
+    }
+
+    #[test]
+    fn stores_and_looks_up_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path(), Cache::DEFAULT_TTL);
+        assert_eq!(cache.lookup("https://example.com/geoip.dat").unwrap(), None);
+
+        let entry = store(&cache, "https://example.com/geoip.dat");
+        assert_eq!(entry.size, CONTENT.len() as u64);
+        assert!(cache.blob_path(&entry.sha256).is_file());
+        assert_eq!(
+            cache.lookup("https://example.com/geoip.dat").unwrap(),
+            Some(entry.clone())
+        );
+        assert_eq!(cache.entries().unwrap(), std::slice::from_ref(&entry));
+
+        let dest = dir.path().join("geoip.dat");
+        cache.copy_to(&entry, &dest).unwrap();
+        assert_eq!(std::fs::read(dest).unwrap(), CONTENT);
+        // The temporary file was moved into the cache
+        let blobs: Vec<_> = std::fs::read_dir(dir.path().join(BLOBS_DIR_NAME))
+            .unwrap()
+            .map(|blob| blob.unwrap().file_name())
+            .collect();
+        assert_eq!(blobs, [entry.sha256.as_str()]);
+    }
+
+    #[test]
+    fn entries_expire_after_the_ttl() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path(), Duration::from_secs(3600));
+        store(&cache, "https://example.com/geoip.dat");
+        let entry = cache.entries().unwrap().remove(0);
+        assert!(cache.is_fresh(&entry));
+        assert!(!Cache::new(dir.path(), Duration::ZERO).is_fresh(&entry));
+
+        age(
+            &cache,
+            "https://example.com/geoip.dat",
+            Duration::from_secs(7200),
+        );
+        let entry = cache.entries().unwrap().remove(0);
+        assert!(!cache.is_fresh(&entry));
+        assert!(entry.age() >= Duration::from_secs(7200));
+        cache.touch("https://example.com/geoip.dat").unwrap();
+        assert!(cache.is_fresh(&cache.entries().unwrap()[0]));
+    }
+
+    #[test]
+    fn prunes_expired_entries_and_orphan_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path(), Duration::from_secs(3600));
+        let blobs_dir = dir.path().join(BLOBS_DIR_NAME);
+        store(&cache, "https://example.com/old.dat");
+        age(
+            &cache,
+            "https://example.com/old.dat",
+            Duration::from_secs(7200),
+        );
+        std::fs::write(blobs_dir.join("orphan"), "orphan").unwrap();
+        // A download in progress, and an abandoned one
+        let downloading = cache
+            .temp_path("https://example.com/downloading.dat")
+            .unwrap();
+        std::fs::write(&downloading, "partial").unwrap();
+        let abandoned = cache
+            .temp_path("https://example.com/abandoned.dat")
+            .unwrap();
+        std::fs::File::create(&abandoned)
+            .unwrap()
+            .set_modified(SystemTime::now() - TEMP_MAX_AGE)
+            .unwrap();
+
+        let removed = cache.prune().unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].url, "https://example.com/old.dat");
+        assert!(cache.entries().unwrap().is_empty());
+        assert!(!blobs_dir.join(&removed[0].sha256).exists());
+        assert!(!blobs_dir.join("orphan").exists());
+        assert!(downloading.exists());
+        assert!(!abandoned.exists());
+
+        // Fresh entries and their files are kept
+        let entry = store(&cache, "https://example.com/new.dat");
+        assert!(cache.prune().unwrap().is_empty());
+        assert!(cache.blob_path(&entry.sha256).is_file());
+    }
+
+    #[test]
+    fn clears_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path().join("cache"), Cache::DEFAULT_TTL);
+        cache.clear().unwrap();
+        store(&cache, "https://example.com/geoip.dat");
+        cache.clear().unwrap();
+        assert!(!cache.dir().exists());
+        assert!(cache.entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn ignores_corrupted_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path(), Cache::DEFAULT_TTL);
+        let entry = store(&cache, "https://example.com/geoip.dat");
+        std::fs::write(cache.blob_path(&entry.sha256), "tampered").unwrap();
+        assert_eq!(cache.lookup("https://example.com/geoip.dat").unwrap(), None);
+        std::fs::remove_file(cache.blob_path(&entry.sha256)).unwrap();
+        assert_eq!(cache.lookup("https://example.com/geoip.dat").unwrap(), None);
+
+        std::fs::write(dir.path().join(INDEX_FILE_NAME), "{not json").unwrap();
+        assert!(cache.entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn ignores_entries_with_invalid_checksums() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path(), Cache::DEFAULT_TTL);
+        let entry = store(&cache, "https://example.com/geoip.dat");
+        let mut index = cache.read_index().unwrap();
+        for (url, sha256) in [
+            ("https://example.com/short", "abc"),
+            ("https://example.com/path", "../../../../etc/passwd"),
+            ("https://example.com/unicode", "é".repeat(32).as_str()),
+            ("https://example.com/upper", &entry.sha256.to_uppercase()),
+        ] {
+            index.insert(
+                url.to_string(),
+                CacheEntry {
+                    url: url.to_string(),
+                    sha256: sha256.to_string(),
+                    ..entry.clone()
+                },
+            );
+        }
+        cache.write_index(&index).unwrap();
+        assert_eq!(cache.entries().unwrap(), [entry]);
+        assert_eq!(cache.lookup("https://example.com/path").unwrap(), None);
+    }
+}
@@ -0,0 +1,210 @@
+use std::{
+    ffi::OsStr,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::errors::{PackError, PackResult};
+
+/// Convert a digest to a lowercase hex string
+pub(crate) fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Calculate the SHA256 checksum of a file as a lowercase hex string
+pub(crate) fn sha256_file(file_path: &Path) -> PackResult<String> {
+    let file_contents =
+        std::fs::read(file_path).map_err(|_| PackError::ReadFailed(file_path.to_path_buf()))?;
+    Ok(to_hex(&Sha256::digest(&file_contents)))
+}
+
+/// Digests of a release archive, as listed in the official `.dgst` files.
+#[derive(Debug, Clone)]
+pub(crate) struct Digests {
+    pub md5: String,
+    pub sha1: String,
+    pub sha256: String,
+    pub sha512: String,
+}
+impl Digests {
+    /// Calculate all digests of a file in a single pass.
+    pub fn of_file(file_path: &Path) -> PackResult<Self> {
+        let mut file = std::fs::File::open(file_path)
+            .map_err(|_| PackError::ReadFailed(file_path.to_path_buf()))?;
+
+        let mut md5 = md5::Md5::new();
+        let mut sha1 = sha1::Sha1::new();
+        let mut sha256 = Sha256::new();
+        let mut sha512 = Sha512::new();
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let len = file
+                .read(&mut buffer)
+                .map_err(|_| PackError::ReadFailed(file_path.to_path_buf()))?;
+            if len == 0 {
+                break;
+            }
+            md5.update(&buffer[..len]);
+            sha1.update(&buffer[..len]);
+            sha256.update(&buffer[..len]);
+            sha512.update(&buffer[..len]);
+        }
+
+        Ok(Digests {
+            md5: to_hex(&md5.finalize()),
+            sha1: to_hex(&sha1.finalize()),
+            sha256: to_hex(&sha256.finalize()),
+            sha512: to_hex(&sha512.finalize()),
+        })
+    }
+
+    /// Content of the `.dgst` file, in the format of `openssl dgst` used by official Xray-core releases.
+    pub fn to_dgst(&self) -> String {
+        format!(
+            "MD5= {}\nSHA1= {}\nSHA2-256= {}\nSHA2-512= {}\n",
+            self.md5, self.sha1, self.sha256, self.sha512
+        )
+    }
+}
+
+/// The file name of an archive, which a path ending in `..` does not have.
+fn archive_name(archive_path: &Path) -> PackResult<&OsStr> {
+    archive_path.file_name().ok_or_else(|| {
+        PackError::InvalidPackageName(format!("{} has no file name", archive_path.display()))
+    })
+}
+
+/// Write `<archive>.dgst` next to the archive. The digests and the path of the `.dgst` file are returned.
+pub(crate) fn write_dgst(archive_path: &Path) -> PackResult<(Digests, PathBuf)> {
+    let mut dgst_name = archive_name(archive_path)?.to_os_string();
+    dgst_name.push(".dgst");
+    let dgst_path = archive_path.with_file_name(dgst_name);
+
+    let digests = Digests::of_file(archive_path)?;
+    std::fs::write(&dgst_path, digests.to_dgst())
+        .map_err(|_| PackError::CreateFailed(dgst_path.clone()))?;
+    log::debug!("Wrote digests to {}", dgst_path.display());
+
+    Ok((digests, dgst_path))
+}
+
+/// Write a `SHA256SUMS` file in the format of `sha256sum`, listing every archive by its file name.
+/// The lines are sorted by file name, so that the file does not depend on the order of the targets.
+pub(crate) fn write_sha256sums(dest_dir: &Path, archives: &[(&Path, &str)]) -> PackResult<PathBuf> {
+    let sums_path = dest_dir.join("SHA256SUMS");
+    let mut lines = archives
+        .iter()
+        .map(|(path, sha256)| {
+            Ok((
+                archive_name(path)?.to_string_lossy().into_owned(),
+                sha256.to_string(),
+            ))
+        })
+        .collect::<PackResult<Vec<_>>>()?;
+    lines.sort();
+    let content: String = lines
+        .iter()
+        .map(|(name, sha256)| format!("{sha256}  {name}\n"))
+        .collect();
+    std::fs::write(&sums_path, content).map_err(|_| PackError::CreateFailed(sums_path.clone()))?;
+    log::info!("Wrote checksums of all packages to {}", sums_path.display());
+
+    Ok(sums_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    #[test]
+    fn digests_of_known_vectors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("abc");
+        std::fs::write(&path, b"abc").unwrap();
+
+        let digests = Digests::of_file(&path).unwrap();
+        assert_eq!(digests.md5, "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(digests.sha1, "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(digests.sha256, ABC_SHA256);
+        assert_eq!(
+            digests.sha512,
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+        );
+        assert_eq!(sha256_file(&path).unwrap(), ABC_SHA256);
+    }
+
+    #[test]
+    fn digests_of_files_larger_than_the_buffer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("large");
+        let content: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &content).unwrap();
+
+        let digests = Digests::of_file(&path).unwrap();
+        assert_eq!(digests.sha256, to_hex(&Sha256::digest(&content)));
+        assert_eq!(digests.sha512, to_hex(&Sha512::digest(&content)));
+    }
+
+    #[test]
+    fn writes_dgst_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("xray-linux-64.zip");
+        std::fs::write(&path, b"abc").unwrap();
+
+        let (digests, dgst_path) = write_dgst(&path).unwrap();
+        assert_eq!(dgst_path, dir.path().join("xray-linux-64.zip.dgst"));
+        assert_eq!(
+            std::fs::read_to_string(&dgst_path).unwrap(),
+            format!(
+                "MD5= 900150983cd24fb0d6963f7d28e17f72\n\
+                 SHA1= a9993e364706816aba3e25717850c26c9cd0d89d\n\
+                 SHA2-256= {ABC_SHA256}\n\
+                 SHA2-512= {}\n",
+                digests.sha512
+            )
+        );
+    }
+
+    #[test]
+    fn writes_sorted_sha256sums() {
+        let dir = tempfile::tempdir().unwrap();
+        let linux = dir.path().join("xray-linux-64.zip");
+        let windows = dir.path().join("xray-windows-64.zip");
+        let android = dir.path().join("xray-android-arm64-v8a.zip");
+
+        let sums_path = write_sha256sums(
+            dir.path(),
+            &[(&linux, "11"), (&windows, "22"), (&android, ABC_SHA256)],
+        )
+        .unwrap();
+        assert_eq!(sums_path, dir.path().join("SHA256SUMS"));
+        assert_eq!(
+            std::fs::read_to_string(&sums_path).unwrap(),
+            format!(
+                "{ABC_SHA256}  xray-android-arm64-v8a.zip\n\
+                 11  xray-linux-64.zip\n\
+                 22  xray-windows-64.zip\n"
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_archive_paths_without_file_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("..");
+
+        assert!(matches!(
+            write_dgst(&path),
+            Err(PackError::InvalidPackageName(_))
+        ));
+        assert!(matches!(
+            write_sha256sums(dir.path(), &[(&path, ABC_SHA256)]),
+            Err(PackError::InvalidPackageName(_))
+        ));
+    }
+}
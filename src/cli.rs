@@ -1,8 +1,18 @@
-use std::{fmt::Display, path::PathBuf, sync::LazyLock};
+use std::{collections::BTreeMap, path::PathBuf, sync::LazyLock, time::Duration};
 
 use clap::{Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use xray_pack::{
+    ArchiveFormat, Cache, Core, DEFAULT_WINTUN_VERSION, GeoFile, GeoSource, GeodataSource,
+    GoTarget, HttpOptions, Mirror, Naming, PackJob, PackResult, Proxy, Region, SbomFormat, Source,
+    Wintun,
+};
 
-pub static ROOT: LazyLock<PathBuf> = LazyLock::new(|| std::env::current_dir().unwrap());
+/// The current directory, or `.` if it cannot be determined (e.g. it was deleted).
+pub static ROOT: LazyLock<PathBuf> =
+    LazyLock::new(|| std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")));
+
+pub const DEFAULT_GCFLAGS: &str = "all:-l=4";
 
 #[derive(Debug, Parser)]
 #[command(
@@ -10,13 +20,18 @@ pub static ROOT: LazyLock<PathBuf> = LazyLock::new(|| std::env::current_dir().un
     version,
     about,
     long_about = "\
-Script to build and package Xray-core.
+Script to build and package Xray-core and v2ray-core.
 
-Xray-core will be compiled and packaged as `Xray-{version}-{arch}-{system}.zip`. \
-You can install the package with the official installation script from <https://github.com/XTLS/Xray-install/raw/main/install-release.sh>.
+The core will be compiled and packaged as `{core}-{version}-{arch}-{goos}.zip`, e.g. `xray-main-amd64-v3-linux.zip`, \
+where `{arch}` includes the microarchitecture level (`--naming descriptive`, the default). \
+With `--naming official`, the package is named like the official releases, e.g. `Xray-linux-64.zip`, \
+and you can install it with the official installation script from <https://github.com/XTLS/Xray-install/raw/main/install-release.sh>. \
+With `--naming template`, the package is named by `--name-template`, e.g. `xray-{version}-{target}`. \
+`--format` selects other archive formats than zip.
 
 There are several options to maximize the performance of Xray-core:
-+ `GOAMD64`, `GO386`, `GOARM`, `GOARM64`, etc. environment variables: Specify the microarchitecture of the CPU.
++ `--goamd64`, `--go386`, `--goarm`, `--goarm64`, `--gomips` and `--gomips64` (or the `GOAMD64`, `GO386`, etc. environment variables): \
+    Specify the microarchitecture of the CPU. The level is part of the package name, e.g. `xray-main-amd64-v3-linux.zip`.
 + `GOEXPERIMENT` environment variable: Specify the experimental features of the Go compiler.
     `jsonv2`, `newinliner` might improve performance.
 + `CGO_ENABLED` environment variable: Specify whether to enable CGO. `CGO_ENABLED=0` is recommended for better performance. \
@@ -30,20 +45,120 @@ pub struct Args {
 
     /// To build xray or v2ray
     #[command(subcommand)]
-    pub target: CompileTarget,
+    pub command: Command,
 
     #[command(flatten)]
-    pub go_target: GoTarget,
+    pub go_target: GoTargetOptions,
 
     #[command(flatten)]
     pub download_options: DownloadOptions,
 
+    #[command(flatten)]
+    pub package_options: PackageOptions,
+
+    #[arg(
+        short,
+        long,
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u16).range(1..),
+        help = "Maximum number of `go build` processes to run concurrently when building several targets"
+    )]
+    pub jobs: u16,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Read configuration from this file instead of `xray-pack.toml` in the current directory \
+                and `$XDG_CONFIG_HOME/xray-pack/xray-pack.toml`"
+    )]
+    pub config: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Apply the named `[profile.<PROFILE>]` section of the configuration file"
+    )]
+    pub profile: Option<String>,
+
     #[arg(short, long, default_value_t = false, help = "Enable verbose output")]
     pub verbose: bool,
+
+    #[arg(
+        short,
+        long,
+        default_value_t = false,
+        conflicts_with = "verbose",
+        help = "Only log warnings and errors, and hide the progress bars"
+    )]
+    pub quiet: bool,
+}
+
+impl Args {
+    /// Convert the command line arguments into a [`PackJob`].
+    ///
+    /// Panics if the command does not build anything.
+    pub fn to_job(&self) -> PackResult<PackJob> {
+        let (core, version, gcflags, ldflags) = self
+            .command
+            .build_options()
+            .expect("only xray and v2ray commands can be converted into a job");
+        let source = if self.path_options.from_source {
+            Source::Clone
+        } else {
+            Source::Path(self.path_options.source_path.clone())
+        };
+
+        let mut builder = PackJob::builder(core)
+            .version(version)
+            .targets(self.go_target.targets())
+            .region(self.download_options.region)
+            .geodata(!self.download_options.no_geodata)
+            .cache(if self.download_options.no_cache {
+                None
+            } else {
+                self.download_options.cache()
+            })
+            .offline(self.download_options.offline)
+            .mirrors(self.download_options.mirror.iter().cloned())
+            .http(self.download_options.http())
+            .wintun(self.download_options.wintun())
+            .source(source)
+            .output_path(&self.path_options.output_path)
+            .formats(self.package_options.format.iter().copied())
+            .naming(self.package_options.naming())
+            .embed_build_info(self.package_options.embed_build_info)
+            .sboms(self.package_options.sbom.iter().copied())
+            .gcflags(gcflags)
+            .jobs(self.jobs.into())
+            .wxray(matches!(
+                self.command,
+                Command::Xray {
+                    no_wxray: false,
+                    ..
+                }
+            ))
+            .verbose(self.verbose)
+            .progress(!self.quiet);
+        if let Some(ldflags) = ldflags {
+            builder = builder.ldflags(ldflags);
+        }
+        if let Some(source) = self.download_options.geodata_source() {
+            builder = builder.geodata_source(source.clone());
+        }
+        for extra in &self.download_options.extra_geodata {
+            builder = builder.extra_geodata(&extra.name, extra.geo_file());
+        }
+        if let Some(geoip) = self.download_options.geoip() {
+            builder = builder.geoip(geoip);
+        }
+        if let Some(geosite) = self.download_options.geosite() {
+            builder = builder.geosite(geosite);
+        }
+        builder.build()
+    }
 }
 
 #[derive(Debug, Subcommand)]
-pub enum CompileTarget {
+pub enum Command {
     Xray {
         #[command(flatten)]
         compile_options: XrayCompileOptions,
@@ -54,10 +169,17 @@ pub enum CompileTarget {
             default_value = "main"
         )]
         xray_version: String,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Do not build `wxray.exe`, Xray-core without a console window, for Windows targets"
+        )]
+        no_wxray: bool,
     },
     V2ray {
         #[command(flatten)]
-        compile_options: XrayCompileOptions,
+        compile_options: V2rayCompileOptions,
 
         #[arg(
             long,
@@ -66,38 +188,60 @@ pub enum CompileTarget {
         )]
         v2ray_version: String,
     },
+    /// Inspect the configuration file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Manage the cache of downloaded files
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
 }
-impl Display for CompileTarget {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl Command {
+    /// Returns the core, version, `-gcflags` and `-ldflags` of the xray and v2ray commands.
+    pub fn build_options(&self) -> Option<(Core, &str, &str, Option<&str>)> {
         match self {
-            CompileTarget::Xray { .. } => write!(f, "Xray-core"),
-            CompileTarget::V2ray { .. } => write!(f, "v2ray-core"),
+            Command::Xray {
+                compile_options,
+                xray_version,
+                ..
+            } => Some((
+                Core::Xray,
+                xray_version,
+                &compile_options.gcflags,
+                compile_options.ldflags.as_deref(),
+            )),
+            Command::V2ray {
+                compile_options,
+                v2ray_version,
+            } => Some((
+                Core::V2ray,
+                v2ray_version,
+                &compile_options.gcflags,
+                compile_options.ldflags.as_deref(),
+            )),
+            Command::Config { .. } | Command::Cache { .. } => None,
         }
     }
 }
-impl CompileTarget {
-    const XRAY_CORE_REPO: &'static str = "https://github.com/XTLS/Xray-core.git";
-    const V2RAY_CORE_REPO: &'static str = "https://github.com/v2fly/v2ray-core.git";
 
-    pub fn repo_url(&self) -> &'static str {
-        match self {
-            CompileTarget::Xray { .. } => Self::XRAY_CORE_REPO,
-            CompileTarget::V2ray { .. } => Self::V2RAY_CORE_REPO,
-        }
-    }
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print the effective configuration after merging the configuration files,
+    /// the selected profile and the command line options
+    Show,
+}
 
-    pub fn repo_version(&self) -> String {
-        match self {
-            CompileTarget::V2ray {
-                compile_options: _,
-                v2ray_version,
-            } => v2ray_version.clone(),
-            CompileTarget::Xray {
-                compile_options: _,
-                xray_version,
-            } => xray_version.clone(),
-        }
-    }
+#[derive(Debug, Subcommand)]
+pub enum CacheCommand {
+    /// List the cached files
+    List,
+    /// Remove the cached files older than `--cache-ttl`
+    Prune,
+    /// Remove all cached files
+    Clear,
 }
 
 #[derive(Debug, Parser)]
@@ -119,7 +263,7 @@ pub struct PathOptions {
         long,
         help = "Path to the source code directory.",
         conflicts_with = "from_source",
-        default_value = ROOT.to_str().unwrap()
+        default_value_os_t = ROOT.clone()
     )]
     pub source_path: PathBuf,
 
@@ -134,7 +278,7 @@ pub struct PathOptions {
 
 #[derive(Debug, Parser)]
 pub struct XrayCompileOptions {
-    #[arg(long, help = "-gcflags for Go compiler", default_value = "all:-l=4")]
+    #[arg(long, help = "-gcflags for Go compiler", default_value = DEFAULT_GCFLAGS)]
     pub gcflags: String,
 
     #[arg(
@@ -154,7 +298,7 @@ pub struct V2rayCompileOptions {
         long_help = "-gcflags for Go compiler. \
                      Note that the default value of v2ray's official release is to leave it empty. \
                      However, inline is highly recommended for better performance.",
-        default_value = "all:-l=4"
+        default_value = DEFAULT_GCFLAGS
     )]
     pub gcflags: String,
 
@@ -167,7 +311,7 @@ pub struct V2rayCompileOptions {
 }
 
 #[derive(Debug, Parser)]
-pub struct GoTarget {
+pub struct GoTargetOptions {
     #[arg(
         long,
         help = "Specify GOOS for the Go compiler. This will override `GOARCH` and `go env GOOS` values.",
@@ -181,6 +325,176 @@ pub struct GoTarget {
         default_value = "amd64"
     )]
     pub goarch: String,
+
+    #[arg(
+        long,
+        visible_alias = "target",
+        value_name = "GOOS/GOARCH[/LEVEL]",
+        value_delimiter = ',',
+        conflicts_with_all = ["goos", "goarch"],
+        help = "Build several targets in one invocation, e.g. `linux/amd64,linux/arm64,windows/amd64`. \
+                A microarchitecture level can be appended, e.g. `linux/amd64/v3`. \
+                Can be repeated. Overrides `--goos` and `--goarch`."
+    )]
+    pub targets: Vec<GoTarget>,
+
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        value_parser = variant_parser("amd64"),
+        help = "GOAMD64 of amd64 targets: v1, v2, v3 or v4"
+    )]
+    pub goamd64: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        value_parser = variant_parser("arm"),
+        help = "GOARM of arm targets: 5, 6 or 7, optionally followed by `,softfloat` or `,hardfloat`"
+    )]
+    pub goarm: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        value_parser = variant_parser("arm64"),
+        help = "GOARM64 of arm64 targets: v8.0 to v8.9 or v9.0 to v9.5, optionally followed by `,lse` and `,crypto`"
+    )]
+    pub goarm64: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        value_parser = variant_parser("386"),
+        help = "GO386 of 386 targets: sse2 or softfloat"
+    )]
+    pub go386: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        value_parser = variant_parser("mips"),
+        help = "GOMIPS of mips and mipsle targets: hardfloat or softfloat"
+    )]
+    pub gomips: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        value_parser = variant_parser("mips64"),
+        help = "GOMIPS64 of mips64 and mips64le targets: hardfloat or softfloat"
+    )]
+    pub gomips64: Option<String>,
+}
+impl GoTargetOptions {
+    /// Returns all targets to build, in the order given on the command line.
+    /// Falls back to the single `--goos`/`--goarch` pair if `--targets` is not given.
+    /// Targets without a microarchitecture level take the one of `--goamd64`, `--goarm`, etc.
+    pub fn targets(&self) -> Vec<GoTarget> {
+        let targets = if self.targets.is_empty() {
+            vec![GoTarget::new(&self.goos, &self.goarch)]
+        } else {
+            self.targets.clone()
+        };
+        targets
+            .into_iter()
+            .map(|mut target| {
+                if target.variant.is_none() {
+                    target.variant = match target.variant_var() {
+                        Some("GOAMD64") => self.goamd64.clone(),
+                        Some("GOARM") => self.goarm.clone(),
+                        Some("GOARM64") => self.goarm64.clone(),
+                        Some("GO386") => self.go386.clone(),
+                        Some("GOMIPS") => self.gomips.clone(),
+                        Some("GOMIPS64") => self.gomips64.clone(),
+                        _ => None,
+                    };
+                }
+                target
+            })
+            .collect()
+    }
+}
+
+/// Value parser of a microarchitecture level of `goarch`.
+fn variant_parser(goarch: &'static str) -> impl Fn(&str) -> Result<String, String> + Clone {
+    move |variant| {
+        GoTarget::check_variant(goarch, variant)?;
+        Ok(variant.to_string())
+    }
+}
+
+/// An additional geo file to package under its own name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ExtraGeodata {
+    pub name: String,
+    pub source: GeoSource,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+impl ExtraGeodata {
+    pub fn geo_file(&self) -> GeoFile {
+        geo_file(&Some(self.source.clone()), &self.sha256).expect("the source is always set")
+    }
+}
+impl std::str::FromStr for ExtraGeodata {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, source) = s
+            .split_once('=')
+            .ok_or_else(|| format!("`{s}` is not in the form NAME=PATH|URL"))?;
+        Ok(ExtraGeodata {
+            name: name.to_string(),
+            source: source.parse().expect("parsing a geo source never fails"),
+            sha256: None,
+        })
+    }
+}
+
+fn geo_file(source: &Option<GeoSource>, sha256: &Option<String>) -> Option<GeoFile> {
+    let geo_file = GeoFile::new(source.clone()?);
+    Some(match sha256 {
+        Some(sha256) => geo_file.with_sha256(sha256),
+        None => geo_file,
+    })
+}
+
+fn parse_proxy(url: &str) -> Result<String, String> {
+    Proxy::check_url(url)?;
+    Ok(url.to_string())
+}
+
+/// Parse a SHA256 checksum given as 64 hex digits.
+pub fn parse_sha256(sha256: &str) -> Result<String, String> {
+    if sha256.len() == 64 && sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(sha256.to_lowercase())
+    } else {
+        Err(format!(
+            "`{sha256}` is not a SHA256 checksum of 64 hex digits"
+        ))
+    }
+}
+
+/// Check that `thumbprint` is the SHA-1 or SHA-256 of a certificate in hex.
+pub fn parse_thumbprint(thumbprint: &str) -> Result<String, String> {
+    if matches!(thumbprint.len(), 40 | 64) && thumbprint.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(thumbprint.to_lowercase())
+    } else {
+        Err(format!(
+            "`{thumbprint}` is not a certificate thumbprint of 40 or 64 hex digits"
+        ))
+    }
+}
+
+/// Check that `version` looks like a wintun release, e.g. `0.14.1`.
+pub fn parse_wintun_version(version: &str) -> Result<String, String> {
+    if !version.is_empty() && version.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        Ok(version.to_string())
+    } else {
+        Err(format!("`{version}` is not a wintun version like `0.14.1`"))
+    }
 }
 
 #[derive(Debug, Parser)]
@@ -191,29 +505,313 @@ pub struct DownloadOptions {
         default_value = "china-mainland"
     )]
     pub region: Region,
+
+    #[arg(
+        long,
+        value_name = "PATH|URL",
+        help = "Package this geoip.dat instead of the one of --region. \
+                It is verified against a sibling `.sha256sum` file if there is one"
+    )]
+    pub geoip: Option<GeoSource>,
+
+    #[arg(
+        long,
+        value_name = "SHA256",
+        requires = "geoip",
+        value_parser = parse_sha256,
+        help = "Expected SHA256 of the file given with --geoip"
+    )]
+    pub geoip_sha256: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PATH|URL",
+        help = "Package this geosite.dat instead of the one of --region. \
+                It is verified against a sibling `.sha256sum` file if there is one"
+    )]
+    pub geosite: Option<GeoSource>,
+
+    #[arg(
+        long,
+        value_name = "SHA256",
+        requires = "geosite",
+        value_parser = parse_sha256,
+        help = "Expected SHA256 of the file given with --geosite"
+    )]
+    pub geosite_sha256: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Download the geo files from this source of the `geodata-sources` table \
+                of the configuration file instead of --region"
+    )]
+    pub geodata_source: Option<String>,
+
+    /// Sources selectable with `--geodata-source`, from the configuration file.
+    #[arg(skip)]
+    pub geodata_sources: BTreeMap<String, GeodataSource>,
+
+    #[arg(
+        long,
+        value_name = "NAME=PATH|URL",
+        help = "Also package this geo file as NAME, e.g. `geosite_IR.dat=https://example.com/geosite.dat`. \
+                NAME must differ from the other files of the package. Can be given several times"
+    )]
+    pub extra_geodata: Vec<ExtraGeodata>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["geoip", "geosite"],
+        help = "Package without geoip.dat and geosite.dat"
+    )]
+    pub no_geodata: bool,
+
+    #[arg(
+        long,
+        value_name = "VERSION",
+        default_value = DEFAULT_WINTUN_VERSION,
+        value_parser = parse_wintun_version,
+        help = "Release of wintun to package for Windows targets of Xray-core"
+    )]
+    pub wintun_version: String,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Package the wintun release from this zip archive instead of downloading it"
+    )]
+    pub wintun_zip: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "SHA256",
+        value_parser = parse_sha256,
+        help = "Expected SHA256 of the wintun zip archive. \
+                Required for releases whose checksum is not known to xray-pack"
+    )]
+    pub wintun_sha256: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "SIGNER",
+        value_parser = parse_thumbprint,
+        help = "Accept a wintun.dll signed by the certificate with this SHA-1 or SHA-256 thumbprint. \
+                Can be given several times. Default to the certificate of WireGuard LLC"
+    )]
+    pub wintun_signer: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        help = "Try this mirror before the original URL of every downloaded file, \
+                e.g. `https://cdn.jsdelivr.net/gh/{owner}/{repo}@{ref}/{file}`. \
+                Can be given several times, mirrors are tried in order. \
+                Placeholders: {url}, {host}, {path}, and {owner}, {repo}, {ref}, {file} for raw GitHub content"
+    )]
+    pub mirror: Vec<Mirror>,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        value_parser = parse_proxy,
+        help = "Proxy for the git clone, every download and `go build`: http, https, socks5 or socks5h. \
+                The git clone needs an http or https proxy. Default to `HTTPS_PROXY` or `ALL_PROXY`"
+    )]
+    pub proxy: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "HOSTS",
+        value_delimiter = ',',
+        help = "Hosts to reach without the proxy, e.g. `localhost,.example.com`. Default to `NO_PROXY`"
+    )]
+    pub no_proxy: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 10,
+        help = "Time allowed to connect to a server"
+    )]
+    pub connect_timeout: u64,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 30,
+        help = "Time allowed between two reads of a download before it is retried"
+    )]
+    pub read_timeout: u64,
+
+    #[arg(
+        long,
+        default_value_t = 3,
+        help = "Retries of a failed download, with exponential backoff starting at 1 second. \
+                Interrupted downloads resume where they stopped"
+    )]
+    pub retries: u32,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Directory of the download cache. Default to `$XDG_CACHE_HOME/xray-pack`"
+    )]
+    pub cache_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "HOURS",
+        default_value_t = 24,
+        help = "Use cached files younger than this without checking upstream. \
+                Older files are used only if the upstream checksum still matches"
+    )]
+    pub cache_ttl: u64,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Download every file instead of using the cache"
+    )]
+    pub no_cache: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with = "no_cache",
+        help = "Forbid all network access. Requires --source-path, downloaded files from the cache \
+                regardless of their age, and Go modules from `vendor` or a populated GOMODCACHE"
+    )]
+    pub offline: bool,
 }
+impl DownloadOptions {
+    /// Timeouts and retries given with `--connect-timeout`, `--read-timeout` and `--retries`.
+    pub fn http(&self) -> HttpOptions {
+        HttpOptions {
+            connect_timeout: Duration::from_secs(self.connect_timeout),
+            read_timeout: Duration::from_secs(self.read_timeout),
+            retries: self.retries,
+            proxy: self.proxy(),
+            ..HttpOptions::default()
+        }
+    }
+
+    /// The proxy given with `--proxy` and `--no-proxy`, falling back to the environment variables.
+    pub fn proxy(&self) -> Proxy {
+        let mut proxy = Proxy::from_env();
+        if let Some(url) = &self.proxy {
+            proxy = proxy
+                .with_url(url)
+                .expect("the proxy URL is checked when parsing");
+        }
+        if !self.no_proxy.is_empty() {
+            proxy = proxy.with_no_proxy(self.no_proxy.clone());
+        }
+        proxy
+    }
+
+    /// The source selected by `--geodata-source`, if it is defined.
+    pub fn geodata_source(&self) -> Option<&GeodataSource> {
+        self.geodata_sources.get(self.geodata_source.as_ref()?)
+    }
+
+    /// The file given with `--geoip` and `--geoip-sha256`.
+    pub fn geoip(&self) -> Option<GeoFile> {
+        geo_file(&self.geoip, &self.geoip_sha256)
+    }
+
+    /// The file given with `--geosite` and `--geosite-sha256`.
+    pub fn geosite(&self) -> Option<GeoFile> {
+        geo_file(&self.geosite, &self.geosite_sha256)
+    }
+
+    /// The wintun release given with `--wintun-version`, `--wintun-zip`, `--wintun-sha256` and `--wintun-signer`.
+    pub fn wintun(&self) -> Wintun {
+        let mut wintun = Wintun::new(&self.wintun_version);
+        if let Some(zip) = &self.wintun_zip {
+            wintun = wintun.with_zip(zip);
+        }
+        if let Some(sha256) = &self.wintun_sha256 {
+            wintun = wintun.with_sha256(sha256);
+        }
+        if !self.wintun_signer.is_empty() {
+            wintun = wintun.with_signers(&self.wintun_signer);
+        }
+        wintun
+    }
 
-/// Three main restricted regions.
-#[derive(Debug, Clone, Copy, ValueEnum)]
-pub enum Region {
-    ChinaMainland,
-    Russia,
-    Iran,
+    /// The cache selected by `--cache-dir` and `--cache-ttl`, ignoring `--no-cache`.
+    pub fn cache(&self) -> Option<Cache> {
+        self.cache_dir
+            .clone()
+            .or_else(Cache::default_dir)
+            .map(|dir| Cache::new(dir, Duration::from_secs(self.cache_ttl * 60 * 60)))
+    }
 }
-impl Region {
-    /// Returns the URL for downloading the geoip and geodat files for the region.
-    /// Add `geoip.dat` and `geosite.dat` to the URL for downloading.
-    pub fn url(&self) -> &str {
-        match self {
-            Region::ChinaMainland => {
-                "https://raw.githubusercontent.com/Loyalsoldier/v2ray-rules-dat/release/"
-            }
-            Region::Russia => {
-                "https://raw.githubusercontent.com/runetfreedom/russia-v2ray-rules-dat/release/"
-            }
-            Region::Iran => {
-                "https://raw.githubusercontent.com/Chocolate4U/Iran-v2ray-rules/release/"
+
+#[derive(Debug, Parser)]
+pub struct PackageOptions {
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "zip",
+        help = "Archive formats of the package. Several formats can be given, e.g. `zip,tar.xz`"
+    )]
+    pub format: Vec<ArchiveFormat>,
+
+    #[arg(
+        long,
+        default_value = "descriptive",
+        help = "Naming scheme of the package. `official` gives the asset names of the official releases, \
+                e.g. `Xray-linux-64.zip`; `descriptive` gives `xray-{version}-{arch}-{system}.zip`; \
+                `template` uses `--name-template`"
+    )]
+    pub naming: NamingScheme,
+
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        help = "Name of the package without extension when `--naming template` is given. \
+                Placeholders: {target} (e.g. `linux-64`), {version}, {describe}, {goos}, {goarch}, \
+                {arch} (e.g. `amd64-v3`)"
+    )]
+    pub name_template: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "Also package build-info.json, the Go version, modules and build settings read from the binary. \
+                It is always written next to the package as `<name>.build-info.json`"
+    )]
+    pub embed_build_info: bool,
+
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        value_delimiter = ',',
+        help = "Write a software bill of materials next to every package, listing its files with their sources \
+                and digests and the Go modules of the binaries. Both formats can be given, e.g. `cyclonedx,spdx`"
+    )]
+    pub sbom: Vec<SbomFormat>,
+}
+impl PackageOptions {
+    pub fn naming(&self) -> Naming {
+        match self.naming {
+            NamingScheme::Official => Naming::Official,
+            NamingScheme::Descriptive => Naming::Descriptive,
+            NamingScheme::Template => {
+                Naming::Template(self.name_template.clone().unwrap_or_default())
             }
         }
     }
 }
+
+/// Value of `--naming`. The template itself is given with `--name-template`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NamingScheme {
+    Official,
+    Descriptive,
+    Template,
+}
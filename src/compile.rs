@@ -1,124 +1,228 @@
-use std::process::Command;
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
 
 use crate::{
-    ARGS, COLLECTED_FILES, REPOSITORY_DIR, TEMP_DIR,
-    cli::{self, CompileTarget},
     errors::{PackError, PackResult},
+    inspect,
+    job::{PackJob, WXRAY_BINARY_NAME},
+    repo::Checkout,
+    target::{Core, GoTarget},
 };
 
-pub fn build_xray(commid: &str) -> PackResult<()> {
-    log::debug!("Building Xray-core");
-    let args = ARGS.get().unwrap();
-
-    let output_name: String = {
-        let s = match args.target {
-            CompileTarget::V2ray {
-                compile_options: _,
-                v2ray_version: _,
-            } => "v2ray",
-            CompileTarget::Xray {
-                compile_options: _,
-                xray_version: _,
-            } => "xray",
-        };
-        let goos = &args.go_target.goos;
-        if goos == "windows" {
-            format!("{s}.exe")
-        } else {
-            s.to_string()
+/// Build Xray-core or v2ray-core for every target, running at most `jobs` `go build` processes at once.
+/// The paths of the compiled binaries of each target are returned in the same order as `targets`.
+///
+/// All targets are attempted even if some of them fail. Every failure is logged with its target,
+/// and the first one is returned.
+pub(crate) fn build_all(job: &PackJob, checkout: &Checkout) -> PackResult<Vec<Vec<PathBuf>>> {
+    let targets = job.targets.as_slice();
+    let jobs = job.jobs.clamp(1, targets.len().max(1));
+    log::debug!("Building {} targets with {jobs} jobs", targets.len());
+
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<PackResult<Vec<PathBuf>>>>> =
+        Mutex::new(targets.iter().map(|_| None).collect());
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(target) = targets.get(index) else {
+                        break;
+                    };
+                    let result = build_xray(job, checkout, target);
+                    if let Err(ref e) = result {
+                        log::error!("[{target}] {e}");
+                    }
+                    results.lock().unwrap()[index] = Some(result);
+                }
+            });
         }
-    };
-    let output_path = TEMP_DIR.join(output_name);
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every target is built exactly once"))
+        .collect()
+}
 
-    // change the working directory to the repository directory
-    std::env::set_current_dir(REPOSITORY_DIR.get().unwrap())
-        .expect("Failed to change working directory");
+/// Check that every Go module needed by the build is available without the network,
+/// either in the `vendor` directory of the checkout or in the module cache.
+pub(crate) fn check_offline_modules(job: &PackJob, checkout: &Checkout) -> PackResult<()> {
+    if checkout.dir.join("vendor").is_dir() {
+        log::info!("Using the vendored Go modules of {}", job.core);
+        return Ok(());
+    }
 
     let mut cmd = Command::new("go");
-    let gcflags: String = match &args.target {
-        CompileTarget::V2ray {
-            compile_options,
-            v2ray_version: _,
-        } => compile_options.gcflags.clone(),
-        CompileTarget::Xray {
-            compile_options,
-            xray_version: _,
-        } => compile_options.gcflags.clone(),
+    cmd.args(["mod", "download"]).current_dir(&checkout.dir);
+    job.http.proxy.apply_to_command(&mut cmd);
+    set_offline_env(&mut cmd, checkout);
+    let output = cmd
+        .output()
+        .map_err(|e| PackError::BuildFailed(format!("failed to run `go mod download`: {e}")))?;
+    if !output.status.success() {
+        return Err(PackError::OfflineUnavailable(format!(
+            "Go modules of {} ({})",
+            job.core,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    log::info!("Using the Go modules of {} in the module cache", job.core);
+    Ok(())
+}
+
+/// Forbid `go` from reaching the module proxy or downloading another toolchain.
+/// Modules are taken from the `vendor` directory if there is one, or else from the module cache.
+fn set_offline_env(cmd: &mut Command, checkout: &Checkout) {
+    let mod_flag = if checkout.dir.join("vendor").is_dir() {
+        "-mod=vendor"
+    } else {
+        "-mod=mod"
     };
-    let ldflags: String = match &args.target {
-        CompileTarget::V2ray {
-            compile_options,
-            v2ray_version: _,
-        } => compile_options
-            .ldflags
-            .clone()
-            .unwrap_or("-s -w -buildid=".to_string()),
-        CompileTarget::Xray {
-            compile_options,
-            xray_version: _,
-        } => compile_options.ldflags.clone().unwrap_or_else(|| {
-            format!("-X github.com/xtls/xray-core/core.build={commid} -s -w -buildid=")
-        }),
+    let goflags = match std::env::var("GOFLAGS") {
+        Ok(goflags) if !goflags.trim().is_empty() => format!("{goflags} {mod_flag}"),
+        _ => mod_flag.to_string(),
     };
+    cmd.env("GOFLAGS", goflags).env("GOPROXY", "off");
+    if std::env::var_os("GOTOOLCHAIN").is_none() {
+        cmd.env("GOTOOLCHAIN", "local");
+    }
+}
 
-    let build_args = {
-        let mut vec = vec![
-            "build",
-            "-o",
-            output_path.to_str().unwrap(),
-            "-trimpath",
-            "-gcflags",
-            &gcflags,
-            "-ldflags",
+/// Build Xray-core or v2ray-core for a single target. The paths of the compiled binaries are returned,
+/// the console binary first, followed by `wxray.exe` if [`PackJob::wxray`] applies to the target.
+///
+/// The output of `go build` is written to `build.log` in the target's temporary directory.
+pub(crate) fn build_xray(
+    job: &PackJob,
+    checkout: &Checkout,
+    target: &GoTarget,
+) -> PackResult<Vec<PathBuf>> {
+    log::info!("[{target}] Building {}", job.core);
+
+    let target_dir = job.target_dir(target)?;
+    let log_path = target_dir.join("build.log");
+    let log_file =
+        File::create(&log_path).map_err(|_| PackError::CreateFailed(log_path.clone()))?;
+
+    let ldflags = job.ldflags(&checkout.describe);
+    let mut builds = vec![(job.core.binary_name(target), ldflags.clone())];
+    if job.builds_wxray(target) {
+        // Same binary in the GUI subsystem, so that it runs without a console window
+        builds.push((
+            WXRAY_BINARY_NAME.to_string(),
+            format!("{ldflags} -H windowsgui"),
+        ));
+    }
+
+    let mut output_paths = Vec::with_capacity(builds.len());
+    for (binary_name, ldflags) in builds {
+        let output_path = target_dir.join(&binary_name);
+        let clone_log = || {
+            log_file
+                .try_clone()
+                .map_err(|_| PackError::CreateFailed(log_path.clone()))
+        };
+        let status = go_build(
+            job,
+            checkout,
+            target,
+            &output_path,
             &ldflags,
-        ];
-        if args.verbose {
+            (clone_log()?, clone_log()?),
+        )?;
+
+        if !status.success() {
+            let build_log = std::fs::read_to_string(&log_path)
+                .map_err(|_| PackError::ReadFailed(log_path.clone()))?;
+            log::error!(
+                "[{target}] Build failed, see {} for details:\n{}",
+                log_path.display(),
+                build_log.trim_end()
+            );
+            return Err(PackError::BuildFailed(format!(
+                "{target}: go build of {binary_name} exited with {status}"
+            )));
+        }
+        // Catch a stale GOOS/GOARCH or a cgo cross-compiler for another target
+        match inspect::check_binary(&output_path, target)? {
+            Some(info) => log::info!(
+                "[{target}] {binary_name} built at {}: {info}",
+                output_path.display()
+            ),
+            None => log::info!(
+                "[{target}] {binary_name} built at {}",
+                output_path.display()
+            ),
+        }
+        output_paths.push(output_path);
+    }
+
+    let build_log =
+        std::fs::read_to_string(&log_path).map_err(|_| PackError::ReadFailed(log_path.clone()))?;
+    if !build_log.trim().is_empty() {
+        log::debug!("[{target}] go build output:\n{}", build_log.trim_end());
+    }
+
+    Ok(output_paths)
+}
+
+/// Run `go build` in the repository directory, redirecting its stdout and stderr to `log`.
+fn go_build(
+    job: &PackJob,
+    checkout: &Checkout,
+    target: &GoTarget,
+    output_path: &Path,
+    ldflags: &str,
+    log: (File, File),
+) -> PackResult<ExitStatus> {
+    let mut cmd = Command::new("go");
+
+    let build_args = {
+        let mut vec = vec!["-trimpath", "-gcflags", &job.gcflags, "-ldflags", ldflags];
+        if job.verbose {
             vec.push("-v")
         }
-        match args.target {
-            CompileTarget::V2ray {
-                compile_options: _,
-                v2ray_version: _,
-            } => {}
-            CompileTarget::Xray {
-                compile_options: _,
-                xray_version: _,
-            } => vec.push("-buildvcs=false"),
+        match job.core {
+            Core::V2ray => {}
+            Core::Xray => vec.push("-buildvcs=false"),
         }
         vec.push("./main");
         vec
     };
 
-    cmd.env("GOOS", &args.go_target.goos)
-        .env("GOARCH", &args.go_target.goarch)
+    cmd.env("GOOS", &target.goos)
+        .env("GOARCH", &target.goarch)
         .env(
             "CGO_ENABLED",
             std::env::var("CGO_ENABLED").unwrap_or_else(|_| "0".to_string()),
         )
+        .arg("build")
+        .arg("-o")
+        .arg(output_path)
         .args(&build_args);
-
-    cmd.spawn()
-        .map_err(|e| PackError::BuildFailed(e.to_string()))?
-        .wait()
-        .map_err(|e| PackError::BuildFailed(e.to_string()))?;
-
-    // print stderr
-    let output = cmd
-        .output()
-        .map_err(|e| PackError::BuildFailed(e.to_string()))?;
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        log::error!("Build failed: {}", stderr);
-        return Err(PackError::BuildFailed(stderr.to_string()));
+    if let (Some(var), Some(variant)) = (target.variant_var(), &target.variant) {
+        cmd.env(var, variant);
+    }
+    job.http.proxy.apply_to_command(&mut cmd);
+    if job.offline {
+        set_offline_env(&mut cmd, checkout);
     }
 
-    log::info!("Xray built at {}", output_path.display());
-
-    // Add the compiled binary to the collected files
-    COLLECTED_FILES.lock().unwrap().push(output_path);
-
-    // change the working directory back to the original directory
-    std::env::set_current_dir(&*cli::ROOT).expect("Failed to change working directory");
-
-    Ok(())
+    cmd.current_dir(&checkout.dir)
+        .stdout(Stdio::from(log.0))
+        .stderr(Stdio::from(log.1))
+        .status()
+        .map_err(|e| PackError::BuildFailed(format!("{target}: {e}")))
 }
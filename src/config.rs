@@ -0,0 +1,713 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use clap::{ArgMatches, parser::ValueSource};
+use serde::{Deserialize, Serialize};
+use xray_pack::{
+    ArchiveFormat, Core, GeodataSource, GoTarget, PackError, PackResult, Proxy, Region, SbomFormat,
+};
+
+use crate::cli::{
+    Args, Command, DEFAULT_GCFLAGS, ExtraGeodata, NamingScheme, ROOT, parse_sha256,
+    parse_thumbprint, parse_wintun_version,
+};
+
+const CONFIG_FILE_NAME: &str = "xray-pack.toml";
+
+/// One layer of configuration. Every field mirrors the command line option of the same name.
+/// Unset fields fall through to the layer below.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Settings {
+    pub from_source: Option<bool>,
+    pub source_path: Option<PathBuf>,
+    pub output_path: Option<PathBuf>,
+    pub goos: Option<String>,
+    pub goarch: Option<String>,
+    pub targets: Option<Vec<String>>,
+    pub goamd64: Option<String>,
+    pub goarm: Option<String>,
+    pub goarm64: Option<String>,
+    pub go386: Option<String>,
+    pub gomips: Option<String>,
+    pub gomips64: Option<String>,
+    pub region: Option<Region>,
+    pub geoip: Option<String>,
+    pub geoip_sha256: Option<String>,
+    pub geosite: Option<String>,
+    pub geosite_sha256: Option<String>,
+    pub no_geodata: Option<bool>,
+    pub geodata_source: Option<String>,
+    /// Sources selectable with `geodata-source`, by name. They can only be defined in configuration files.
+    pub geodata_sources: Option<BTreeMap<String, GeodataSource>>,
+    pub extra_geodata: Option<Vec<ExtraGeodata>>,
+    pub wintun_version: Option<String>,
+    pub wintun_zip: Option<PathBuf>,
+    pub wintun_sha256: Option<String>,
+    pub wintun_signer: Option<Vec<String>>,
+    pub mirror: Option<Vec<String>>,
+    pub proxy: Option<String>,
+    pub no_proxy: Option<Vec<String>>,
+    pub connect_timeout: Option<u64>,
+    pub read_timeout: Option<u64>,
+    pub retries: Option<u32>,
+    pub cache_dir: Option<PathBuf>,
+    pub cache_ttl: Option<u64>,
+    pub no_cache: Option<bool>,
+    pub offline: Option<bool>,
+    pub format: Option<Vec<ArchiveFormat>>,
+    pub naming: Option<NamingScheme>,
+    pub name_template: Option<String>,
+    pub embed_build_info: Option<bool>,
+    pub sbom: Option<Vec<SbomFormat>>,
+    pub jobs: Option<u16>,
+    pub gcflags: Option<String>,
+    pub ldflags: Option<String>,
+    pub xray_version: Option<String>,
+    pub v2ray_version: Option<String>,
+    pub wxray: Option<bool>,
+}
+
+/// Layout of `xray-pack.toml`: top-level settings plus named `[profile.<name>]` sections.
+#[derive(Debug, Default, Deserialize)]
+struct ConfigFile {
+    #[serde(flatten)]
+    settings: Settings,
+
+    #[serde(default)]
+    profile: BTreeMap<String, Settings>,
+
+    /// Top-level keys that are not settings. `deny_unknown_fields` has no effect on flattened fields,
+    /// so they are collected here and rejected.
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+/// Returns the path of the user configuration file,
+/// `$XDG_CONFIG_HOME/xray-pack/xray-pack.toml` or its platform equivalent.
+fn user_config_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("xray-pack").join(CONFIG_FILE_NAME))
+}
+
+/// Load the configuration files and apply the profile selected with `--profile`.
+///
+/// Without `--config`, the user configuration file is loaded first and `xray-pack.toml`
+/// in the current directory is layered on top of it. Missing files are skipped.
+pub fn load(args: &Args) -> PackResult<Settings> {
+    let paths: Vec<PathBuf> = match &args.config {
+        Some(path) => vec![path.clone()],
+        None => [user_config_path(), Some(ROOT.join(CONFIG_FILE_NAME))]
+            .into_iter()
+            .flatten()
+            .filter(|path| path.is_file())
+            .collect(),
+    };
+
+    let mut settings = Settings::default();
+    let mut profiles: BTreeMap<String, Settings> = BTreeMap::new();
+    for path in paths {
+        log::debug!("Loading configuration from {}", path.display());
+        let content =
+            std::fs::read_to_string(&path).map_err(|_| PackError::ReadFailed(path.clone()))?;
+        let file = parse_config_file(&path, &content)?;
+
+        settings = settings.merge(file.settings);
+        for (name, profile) in file.profile {
+            let merged = profiles.remove(&name).unwrap_or_default().merge(profile);
+            profiles.insert(name, merged);
+        }
+    }
+
+    if let Some(name) = &args.profile {
+        let profile = profiles.remove(name).ok_or_else(|| {
+            PackError::ConfigError(format!(
+                "profile `{name}` not found. Available profiles: [{}]",
+                profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+            ))
+        })?;
+        log::info!("Using profile {name}");
+        settings = settings.merge(profile);
+    }
+
+    Ok(settings)
+}
+
+/// Parse the content of the configuration file at `path`, rejecting unknown keys.
+fn parse_config_file(path: &Path, content: &str) -> PackResult<ConfigFile> {
+    let file: ConfigFile = toml::from_str(content)
+        .map_err(|e| PackError::ConfigError(format!("{}: {e}", path.display())))?;
+    if !file.unknown.is_empty() {
+        return Err(PackError::ConfigError(format!(
+            "{}: unknown keys {}",
+            path.display(),
+            file.unknown
+                .keys()
+                .map(|key| format!("`{key}`"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    }
+    Ok(file)
+}
+
+impl Settings {
+    /// Overlay `other` on top of `self`. Every field set in `other` wins.
+    fn merge(mut self, other: Settings) -> Settings {
+        // A source path or a single target in the upper layer replaces
+        // cloning or a target matrix in the lower layer.
+        if other.source_path.is_some() && other.from_source.is_none() {
+            self.from_source = Some(false);
+        }
+        if (other.goos.is_some() || other.goarch.is_some()) && other.targets.is_none() {
+            self.targets = None;
+        }
+        // A checksum belongs to the geo file of the same layer, even if that layer gives none
+        if other.geoip.is_some() {
+            self.geoip_sha256 = None;
+        }
+        if other.geosite.is_some() {
+            self.geosite_sha256 = None;
+        }
+
+        Settings {
+            from_source: other.from_source.or(self.from_source),
+            source_path: other.source_path.or(self.source_path),
+            output_path: other.output_path.or(self.output_path),
+            goos: other.goos.or(self.goos),
+            goarch: other.goarch.or(self.goarch),
+            targets: other.targets.or(self.targets),
+            goamd64: other.goamd64.or(self.goamd64),
+            goarm: other.goarm.or(self.goarm),
+            goarm64: other.goarm64.or(self.goarm64),
+            go386: other.go386.or(self.go386),
+            gomips: other.gomips.or(self.gomips),
+            gomips64: other.gomips64.or(self.gomips64),
+            region: other.region.or(self.region),
+            geoip: other.geoip.or(self.geoip),
+            geoip_sha256: other.geoip_sha256.or(self.geoip_sha256),
+            geosite: other.geosite.or(self.geosite),
+            geosite_sha256: other.geosite_sha256.or(self.geosite_sha256),
+            no_geodata: other.no_geodata.or(self.no_geodata),
+            geodata_source: other.geodata_source.or(self.geodata_source),
+            // Sources of all layers are kept, the upper one winning for the same name
+            geodata_sources: match (self.geodata_sources, other.geodata_sources) {
+                (Some(mut sources), Some(other)) => {
+                    sources.extend(other);
+                    Some(sources)
+                }
+                (sources, other) => other.or(sources),
+            },
+            extra_geodata: other.extra_geodata.or(self.extra_geodata),
+            wintun_version: other.wintun_version.or(self.wintun_version),
+            wintun_zip: other.wintun_zip.or(self.wintun_zip),
+            wintun_sha256: other.wintun_sha256.or(self.wintun_sha256),
+            wintun_signer: other.wintun_signer.or(self.wintun_signer),
+            mirror: other.mirror.or(self.mirror),
+            proxy: other.proxy.or(self.proxy),
+            no_proxy: other.no_proxy.or(self.no_proxy),
+            connect_timeout: other.connect_timeout.or(self.connect_timeout),
+            read_timeout: other.read_timeout.or(self.read_timeout),
+            retries: other.retries.or(self.retries),
+            cache_dir: other.cache_dir.or(self.cache_dir),
+            cache_ttl: other.cache_ttl.or(self.cache_ttl),
+            no_cache: other.no_cache.or(self.no_cache),
+            offline: other.offline.or(self.offline),
+            format: other.format.or(self.format),
+            naming: other.naming.or(self.naming),
+            name_template: other.name_template.or(self.name_template),
+            embed_build_info: other.embed_build_info.or(self.embed_build_info),
+            sbom: other.sbom.or(self.sbom),
+            jobs: other.jobs.or(self.jobs),
+            gcflags: other.gcflags.or(self.gcflags),
+            ldflags: other.ldflags.or(self.ldflags),
+            xray_version: other.xray_version.or(self.xray_version),
+            v2ray_version: other.v2ray_version.or(self.v2ray_version),
+            wxray: other.wxray.or(self.wxray),
+        }
+    }
+
+    /// Fill every option that is not given explicitly on the command line from the settings.
+    pub fn apply(&self, args: &mut Args, matches: &ArgMatches) -> PackResult<()> {
+        fn explicit(matches: &ArgMatches, id: &str) -> bool {
+            matches.value_source(id) == Some(ValueSource::CommandLine)
+        }
+
+        let path_options = &mut args.path_options;
+        if !explicit(matches, "from_source") && !explicit(matches, "source_path") {
+            if let Some(from_source) = self.from_source {
+                path_options.from_source = from_source;
+            }
+            if let Some(source_path) = &self.source_path {
+                path_options.source_path = source_path.clone();
+            }
+        }
+        if !explicit(matches, "output_path")
+            && let Some(output_path) = &self.output_path
+        {
+            path_options.output_path = output_path.clone();
+        }
+
+        let go_target = &mut args.go_target;
+        if !explicit(matches, "goos")
+            && let Some(goos) = &self.goos
+        {
+            go_target.goos = goos.clone();
+        }
+        if !explicit(matches, "goarch")
+            && let Some(goarch) = &self.goarch
+        {
+            go_target.goarch = goarch.clone();
+        }
+        if !["goos", "goarch", "targets"]
+            .iter()
+            .any(|id| explicit(matches, id))
+            && let Some(targets) = &self.targets
+        {
+            go_target.targets = targets
+                .iter()
+                .map(|target| target.parse::<GoTarget>())
+                .collect::<Result<_, _>>()
+                .map_err(PackError::ConfigError)?;
+        }
+        for (id, goarch, variant, option) in [
+            ("goamd64", "amd64", &self.goamd64, &mut go_target.goamd64),
+            ("goarm", "arm", &self.goarm, &mut go_target.goarm),
+            ("goarm64", "arm64", &self.goarm64, &mut go_target.goarm64),
+            ("go386", "386", &self.go386, &mut go_target.go386),
+            ("gomips", "mips", &self.gomips, &mut go_target.gomips),
+            (
+                "gomips64",
+                "mips64",
+                &self.gomips64,
+                &mut go_target.gomips64,
+            ),
+        ] {
+            if !explicit(matches, id)
+                && let Some(variant) = variant
+            {
+                GoTarget::check_variant(goarch, variant).map_err(PackError::ConfigError)?;
+                *option = Some(variant.clone());
+            }
+        }
+
+        if !explicit(matches, "region")
+            && let Some(region) = self.region
+        {
+            args.download_options.region = region;
+        }
+        for (id, source, sha256, option, sha256_option) in [
+            (
+                "geoip",
+                &self.geoip,
+                &self.geoip_sha256,
+                &mut args.download_options.geoip,
+                &mut args.download_options.geoip_sha256,
+            ),
+            (
+                "geosite",
+                &self.geosite,
+                &self.geosite_sha256,
+                &mut args.download_options.geosite,
+                &mut args.download_options.geosite_sha256,
+            ),
+        ] {
+            // The checksum belongs to the file of the same layer
+            if !explicit(matches, id)
+                && let Some(source) = source
+            {
+                *option = Some(source.parse().expect("parsing a geo source never fails"));
+                *sha256_option = sha256
+                    .as_deref()
+                    .map(parse_sha256)
+                    .transpose()
+                    .map_err(PackError::ConfigError)?;
+            }
+        }
+        if !explicit(matches, "no_geodata")
+            && let Some(no_geodata) = self.no_geodata
+        {
+            args.download_options.no_geodata = no_geodata;
+        }
+        args.download_options.geodata_sources = self.geodata_sources.clone().unwrap_or_default();
+        if !explicit(matches, "geodata_source") && self.geodata_source.is_some() {
+            args.download_options.geodata_source = self.geodata_source.clone();
+        }
+        if let Some(name) = &args.download_options.geodata_source
+            && args.download_options.geodata_source().is_none()
+        {
+            return Err(PackError::ConfigError(format!(
+                "geodata source `{name}` not found. Available sources: [{}]",
+                args.download_options
+                    .geodata_sources
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )));
+        }
+        if !explicit(matches, "extra_geodata")
+            && let Some(extra_geodata) = &self.extra_geodata
+        {
+            args.download_options.extra_geodata = extra_geodata
+                .iter()
+                .map(|extra| {
+                    Ok(ExtraGeodata {
+                        sha256: extra.sha256.as_deref().map(parse_sha256).transpose()?,
+                        ..extra.clone()
+                    })
+                })
+                .collect::<Result<_, String>>()
+                .map_err(PackError::ConfigError)?;
+        }
+        if !explicit(matches, "wintun_version")
+            && let Some(version) = &self.wintun_version
+        {
+            args.download_options.wintun_version =
+                parse_wintun_version(version).map_err(PackError::ConfigError)?;
+        }
+        if !explicit(matches, "wintun_zip") && self.wintun_zip.is_some() {
+            args.download_options.wintun_zip = self.wintun_zip.clone();
+        }
+        if !explicit(matches, "wintun_sha256")
+            && let Some(sha256) = &self.wintun_sha256
+        {
+            args.download_options.wintun_sha256 =
+                Some(parse_sha256(sha256).map_err(PackError::ConfigError)?);
+        }
+        if !explicit(matches, "wintun_signer")
+            && let Some(signers) = &self.wintun_signer
+        {
+            args.download_options.wintun_signer = signers
+                .iter()
+                .map(|signer| parse_thumbprint(signer))
+                .collect::<Result<_, _>>()
+                .map_err(PackError::ConfigError)?;
+        }
+        if !explicit(matches, "mirror")
+            && let Some(mirror) = &self.mirror
+        {
+            args.download_options.mirror = mirror
+                .iter()
+                .map(|mirror| mirror.parse())
+                .collect::<Result<_, _>>()
+                .map_err(PackError::ConfigError)?;
+        }
+        if !explicit(matches, "proxy")
+            && let Some(proxy) = &self.proxy
+        {
+            Proxy::check_url(proxy).map_err(PackError::ConfigError)?;
+            args.download_options.proxy = Some(proxy.clone());
+        }
+        if !explicit(matches, "no_proxy")
+            && let Some(no_proxy) = &self.no_proxy
+        {
+            args.download_options.no_proxy = no_proxy.clone();
+        }
+        if !explicit(matches, "connect_timeout")
+            && let Some(connect_timeout) = self.connect_timeout
+        {
+            args.download_options.connect_timeout = connect_timeout;
+        }
+        if !explicit(matches, "read_timeout")
+            && let Some(read_timeout) = self.read_timeout
+        {
+            args.download_options.read_timeout = read_timeout;
+        }
+        if !explicit(matches, "retries")
+            && let Some(retries) = self.retries
+        {
+            args.download_options.retries = retries;
+        }
+        if !explicit(matches, "cache_dir") && self.cache_dir.is_some() {
+            args.download_options.cache_dir = self.cache_dir.clone();
+        }
+        if !explicit(matches, "cache_ttl")
+            && let Some(cache_ttl) = self.cache_ttl
+        {
+            args.download_options.cache_ttl = cache_ttl;
+        }
+        if !explicit(matches, "no_cache")
+            && let Some(no_cache) = self.no_cache
+        {
+            args.download_options.no_cache = no_cache;
+        }
+        if !explicit(matches, "offline")
+            && let Some(offline) = self.offline
+        {
+            args.download_options.offline = offline;
+        }
+        if !explicit(matches, "format")
+            && let Some(format) = &self.format
+        {
+            args.package_options.format = format.clone();
+        }
+        if !explicit(matches, "naming")
+            && let Some(naming) = self.naming
+        {
+            args.package_options.naming = naming;
+        }
+        if !explicit(matches, "name_template") && self.name_template.is_some() {
+            args.package_options.name_template = self.name_template.clone();
+        }
+        if !explicit(matches, "embed_build_info")
+            && let Some(embed_build_info) = self.embed_build_info
+        {
+            args.package_options.embed_build_info = embed_build_info;
+        }
+        if !explicit(matches, "sbom")
+            && let Some(sbom) = &self.sbom
+        {
+            args.package_options.sbom = sbom.clone();
+        }
+        if args.package_options.naming == NamingScheme::Template
+            && args.package_options.name_template.is_none()
+        {
+            return Err(PackError::ConfigError(
+                "naming scheme `template` requires `--name-template` or `name-template` in the configuration file".to_string(),
+            ));
+        }
+        if !explicit(matches, "jobs")
+            && let Some(jobs) = self.jobs
+        {
+            if jobs == 0 {
+                return Err(PackError::ConfigError(
+                    "`jobs` must be at least 1".to_string(),
+                ));
+            }
+            args.jobs = jobs;
+        }
+
+        let (gcflags, ldflags, version, version_id, file_version) = match &mut args.command {
+            Command::Xray {
+                compile_options,
+                xray_version,
+                ..
+            } => (
+                &mut compile_options.gcflags,
+                &mut compile_options.ldflags,
+                xray_version,
+                "xray_version",
+                &self.xray_version,
+            ),
+            Command::V2ray {
+                compile_options,
+                v2ray_version,
+            } => (
+                &mut compile_options.gcflags,
+                &mut compile_options.ldflags,
+                v2ray_version,
+                "v2ray_version",
+                &self.v2ray_version,
+            ),
+            Command::Config { .. } | Command::Cache { .. } => return Ok(()),
+        };
+        let (_, sub_matches) = matches
+            .subcommand()
+            .expect("xray and v2ray are subcommands");
+        if !explicit(sub_matches, "gcflags")
+            && let Some(file_gcflags) = &self.gcflags
+        {
+            *gcflags = file_gcflags.clone();
+        }
+        if !explicit(sub_matches, "ldflags") && self.ldflags.is_some() {
+            *ldflags = self.ldflags.clone();
+        }
+        if !explicit(sub_matches, version_id)
+            && let Some(file_version) = file_version
+        {
+            *version = file_version.clone();
+        }
+        if let Command::Xray { no_wxray, .. } = &mut args.command
+            && !explicit(sub_matches, "no_wxray")
+            && let Some(wxray) = self.wxray
+        {
+            *no_wxray = !wxray;
+        }
+
+        Ok(())
+    }
+
+    /// The effective configuration of the command line arguments after [`Settings::apply`].
+    /// Options of the xray and v2ray commands that are not part of `args` are taken from
+    /// `settings`, or their default values.
+    pub fn effective(args: &Args, settings: &Settings) -> Settings {
+        let gcflags = settings
+            .gcflags
+            .clone()
+            .unwrap_or_else(|| DEFAULT_GCFLAGS.to_string());
+        let xray_version = settings
+            .xray_version
+            .clone()
+            .unwrap_or_else(|| Core::Xray.default_version().to_string());
+        let v2ray_version = settings
+            .v2ray_version
+            .clone()
+            .unwrap_or_else(|| Core::V2ray.default_version().to_string());
+
+        let (gcflags, ldflags, xray_version, v2ray_version) = match &args.command {
+            Command::Xray {
+                compile_options,
+                xray_version,
+                ..
+            } => (
+                compile_options.gcflags.clone(),
+                compile_options.ldflags.clone(),
+                xray_version.clone(),
+                v2ray_version,
+            ),
+            Command::V2ray {
+                compile_options,
+                v2ray_version,
+            } => (
+                compile_options.gcflags.clone(),
+                compile_options.ldflags.clone(),
+                xray_version,
+                v2ray_version.clone(),
+            ),
+            Command::Config { .. } | Command::Cache { .. } => (
+                gcflags,
+                settings.ldflags.clone(),
+                xray_version,
+                v2ray_version,
+            ),
+        };
+
+        let path_options = &args.path_options;
+        let go_target = &args.go_target;
+        Settings {
+            from_source: Some(path_options.from_source),
+            source_path: (!path_options.from_source).then(|| path_options.source_path.clone()),
+            output_path: Some(path_options.output_path.clone()),
+            goos: Some(go_target.goos.clone()),
+            goarch: Some(go_target.goarch.clone()),
+            targets: (!go_target.targets.is_empty())
+                .then(|| go_target.targets.iter().map(ToString::to_string).collect()),
+            goamd64: go_target.goamd64.clone(),
+            goarm: go_target.goarm.clone(),
+            goarm64: go_target.goarm64.clone(),
+            go386: go_target.go386.clone(),
+            gomips: go_target.gomips.clone(),
+            gomips64: go_target.gomips64.clone(),
+            region: Some(args.download_options.region),
+            geoip: args
+                .download_options
+                .geoip
+                .as_ref()
+                .map(ToString::to_string),
+            geoip_sha256: args.download_options.geoip_sha256.clone(),
+            geosite: args
+                .download_options
+                .geosite
+                .as_ref()
+                .map(ToString::to_string),
+            geosite_sha256: args.download_options.geosite_sha256.clone(),
+            no_geodata: Some(args.download_options.no_geodata),
+            geodata_source: args.download_options.geodata_source.clone(),
+            geodata_sources: (!args.download_options.geodata_sources.is_empty())
+                .then(|| args.download_options.geodata_sources.clone()),
+            extra_geodata: (!args.download_options.extra_geodata.is_empty())
+                .then(|| args.download_options.extra_geodata.clone()),
+            wintun_version: Some(args.download_options.wintun_version.clone()),
+            wintun_zip: args.download_options.wintun_zip.clone(),
+            wintun_sha256: args.download_options.wintun_sha256.clone(),
+            wintun_signer: (!args.download_options.wintun_signer.is_empty())
+                .then(|| args.download_options.wintun_signer.clone()),
+            mirror: (!args.download_options.mirror.is_empty()).then(|| {
+                args.download_options
+                    .mirror
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect()
+            }),
+            proxy: args.download_options.proxy.clone(),
+            no_proxy: (!args.download_options.no_proxy.is_empty())
+                .then(|| args.download_options.no_proxy.clone()),
+            connect_timeout: Some(args.download_options.connect_timeout),
+            read_timeout: Some(args.download_options.read_timeout),
+            retries: Some(args.download_options.retries),
+            cache_dir: args.download_options.cache_dir.clone(),
+            cache_ttl: Some(args.download_options.cache_ttl),
+            no_cache: Some(args.download_options.no_cache),
+            offline: Some(args.download_options.offline),
+            format: Some(args.package_options.format.clone()),
+            naming: Some(args.package_options.naming),
+            name_template: args.package_options.name_template.clone(),
+            embed_build_info: Some(args.package_options.embed_build_info),
+            sbom: (!args.package_options.sbom.is_empty())
+                .then(|| args.package_options.sbom.clone()),
+            jobs: Some(args.jobs),
+            gcflags: Some(gcflags),
+            ldflags,
+            xray_version: Some(xray_version),
+            v2ray_version: Some(v2ray_version),
+            wxray: Some(match &args.command {
+                Command::Xray { no_wxray, .. } => !no_wxray,
+                _ => settings.wxray.unwrap_or(true),
+            }),
+        }
+    }
+
+    pub fn to_toml(&self) -> PackResult<String> {
+        toml::to_string_pretty(self).map_err(|e| PackError::ConfigError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> PackResult<ConfigFile> {
+        parse_config_file(Path::new("xray-pack.toml"), content)
+    }
+
+    #[test]
+    fn parses_settings_and_profiles() {
+        let file = parse("gcflags = \"all:-l\"\n[profile.release]\nldflags = \"-s -w\"\n").unwrap();
+        assert_eq!(file.settings.gcflags.as_deref(), Some("all:-l"));
+        assert_eq!(file.profile["release"].ldflags.as_deref(), Some("-s -w"));
+    }
+
+    #[test]
+    fn geo_file_checksums_follow_their_layer() {
+        let base = parse(
+            "geoip = \"/srv/geoip.dat\"\ngeoip-sha256 = \"aaaa\"\n\
+             geosite = \"/srv/geosite.dat\"\ngeosite-sha256 = \"bbbb\"\n\
+             [profile.p]\ngeoip = \"/srv/other-geoip.dat\"\n\
+             [profile.q]\ngeosite-sha256 = \"cccc\"\n",
+        )
+        .unwrap();
+        let settings = base.settings.clone().merge(base.profile["p"].clone());
+        assert_eq!(settings.geoip.as_deref(), Some("/srv/other-geoip.dat"));
+        assert_eq!(settings.geoip_sha256, None);
+        assert_eq!(settings.geosite.as_deref(), Some("/srv/geosite.dat"));
+        assert_eq!(settings.geosite_sha256.as_deref(), Some("bbbb"));
+
+        // A checksum alone still applies to the file of the lower layer
+        let settings = base.settings.merge(base.profile["q"].clone());
+        assert_eq!(settings.geosite.as_deref(), Some("/srv/geosite.dat"));
+        assert_eq!(settings.geosite_sha256.as_deref(), Some("cccc"));
+    }
+
+    #[test]
+    fn rejects_unknown_top_level_keys() {
+        let error = parse("gcflag = \"typo\"\n").unwrap_err().to_string();
+        assert!(error.contains("xray-pack.toml"), "{error}");
+        assert!(error.contains("`gcflag`"), "{error}");
+    }
+
+    #[test]
+    fn rejects_unknown_profile_keys() {
+        let error = parse("[profile.release]\nldflag = \"-s\"\n")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("xray-pack.toml"), "{error}");
+        assert!(error.contains("`ldflag`"), "{error}");
+    }
+}
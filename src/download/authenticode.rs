@@ -0,0 +1,496 @@
+//! Minimal reader of the Authenticode signature embedded in a PE file.
+//!
+//! The PE file is read by goblin, which finds the attribute certificates. Only their metadata is read here:
+//! the subject and thumbprints of the certificate that signed the file. Signers are identified by the
+//! thumbprint of their certificate, never by its names, which anyone can choose. The signature itself is
+//! not verified cryptographically: this catches a file signed by someone else, not a forged signature,
+//! and the integrity of the file comes from the checksum of the archive it is extracted from.
+
+use std::{fmt::Display, path::Path};
+
+use goblin::pe::{PE, certificate_table::AttributeCertificateType};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+use crate::{
+    checksum::to_hex,
+    errors::{PackError, PackResult},
+};
+
+/// DER encoding of the `signedData` content type, 1.2.840.113549.1.7.2.
+const OID_SIGNED_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_CONTEXT_0: u8 = 0xa0;
+
+/// The certificate that signed a PE file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Signer {
+    /// Subject of the certificate, e.g. `CN=WireGuard LLC, O=WireGuard LLC, C=US`. Only for display,
+    /// anyone can issue a certificate with any subject.
+    pub subject: String,
+    /// SHA-1 of the DER encoded certificate, in lowercase hex.
+    pub sha1: String,
+    /// SHA-256 of the DER encoded certificate, in lowercase hex.
+    pub sha256: String,
+}
+impl Signer {
+    /// Whether `thumbprint`, the SHA-1 or SHA-256 of a certificate in hex, is the one of this signer.
+    pub fn matches(&self, thumbprint: &str) -> bool {
+        let thumbprint = thumbprint.trim();
+        thumbprint.eq_ignore_ascii_case(&self.sha1) || thumbprint.eq_ignore_ascii_case(&self.sha256)
+    }
+}
+impl Display for Signer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (thumbprint {})", self.subject, self.sha1)
+    }
+}
+
+/// The machine type and the signer of a PE file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PeSignature {
+    /// `IMAGE_FILE_MACHINE_*` of the COFF header, e.g. [`goblin::pe::header::COFF_MACHINE_X86_64`].
+    pub machine: u16,
+    pub signer: Option<Signer>,
+}
+
+/// Read the machine type and the signer of the PE file at `path`.
+pub(crate) fn read_pe_signature(path: &Path) -> PackResult<PeSignature> {
+    let data = std::fs::read(path).map_err(|_| PackError::ReadFailed(path.to_path_buf()))?;
+    parse_pe(&data).map_err(|reason| PackError::SignatureFailed(path.to_path_buf(), reason))
+}
+
+fn parse_pe(data: &[u8]) -> Result<PeSignature, String> {
+    let pe = PE::parse(data).map_err(|e| format!("not a valid PE file: {e}"))?;
+    let signer = pe
+        .certificates
+        .iter()
+        .find(|certificate| {
+            certificate.certificate_type == AttributeCertificateType::PkcsSignedData
+        })
+        .map(|certificate| parse_pkcs7_signer(certificate.certificate))
+        .transpose()?;
+    Ok(PeSignature {
+        machine: pe.header.coff_header.machine,
+        signer,
+    })
+}
+
+/// A DER encoded value.
+#[derive(Debug, Clone, Copy)]
+struct Tlv<'a> {
+    tag: u8,
+    content: &'a [u8],
+    /// The whole encoding, tag and length included.
+    raw: &'a [u8],
+}
+
+/// Read the first DER value of `data`, returning it with the bytes after it.
+fn read_tlv(data: &[u8]) -> Result<(Tlv<'_>, &[u8]), String> {
+    let truncated = || "truncated DER value".to_string();
+    let tag = *data.first().ok_or_else(truncated)?;
+    let first = *data.get(1).ok_or_else(truncated)?;
+    let (length, header) = match first {
+        0..=0x7f => (first as usize, 2),
+        0x81..=0x84 => {
+            let size = (first & 0x7f) as usize;
+            let bytes = data.get(2..2 + size).ok_or_else(truncated)?;
+            let length = bytes
+                .iter()
+                .fold(0usize, |length, byte| (length << 8) | *byte as usize);
+            (length, 2 + size)
+        }
+        _ => return Err("unsupported DER length".to_string()),
+    };
+    let end = header.checked_add(length).ok_or_else(truncated)?;
+    let raw = data.get(..end).ok_or_else(truncated)?;
+    Ok((
+        Tlv {
+            tag,
+            content: &raw[header..],
+            raw,
+        },
+        &data[end..],
+    ))
+}
+
+/// Read the first DER value of `data`, which must have `tag`.
+fn expect_tlv(data: &[u8], tag: u8) -> Result<Tlv<'_>, String> {
+    let (tlv, _) = read_tlv(data)?;
+    if tlv.tag != tag {
+        return Err(format!(
+            "expected DER tag {tag:#04x}, found {:#04x}",
+            tlv.tag
+        ));
+    }
+    Ok(tlv)
+}
+
+/// The values inside a constructed DER value.
+fn children(content: &[u8]) -> Result<Vec<Tlv<'_>>, String> {
+    let mut children = Vec::new();
+    let mut rest = content;
+    while !rest.is_empty() {
+        let (child, next) = read_tlv(rest)?;
+        children.push(child);
+        rest = next;
+    }
+    Ok(children)
+}
+
+/// Find the certificate of the (first) signer of a PKCS#7 `SignedData`.
+fn parse_pkcs7_signer(pkcs7: &[u8]) -> Result<Signer, String> {
+    // ContentInfo ::= SEQUENCE { contentType OID, content [0] EXPLICIT SignedData }
+    let content_info = children(expect_tlv(pkcs7, TAG_SEQUENCE)?.content)?;
+    match content_info.as_slice() {
+        [content_type, _, ..]
+            if content_type.tag == TAG_OID && content_type.content == OID_SIGNED_DATA => {}
+        _ => return Err("PKCS#7 content is not SignedData".to_string()),
+    }
+    let explicit = content_info[1];
+    if explicit.tag != TAG_CONTEXT_0 {
+        return Err("PKCS#7 SignedData is missing".to_string());
+    }
+
+    // SignedData ::= SEQUENCE { version, digestAlgorithms, contentInfo,
+    //     certificates [0] IMPLICIT OPTIONAL, crls [1] IMPLICIT OPTIONAL, signerInfos SET }
+    let signed_data = children(expect_tlv(explicit.content, TAG_SEQUENCE)?.content)?;
+    let certificates = signed_data
+        .iter()
+        .find(|field| field.tag == TAG_CONTEXT_0)
+        .map(|field| children(field.content))
+        .transpose()?
+        .unwrap_or_default();
+    let signer_infos = signed_data
+        .last()
+        .filter(|field| field.tag == TAG_SET)
+        .ok_or("PKCS#7 SignedData has no signerInfos")?;
+    let signer_info = children(signer_infos.content)?
+        .into_iter()
+        .next()
+        .ok_or("PKCS#7 SignedData has no signer")?;
+
+    // SignerInfo ::= SEQUENCE { version, sid IssuerAndSerialNumber, ... }
+    let sid = children(signer_info.content)?
+        .get(1)
+        .copied()
+        .filter(|sid| sid.tag == TAG_SEQUENCE)
+        .ok_or("signer is not identified by issuer and serial number")?;
+    let (issuer, serial) = match children(sid.content)?.as_slice() {
+        [issuer, serial] if issuer.tag == TAG_SEQUENCE && serial.tag == TAG_INTEGER => {
+            (issuer.raw, serial.content)
+        }
+        _ => return Err("invalid IssuerAndSerialNumber".to_string()),
+    };
+
+    for certificate in certificates {
+        let fields = certificate_fields(certificate)?;
+        if fields.issuer.raw == issuer && fields.serial == serial {
+            return Ok(Signer {
+                subject: names(fields.subject)?
+                    .iter()
+                    .map(|(name, value)| format!("{name}={value}"))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                sha1: to_hex(&Sha1::digest(certificate.raw)),
+                sha256: to_hex(&Sha256::digest(certificate.raw)),
+            });
+        }
+    }
+    Err("the certificate of the signer is not embedded".to_string())
+}
+
+struct CertificateFields<'a> {
+    serial: &'a [u8],
+    issuer: Tlv<'a>,
+    subject: Tlv<'a>,
+}
+
+/// Certificate ::= SEQUENCE { tbsCertificate SEQUENCE { [0] version OPTIONAL, serialNumber,
+/// signature, issuer, validity, subject, ... }, ... }
+fn certificate_fields(certificate: Tlv<'_>) -> Result<CertificateFields<'_>, String> {
+    let tbs = expect_tlv(certificate.content, TAG_SEQUENCE)?;
+    let mut fields = children(tbs.content)?;
+    if fields
+        .first()
+        .is_some_and(|field| field.tag == TAG_CONTEXT_0)
+    {
+        fields.remove(0);
+    }
+    match fields.as_slice() {
+        [serial, _, issuer, _, subject, ..] if serial.tag == TAG_INTEGER => Ok(CertificateFields {
+            serial: serial.content,
+            issuer: *issuer,
+            subject: *subject,
+        }),
+        _ => Err("invalid certificate".to_string()),
+    }
+}
+
+/// The attributes of an X.509 `Name`, with the short names of the common ones.
+fn names(name: Tlv<'_>) -> Result<Vec<(String, String)>, String> {
+    let mut attributes = Vec::new();
+    for rdn in children(name.content)? {
+        for attribute in children(rdn.content)? {
+            let [oid, value] = children(attribute.content)?[..] else {
+                return Err("invalid name attribute".to_string());
+            };
+            let name = match oid.content {
+                [0x55, 0x04, 0x03] => "CN".to_string(),
+                [0x55, 0x04, 0x06] => "C".to_string(),
+                [0x55, 0x04, 0x07] => "L".to_string(),
+                [0x55, 0x04, 0x08] => "ST".to_string(),
+                [0x55, 0x04, 0x0a] => "O".to_string(),
+                [0x55, 0x04, 0x0b] => "OU".to_string(),
+                other => other.iter().map(|byte| format!("{byte:02x}")).collect(),
+            };
+            let value = match value.tag {
+                // BMPString
+                0x1e => String::from_utf16_lossy(
+                    &value
+                        .content
+                        .chunks_exact(2)
+                        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                        .collect::<Vec<_>>(),
+                ),
+                _ => String::from_utf8_lossy(value.content).into_owned(),
+            };
+            attributes.push((name, value));
+        }
+    }
+    Ok(attributes)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use goblin::pe::header::{COFF_MACHINE_ARM64, COFF_MACHINE_X86, COFF_MACHINE_X86_64};
+
+    use super::*;
+
+    /// Index of the certificate table in the data directories of the optional header.
+    const SECURITY_DIRECTORY: usize = 4;
+
+    fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut encoded = vec![tag];
+        match content.len() {
+            len @ 0..=0x7f => encoded.push(len as u8),
+            len => {
+                encoded.push(0x82);
+                encoded.extend((len as u16).to_be_bytes());
+            }
+        }
+        encoded.extend(content);
+        encoded
+    }
+
+    fn name(common_name: &str) -> Vec<u8> {
+        let attribute = |oid: &[u8], value: &str| {
+            der(
+                TAG_SET,
+                &der(
+                    TAG_SEQUENCE,
+                    &[der(TAG_OID, oid), der(0x0c, value.as_bytes())].concat(),
+                ),
+            )
+        };
+        der(
+            TAG_SEQUENCE,
+            &[
+                attribute(&[0x55, 0x04, 0x03], common_name),
+                attribute(&[0x55, 0x04, 0x0a], "WireGuard LLC"),
+            ]
+            .concat(),
+        )
+    }
+
+    /// A certificate issued to `common_name` with `serial`, and an Authenticode signature
+    /// whose signer is identified by `signer_serial`.
+    pub(crate) fn signature(
+        common_name: &str,
+        serial: u8,
+        signer_serial: u8,
+    ) -> (Vec<u8>, Vec<u8>) {
+        let issuer = name("Test CA");
+        let tbs = der(
+            TAG_SEQUENCE,
+            &[
+                der(TAG_CONTEXT_0, &der(TAG_INTEGER, &[2])),
+                der(TAG_INTEGER, &[serial]),
+                der(TAG_SEQUENCE, &[]),
+                issuer.clone(),
+                der(TAG_SEQUENCE, &[]),
+                name(common_name),
+                der(TAG_SEQUENCE, &[]),
+            ]
+            .concat(),
+        );
+        let certificate = der(
+            TAG_SEQUENCE,
+            &[tbs, der(TAG_SEQUENCE, &[]), der(0x03, &[0])].concat(),
+        );
+        let signer_info = der(
+            TAG_SEQUENCE,
+            &[
+                der(TAG_INTEGER, &[1]),
+                der(
+                    TAG_SEQUENCE,
+                    &[issuer, der(TAG_INTEGER, &[signer_serial])].concat(),
+                ),
+            ]
+            .concat(),
+        );
+        let signed_data = der(
+            TAG_SEQUENCE,
+            &[
+                der(TAG_INTEGER, &[1]),
+                der(TAG_SET, &[]),
+                der(TAG_SEQUENCE, &[]),
+                der(TAG_CONTEXT_0, &certificate),
+                der(TAG_SET, &signer_info),
+            ]
+            .concat(),
+        );
+        let pkcs7 = der(
+            TAG_SEQUENCE,
+            &[
+                der(TAG_OID, OID_SIGNED_DATA),
+                der(TAG_CONTEXT_0, &signed_data),
+            ]
+            .concat(),
+        );
+        (pkcs7, certificate)
+    }
+
+    /// A PE file without sections, with `directories` data directories and `pkcs7` as its signature.
+    pub(crate) fn pe_file(
+        machine: u16,
+        pe32: bool,
+        directories: u32,
+        pkcs7: Option<&[u8]>,
+    ) -> Vec<u8> {
+        let mut data = vec![0; 64];
+        data[..2].copy_from_slice(b"MZ");
+        data[0x3c..0x40].copy_from_slice(&64u32.to_le_bytes());
+        data.extend(b"PE\0\0");
+
+        let (magic, count_offset, directories_offset) = if pe32 {
+            (0x10bu16, 92, 96)
+        } else {
+            (0x20b, 108, 112)
+        };
+        let mut optional = vec![0; directories_offset + 16 * 8];
+        optional[..2].copy_from_slice(&magic.to_le_bytes());
+        optional[count_offset..count_offset + 4].copy_from_slice(&directories.to_le_bytes());
+
+        let mut coff = vec![0; 20];
+        coff[..2].copy_from_slice(&machine.to_le_bytes());
+        coff[16..18].copy_from_slice(&(optional.len() as u16).to_le_bytes());
+        data.extend(coff);
+
+        let table_offset = (data.len() + optional.len()).next_multiple_of(8);
+        let mut table = Vec::new();
+        if let Some(pkcs7) = pkcs7 {
+            table.extend((8 + pkcs7.len() as u32).to_le_bytes());
+            table.extend(0x0200u16.to_le_bytes());
+            table.extend((AttributeCertificateType::PkcsSignedData as u16).to_le_bytes());
+            table.extend(pkcs7);
+            let entry = directories_offset + SECURITY_DIRECTORY * 8;
+            optional[entry..entry + 4].copy_from_slice(&(table_offset as u32).to_le_bytes());
+            optional[entry + 4..entry + 8].copy_from_slice(&(table.len() as u32).to_le_bytes());
+        }
+        data.extend(optional);
+        data.resize(table_offset, 0);
+        data.extend(table);
+        data
+    }
+
+    #[test]
+    fn reads_the_signer() {
+        let (pkcs7, certificate) = signature("WireGuard LLC", 7, 7);
+        for pe32 in [true, false] {
+            let signature = parse_pe(&pe_file(COFF_MACHINE_ARM64, pe32, 16, Some(&pkcs7))).unwrap();
+            assert_eq!(signature.machine, COFF_MACHINE_ARM64);
+            let signer = signature.signer.unwrap();
+            assert_eq!(signer.subject, "CN=WireGuard LLC, O=WireGuard LLC");
+            assert_eq!(signer.sha1, to_hex(&Sha1::digest(&certificate)));
+            assert_eq!(signer.sha256, to_hex(&Sha256::digest(&certificate)));
+        }
+    }
+
+    #[test]
+    fn matches_signers_by_thumbprint_only() {
+        let (pkcs7, _) = signature("WireGuard LLC", 7, 7);
+        let signer = parse_pkcs7_signer(&pkcs7).unwrap();
+        assert!(signer.matches(&signer.sha1));
+        assert!(signer.matches(&signer.sha256.to_uppercase()));
+        for name in ["WireGuard LLC", &signer.subject, ""] {
+            assert!(!signer.matches(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn reads_unsigned_files() {
+        // No certificate table, and no security directory at all
+        for directories in [16, SECURITY_DIRECTORY as u32, 0] {
+            let signature =
+                parse_pe(&pe_file(COFF_MACHINE_X86_64, false, directories, None)).unwrap();
+            assert_eq!(
+                signature,
+                PeSignature {
+                    machine: COFF_MACHINE_X86_64,
+                    signer: None
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_pe_files() {
+        let (pkcs7, _) = signature("WireGuard LLC", 7, 7);
+        let file = pe_file(COFF_MACHINE_X86, true, 16, Some(&pkcs7));
+        assert!(parse_pe(b"ELF").is_err());
+        // Every truncation of the headers or of the signature fails without panicking
+        for len in 0..file.len() {
+            assert!(parse_pe(&file[..len]).is_err(), "truncated to {len} bytes");
+        }
+
+        let mut bad_magic = file.clone();
+        bad_magic[88..90].copy_from_slice(&0x107u16.to_le_bytes());
+        assert!(parse_pe(&bad_magic).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_der() {
+        for data in [
+            &[][..],
+            &[TAG_SEQUENCE],
+            &[TAG_SEQUENCE, 0x03, 0x02, 0x01],
+            &[TAG_SEQUENCE, 0x82, 0x01],
+            &[TAG_SEQUENCE, 0x82, 0xff, 0xff, 0x00],
+            &[TAG_SEQUENCE, 0x80, 0x00, 0x00],
+            &[TAG_SEQUENCE, 0x85, 0x01, 0x00, 0x00, 0x00, 0x00],
+        ] {
+            assert!(read_tlv(data).is_err(), "{data:02x?}");
+        }
+        let (tlv, rest) = read_tlv(&[TAG_SET, 0x81, 0x01, 0xaa, 0xbb]).unwrap();
+        assert_eq!(
+            (tlv.tag, tlv.content, rest),
+            (TAG_SET, &[0xaa][..], &[0xbb][..])
+        );
+
+        assert!(expect_tlv(&[TAG_SET, 0x00], TAG_SEQUENCE).is_err());
+        let (pkcs7, _) = signature("WireGuard LLC", 7, 7);
+        assert!(parse_pkcs7_signer(&der(TAG_SEQUENCE, &der(TAG_OID, &[0x2a]))).is_err());
+        assert!(parse_pkcs7_signer(&pkcs7[..pkcs7.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn rejects_signatures_without_the_signer_certificate() {
+        let (pkcs7, _) = signature("WireGuard LLC", 7, 8);
+        assert!(parse_pkcs7_signer(&pkcs7).is_err());
+    }
+}
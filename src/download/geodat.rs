@@ -1,86 +1,426 @@
 use crate::{
-    COLLECTED_FILES, TEMP_DIR,
-    cli::Region,
-    download::{download_file, download_file_content},
-    errors::PackResult,
+    checksum::sha256_file,
+    download::{Downloader, Verification, join_downloads, parse_sha256sum},
+    errors::{PackError, PackResult},
 };
-use sha2::{Digest, Sha256};
-use std::fs;
-
-/// Download geoip and geodat
-pub fn download_geodat(region: Region) -> PackResult<()> {
-    let url = region.url();
-
-    // Download geoip.dat
-    let geoip_path = TEMP_DIR.join("geoip.dat");
-    download_file(format!("{url}geoip.dat"), &geoip_path)?;
-    log::info!("Downloaded geoip.dat");
-
-    // Get expected checksum for geoip.dat
-    let geoip_checksum = download_file_content(format!("{url}geoip.dat.sha256sum"))?
-        .trim()
-        .to_string();
-
-    // Verify geoip.dat checksum
-    verify_sha256(
-        &geoip_path,
-        geoip_checksum.split_whitespace().next().unwrap(),
-    )?;
-    log::info!("Verified geoip.dat checksum");
-
-    // Add geoip.dat to collected files
-    COLLECTED_FILES.lock().unwrap().push(geoip_path);
-
-    // Download geosite.dat
-    let geosite_path = TEMP_DIR.join("geosite.dat");
-    download_file(format!("{url}geosite.dat"), &geosite_path)?;
-    log::info!("Downloaded geosite.dat");
-
-    // Get expected checksum for geosite.dat
-    let geosite_checksum = download_file_content(format!("{url}geosite.dat.sha256sum"))?
-        .trim()
-        .to_string();
-
-    // Verify geosite.dat checksum
-    verify_sha256(
-        &geosite_path,
-        geosite_checksum.split_whitespace().next().unwrap(),
-    )?;
-    log::info!("Verified geosite.dat checksum");
-
-    // Add geosite.dat to collected files
-    COLLECTED_FILES.lock().unwrap().push(geosite_path);
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::{
+    convert::Infallible,
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Three main restricted regions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Region {
+    ChinaMainland,
+    Russia,
+    Iran,
+}
+impl Region {
+    /// Returns the URL for downloading the geoip and geodat files for the region.
+    /// Add `geoip.dat` and `geosite.dat` to the URL for downloading.
+    pub fn url(&self) -> &str {
+        match self {
+            Region::ChinaMainland => {
+                "https://raw.githubusercontent.com/Loyalsoldier/v2ray-rules-dat/release/"
+            }
+            Region::Russia => {
+                "https://raw.githubusercontent.com/runetfreedom/russia-v2ray-rules-dat/release/"
+            }
+            Region::Iran => {
+                "https://raw.githubusercontent.com/Chocolate4U/Iran-v2ray-rules/release/"
+            }
+        }
+    }
+}
+
+/// A repository of geoip and geosite files, like those of the [`Region`]s.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GeodataSource {
+    /// URL the file names are appended to.
+    pub base_url: String,
+    /// Name of the geoip file in the repository. It is packaged as `geoip.dat`.
+    #[serde(default = "GeodataSource::default_geoip")]
+    pub geoip: String,
+    /// Name of the geosite file in the repository. It is packaged as `geosite.dat`.
+    #[serde(default = "GeodataSource::default_geosite")]
+    pub geosite: String,
+    /// Name of the `sha256sum` style file of each file, where `{file}` is replaced by the file name.
+    /// The files are not verified if it is empty.
+    #[serde(default = "GeodataSource::default_checksum")]
+    pub checksum: String,
+}
+impl GeodataSource {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        GeodataSource {
+            base_url: base_url.into(),
+            geoip: Self::default_geoip(),
+            geosite: Self::default_geosite(),
+            checksum: Self::default_checksum(),
+        }
+    }
+
+    fn default_geoip() -> String {
+        "geoip.dat".to_string()
+    }
+
+    fn default_geosite() -> String {
+        "geosite.dat".to_string()
+    }
+
+    fn default_checksum() -> String {
+        "{file}.sha256sum".to_string()
+    }
+
+    /// Download `file_name` of the repository to `dest`, verifying it against its checksum file.
+    async fn fetch(&self, file_name: &str, dest: &Path, downloader: &Downloader) -> PackResult<()> {
+        let base_url = &self.base_url;
+        let checksum_url = format!("{base_url}{}", self.checksum.replace("{file}", file_name));
+        let verification = if self.checksum.is_empty() {
+            Verification::Nothing
+        } else {
+            Verification::Sha256sum {
+                url: &checksum_url,
+                required: true,
+            }
+        };
+        downloader
+            .fetch_file(&format!("{base_url}{file_name}"), verification, dest)
+            .await
+    }
+}
+impl From<Region> for GeodataSource {
+    fn from(region: Region) -> Self {
+        GeodataSource::new(region.url())
+    }
+}
+
+/// Where a geo file is taken from instead of the repository of the [`Region`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum GeoSource {
+    /// A local file.
+    Path(PathBuf),
+    /// An `http://` or `https://` URL to download.
+    Url(String),
+}
+impl FromStr for GeoSource {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("http://") || s.starts_with("https://") {
+            Ok(GeoSource::Url(s.to_string()))
+        } else {
+            Ok(GeoSource::Path(PathBuf::from(s)))
+        }
+    }
+}
+impl From<String> for GeoSource {
+    fn from(s: String) -> Self {
+        s.parse().expect("parsing a geo source never fails")
+    }
+}
+impl From<GeoSource> for String {
+    fn from(source: GeoSource) -> Self {
+        source.to_string()
+    }
+}
+impl Display for GeoSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GeoSource::Path(path) => write!(f, "{}", path.display()),
+            GeoSource::Url(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+/// A geo file taken from a [`GeoSource`].
+///
+/// It is verified against `sha256` if given, or else against a sibling `.sha256sum` file if there is one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeoFile {
+    pub source: GeoSource,
+    pub sha256: Option<String>,
+}
+impl GeoFile {
+    pub fn new(source: GeoSource) -> Self {
+        GeoFile {
+            source,
+            sha256: None,
+        }
+    }
+
+    pub fn with_sha256(mut self, sha256: impl Into<String>) -> Self {
+        self.sha256 = Some(sha256.into());
+        self
+    }
 
+    /// Copy or download the file to `dest` and verify it.
+    async fn fetch(&self, dest: &Path, downloader: &Downloader) -> PackResult<()> {
+        match &self.source {
+            GeoSource::Url(url) => {
+                let checksum_url = format!("{url}.sha256sum");
+                let verification = match &self.sha256 {
+                    Some(sha256) => Verification::Sha256(sha256),
+                    None => Verification::Sha256sum {
+                        url: &checksum_url,
+                        required: false,
+                    },
+                };
+                downloader.fetch_file(url, verification, dest).await
+            }
+            GeoSource::Path(path) => {
+                std::fs::copy(path, dest)
+                    .map_err(|_| PackError::CopyFailed(path.clone(), dest.to_path_buf()))?;
+
+                let mut checksum_path = path.clone().into_os_string();
+                checksum_path.push(".sha256sum");
+                let checksum_path = PathBuf::from(checksum_path);
+                let expected = match &self.sha256 {
+                    Some(sha256) => sha256.to_lowercase(),
+                    None if checksum_path.is_file() => parse_sha256sum(
+                        &std::fs::read_to_string(&checksum_path)
+                            .map_err(|_| PackError::ReadFailed(checksum_path.clone()))?,
+                    ),
+                    None => {
+                        log::info!("Using {} without verification", path.display());
+                        return Ok(());
+                    }
+                };
+                let got = sha256_file(dest)?;
+                if got != expected {
+                    return Err(PackError::ChecksumFailed { expected, got });
+                }
+                log::info!("Using {}, checksum verified", path.display());
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Download geoip and geodat from `source` into `dest_dir` concurrently, verifying them against
+/// their checksum files. `geoip` and `geosite` replace the files of the source if given.
+/// The paths of the downloaded files are returned.
+pub async fn download_geodat(
+    source: &GeodataSource,
+    geoip: Option<&GeoFile>,
+    geosite: Option<&GeoFile>,
+    dest_dir: &Path,
+    downloader: &Downloader,
+) -> PackResult<Vec<PathBuf>> {
+    join_downloads(
+        [
+            ("geoip.dat", &source.geoip, geoip),
+            ("geosite.dat", &source.geosite, geosite),
+        ]
+        .map(|(file_name, source_name, geo_file)| async move {
+            let path = dest_dir.join(file_name);
+            match geo_file {
+                Some(geo_file) => geo_file.fetch(&path, downloader).await?,
+                None => source.fetch(source_name, &path, downloader).await?,
+            }
+            Ok(path)
+        }),
+    )
+    .await
+}
+
+/// Fetch additional geo files into `dest_dir` concurrently, each under its own name, e.g. `geosite_IR.dat`.
+/// The paths of the fetched files are returned.
+pub async fn download_extra_geodat(
+    files: &[(String, GeoFile)],
+    dest_dir: &Path,
+    downloader: &Downloader,
+) -> PackResult<Vec<PathBuf>> {
+    join_downloads(files.iter().map(|(name, geo_file)| async move {
+        let path = dest_dir.join(name);
+        geo_file.fetch(&path, downloader).await?;
+        Ok(path)
+    }))
+    .await
+}
+
+/// Check that the names of the extra geo `files` are plain file names, distinct from each other
+/// and from `packaged`, the names of the other files of the packages.
+pub(crate) fn check_extra_names(
+    files: &[(String, GeoFile)],
+    packaged: &[String],
+) -> Result<(), String> {
+    for (index, (name, _)) in files.iter().enumerate() {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return Err(format!(
+                "`{name}` is not a valid name for an extra geo file, use a plain file name"
+            ));
+        }
+        if packaged.contains(name) {
+            return Err(format!(
+                "the extra geo file `{name}` would replace a file of the package, use another name"
+            ));
+        }
+        if files[..index].iter().any(|(other, _)| other == name) {
+            return Err(format!("several extra geo files are named `{name}`"));
+        }
+    }
     Ok(())
 }
 
-/// Verify the SHA256 checksum of a file
-fn verify_sha256(file_path: &std::path::Path, expected_checksum: &str) -> PackResult<()> {
-    // Read the file contents
-    let file_contents = fs::read(file_path)?;
-
-    // Calculate the SHA256 hash
-    let mut hasher = Sha256::new();
-    hasher.update(&file_contents);
-    let calculated_hash = hasher.finalize();
-
-    // Convert the hash to hex string
-    let calculated_checksum = calculated_hash
-        .iter()
-        .map(|b| format!("{:02x}", b))
-        .collect::<String>();
-    log::debug!(
-        "Calculated {} checksum: {calculated_checksum}",
-        file_path.display()
-    );
-
-    // Compare the calculated checksum with the expected one
-    if calculated_checksum != expected_checksum {
-        return Err(crate::errors::PackError::ChecksumFailed {
-            expected: expected_checksum.to_string(),
-            got: calculated_checksum,
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+    use crate::{
+        checksum::to_hex,
+        download::tests::{BODY, downloader, options, respond, serve},
+    };
+
+    /// Serve `files` by path, and 404 for any other path.
+    /// Returns the base URL of the server and the paths requested so far.
+    fn serve_files(files: &[(&str, &[u8])]) -> (String, Arc<Mutex<Vec<String>>>) {
+        let files: Vec<(String, Vec<u8>)> = files
+            .iter()
+            .map(|(path, content)| (path.to_string(), content.to_vec()))
+            .collect();
+        let requested = Arc::new(Mutex::new(Vec::new()));
+        let server_requested = Arc::clone(&requested);
+        let (url, _) = serve(move |request, stream| {
+            server_requested.lock().unwrap().push(request.path.clone());
+            match files.iter().find(|(path, _)| *path == request.path) {
+                Some((_, content)) => respond(
+                    stream,
+                    "200 OK",
+                    &[format!("Content-Length: {}", content.len())],
+                    content,
+                ),
+                None => respond(stream, "404 Not Found", &[], b""),
+            }
         });
+        (url.trim_end_matches("file").to_string(), requested)
     }
 
-    Ok(())
+    fn sha256sum(content: &[u8], name: &str) -> Vec<u8> {
+        format!("{}  {name}\n", to_hex(&Sha256::digest(content))).into_bytes()
+    }
+
+    fn fetch(geo_file: &GeoFile, dest: &Path) -> PackResult<()> {
+        let downloader = downloader(options());
+        downloader.block_on(geo_file.fetch(dest, &downloader))
+    }
+
+    fn fetch_from(source: &GeodataSource, file_name: &str, dest: &Path) -> PackResult<()> {
+        let downloader = downloader(options());
+        downloader.block_on(source.fetch(file_name, dest, &downloader))
+    }
+
+    #[test]
+    fn verifies_local_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("geoip.dat");
+        let checksum_path = dir.path().join("geoip.dat.sha256sum");
+        let dest = dir.path().join("packaged.dat");
+        let sha256 = to_hex(&Sha256::digest(BODY));
+        let wrong = to_hex(&Sha256::digest(b"other"));
+        std::fs::write(&path, BODY).unwrap();
+        let geo_file = GeoFile::new(GeoSource::Path(path.clone()));
+
+        // Without a sibling checksum file, the file is used as is
+        fetch(&geo_file, &dest).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), BODY);
+
+        std::fs::write(&checksum_path, sha256sum(BODY, "geoip.dat")).unwrap();
+        fetch(&geo_file, &dest).unwrap();
+        std::fs::write(&checksum_path, sha256sum(b"other", "geoip.dat")).unwrap();
+        assert!(matches!(
+            fetch(&geo_file, &dest),
+            Err(PackError::ChecksumFailed { .. })
+        ));
+
+        // A given checksum replaces the sibling checksum file
+        fetch(&geo_file.clone().with_sha256(sha256.to_uppercase()), &dest).unwrap();
+        std::fs::write(&checksum_path, sha256sum(BODY, "geoip.dat")).unwrap();
+        assert!(matches!(
+            fetch(&geo_file.clone().with_sha256(wrong), &dest),
+            Err(PackError::ChecksumFailed { .. })
+        ));
+
+        let missing = GeoFile::new(GeoSource::Path(dir.path().join("missing.dat")));
+        assert!(matches!(
+            fetch(&missing, &dest),
+            Err(PackError::CopyFailed(..))
+        ));
+    }
+
+    #[test]
+    fn checksum_files_of_urls_are_optional() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("geosite.dat");
+
+        // A missing `.sha256sum` file drops the verification
+        let (base_url, requested) = serve_files(&[("/geosite.dat", BODY)]);
+        let geo_file = GeoFile::new(GeoSource::Url(format!("{base_url}geosite.dat")));
+        fetch(&geo_file, &dest).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), BODY);
+        assert_eq!(
+            *requested.lock().unwrap(),
+            ["/geosite.dat.sha256sum", "/geosite.dat"]
+        );
+
+        let checksum = sha256sum(b"other", "geosite.dat");
+        let (base_url, requested) = serve_files(&[
+            ("/geosite.dat", BODY),
+            ("/geosite.dat.sha256sum", &checksum),
+        ]);
+        let geo_file = GeoFile::new(GeoSource::Url(format!("{base_url}geosite.dat")));
+        assert!(matches!(
+            fetch(&geo_file, &dest),
+            Err(PackError::ChecksumFailed { .. })
+        ));
+
+        // A given checksum is used without fetching the `.sha256sum` file
+        requested.lock().unwrap().clear();
+        fetch(&geo_file.with_sha256(to_hex(&Sha256::digest(BODY))), &dest).unwrap();
+        assert_eq!(*requested.lock().unwrap(), ["/geosite.dat"]);
+    }
+
+    #[test]
+    fn checksum_file_names_follow_the_template() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("geoip.dat");
+        let checksum = sha256sum(BODY, "ip.dat");
+        let (base_url, requested) =
+            serve_files(&[("/ip.dat", BODY), ("/sums/ip.dat.txt", &checksum)]);
+
+        let source = GeodataSource {
+            checksum: "sums/{file}.txt".to_string(),
+            ..GeodataSource::new(&base_url)
+        };
+        fetch_from(&source, "ip.dat", &dest).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), BODY);
+        assert_eq!(*requested.lock().unwrap(), ["/sums/ip.dat.txt", "/ip.dat"]);
+
+        // Unlike for a single file, the checksum file of a source is required
+        requested.lock().unwrap().clear();
+        let source = GeodataSource::new(&base_url);
+        assert!(matches!(
+            fetch_from(&source, "ip.dat", &dest),
+            Err(PackError::NetworkError(_))
+        ));
+        assert_eq!(*requested.lock().unwrap(), ["/ip.dat.sha256sum"]);
+
+        // An empty template disables the verification
+        requested.lock().unwrap().clear();
+        let source = GeodataSource {
+            checksum: String::new(),
+            ..GeodataSource::new(&base_url)
+        };
+        fetch_from(&source, "ip.dat", &dest).unwrap();
+        assert_eq!(*requested.lock().unwrap(), ["/ip.dat"]);
+    }
 }
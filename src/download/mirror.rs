@@ -0,0 +1,179 @@
+use std::{fmt::Display, str::FromStr};
+
+use reqwest::Url;
+
+/// Placeholders available for every URL.
+const URL_PLACEHOLDERS: [&str; 3] = ["url", "host", "path"];
+/// Placeholders available only for `https://raw.githubusercontent.com/{owner}/{repo}/{ref}/{file}` URLs.
+const GITHUB_PLACEHOLDERS: [&str; 4] = ["owner", "repo", "ref", "file"];
+
+/// A URL template that maps the URL of a downloaded file to a mirror of it.
+///
+/// `{url}` is replaced by the whole URL, `{host}` and `{path}` by its parts, e.g.
+/// `https://ghproxy.example.com/{url}`. For raw GitHub content, `{owner}`, `{repo}`, `{ref}` and `{file}`
+/// are available as well, e.g. `https://cdn.jsdelivr.net/gh/{owner}/{repo}@{ref}/{file}`.
+/// A mirror using them is skipped for other URLs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mirror {
+    template: String,
+}
+impl Mirror {
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// The URL of the mirrored `url`, or `None` if the template does not apply to it.
+    pub fn apply(&self, url: &str) -> Option<String> {
+        let parsed = Url::parse(url).ok()?;
+        let path = parsed.path().trim_start_matches('/');
+        let github = (parsed.host_str() == Some("raw.githubusercontent.com"))
+            .then(|| path.splitn(4, '/').collect::<Vec<_>>())
+            .filter(|parts| parts.len() == 4);
+
+        let mut rendered = String::with_capacity(self.template.len() + url.len());
+        for (literal, placeholder) in segments(&self.template) {
+            rendered.push_str(literal);
+            let value = match placeholder {
+                None => continue,
+                Some("url") => url,
+                Some("host") => parsed.host_str()?,
+                Some("path") => path,
+                Some("owner") => github.as_ref()?[0],
+                Some("repo") => github.as_ref()?[1],
+                Some("ref") => github.as_ref()?[2],
+                Some("file") => github.as_ref()?[3],
+                Some(_) => unreachable!("placeholders are checked when parsing"),
+            };
+            rendered.push_str(value);
+        }
+        Some(rendered)
+    }
+}
+impl FromStr for Mirror {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        for (literal, placeholder) in segments(s) {
+            if literal.contains(['{', '}']) {
+                return Err(format!("unbalanced brace in mirror template `{s}`"));
+            }
+            if let Some(placeholder) = placeholder
+                && !URL_PLACEHOLDERS.contains(&placeholder)
+                && !GITHUB_PLACEHOLDERS.contains(&placeholder)
+            {
+                return Err(format!(
+                    "unknown placeholder {{{placeholder}}} in mirror template `{s}`. Available placeholders: {}",
+                    URL_PLACEHOLDERS
+                        .iter()
+                        .chain(&GITHUB_PLACEHOLDERS)
+                        .map(|name| format!("{{{name}}}"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+        }
+        if !s.starts_with("http://") && !s.starts_with("https://") && !s.starts_with('{') {
+            return Err(format!("mirror template `{s}` is not an http(s) URL"));
+        }
+        Ok(Mirror {
+            template: s.to_string(),
+        })
+    }
+}
+impl Display for Mirror {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.template)
+    }
+}
+
+/// Split `template` into literal text, each followed by the placeholder after it if any.
+fn segments(template: &str) -> Vec<(&str, Option<&str>)> {
+    let mut segments = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        segments.push((&rest[..start], Some(&rest[start + 1..start + end])));
+        rest = &rest[start + end + 1..];
+    }
+    segments.push((rest, None));
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GITHUB_URL: &str =
+        "https://raw.githubusercontent.com/Loyalsoldier/v2ray-rules-dat/release/dir/geoip.dat";
+    const OTHER_URL: &str = "https://www.wintun.net/builds/wintun-0.14.1.zip";
+
+    fn mirror(template: &str) -> Mirror {
+        template.parse().unwrap()
+    }
+
+    #[test]
+    fn applies_url_placeholders() {
+        assert_eq!(
+            mirror("https://ghproxy.example.com/{url}").apply(OTHER_URL),
+            Some(
+                "https://ghproxy.example.com/https://www.wintun.net/builds/wintun-0.14.1.zip"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            mirror("https://mirror.example.internal/{host}/{path}").apply(OTHER_URL),
+            Some(
+                "https://mirror.example.internal/www.wintun.net/builds/wintun-0.14.1.zip"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            mirror("{url}").apply(OTHER_URL),
+            Some(OTHER_URL.to_string())
+        );
+    }
+
+    #[test]
+    fn applies_github_placeholders() {
+        assert_eq!(
+            mirror("https://cdn.jsdelivr.net/gh/{owner}/{repo}@{ref}/{file}").apply(GITHUB_URL),
+            Some(
+                "https://cdn.jsdelivr.net/gh/Loyalsoldier/v2ray-rules-dat@release/dir/geoip.dat"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn skips_github_templates_for_other_urls() {
+        let jsdelivr = mirror("https://cdn.jsdelivr.net/gh/{owner}/{repo}@{ref}/{file}");
+        assert_eq!(jsdelivr.apply(OTHER_URL), None);
+        assert_eq!(
+            jsdelivr.apply("https://raw.githubusercontent.com/owner/repo/geoip.dat"),
+            None
+        );
+        assert_eq!(jsdelivr.apply("not a url"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        for template in [
+            "https://mirror.example.com/{name}",
+            "https://mirror.example.com/{url",
+            "https://mirror.example.com/url}",
+            "ftp://mirror.example.com/{path}",
+            "mirror.example.com/{path}",
+        ] {
+            assert!(template.parse::<Mirror>().is_err(), "{template}");
+        }
+    }
+
+    #[test]
+    fn displays_the_template() {
+        let template = "https://ghproxy.example.com/{url}";
+        assert_eq!(mirror(template).to_string(), template);
+        assert_eq!(mirror(template).template(), template);
+    }
+}
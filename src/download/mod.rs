@@ -1,72 +1,1150 @@
-use std::path::Path;
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
 use futures::StreamExt;
+use indicatif::{HumanBytes, HumanDuration, ProgressBar};
+use reqwest::{
+    Client, Response, StatusCode,
+    header::{CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE},
+};
 use tokio::io::AsyncWriteExt;
 use tokio::runtime::Runtime;
 
-use crate::errors::{PackError, PackResult};
+use crate::{
+    cache::Cache,
+    checksum::sha256_file,
+    download::mirror::Mirror,
+    errors::{PackError, PackResult},
+    job::PackJob,
+    progress::{download_bar, set_download_length},
+    proxy::Proxy,
+};
 
+mod authenticode;
 pub mod geodat;
+pub mod mirror;
 pub mod v2ray_extra;
 pub mod wintun;
 
-/// Asynchronous version of download function
-pub async fn download_file_async(url: impl AsRef<str>, dest: impl AsRef<Path>) -> PackResult<()> {
-    let response = reqwest::get(url.as_ref()).await?;
+/// Timeouts, retries and proxy of every HTTP request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpOptions {
+    /// Time allowed to establish a connection.
+    pub connect_timeout: Duration,
+    /// Time allowed between two reads of the response.
+    pub read_timeout: Duration,
+    /// Number of retries after a failed attempt.
+    pub retries: u32,
+    /// Delay before the first retry. It is doubled before every further retry.
+    pub backoff: Duration,
+    pub proxy: Proxy,
+}
+impl Default for HttpOptions {
+    fn default() -> Self {
+        HttpOptions {
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(30),
+            retries: 3,
+            backoff: Duration::from_secs(1),
+            proxy: Proxy::from_env(),
+        }
+    }
+}
+impl HttpOptions {
+    pub(crate) fn client(&self) -> PackResult<Client> {
+        let builder = Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .read_timeout(self.read_timeout);
+        Ok(self.proxy.apply_to_client(builder)?.build()?)
+    }
 
-    if response.status().is_success() {
-        log::debug!("Successfully connected to {}", url.as_ref());
-        let mut file = tokio::fs::File::create(dest.as_ref())
-            .await
-            .map_err(|_| PackError::CreateFailed(dest.as_ref().to_path_buf()))?;
-        let mut stream = response.bytes_stream();
+    /// Run `attempt` until it succeeds, a non-transient error occurs, or the retries are exhausted.
+    async fn retry<T, F, Fut>(&self, url: &str, mut attempt: F) -> PackResult<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = PackResult<T>>,
+    {
+        let mut delay = self.backoff;
+        for retry in 0.. {
+            match attempt().await {
+                Err(PackError::NetworkError(e)) if retry < self.retries && is_transient(&e) => {
+                    log::warn!(
+                        "{url}: {e}. Retrying in {:.1}s ({}/{})",
+                        delay.as_secs_f64(),
+                        retry + 1,
+                        self.retries
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = delay.saturating_mul(2);
+                }
+                result => return result,
+            }
+        }
+        unreachable!("the loop only ends by returning")
+    }
+}
 
-        // Todo: optimize the implementation so that download and write are done concurrently.
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk?;
-            file.write_all(&chunk).await?;
+/// Whether a request that failed with `e` might succeed if it is sent again.
+fn is_transient(e: &reqwest::Error) -> bool {
+    match e.status() {
+        Some(status) => status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+        None => {
+            // An interrupted body surfaces as a decode error wrapping a body error
+            let body_error = std::error::Error::source(e)
+                .and_then(|source| source.downcast_ref::<reqwest::Error>())
+                .is_some_and(reqwest::Error::is_body);
+            e.is_timeout() || e.is_connect() || e.is_request() || e.is_body() || body_error
         }
+    }
+}
 
-        file.flush().await?;
-        Ok(())
-    } else {
-        Err(crate::errors::PackError::NetworkError(
-            response.error_for_status().unwrap_err(),
-        ))
+/// The file a download to `dest` is written to until it is complete.
+pub(crate) fn part_path(dest: &Path) -> PathBuf {
+    let mut part_path = dest.as_os_str().to_owned();
+    part_path.push(".part");
+    PathBuf::from(part_path)
+}
+
+/// The file next to a `.part` file holding the validator of the response the `.part` file was started from.
+fn validator_path(part_path: &Path) -> PathBuf {
+    let mut validator_path = part_path.as_os_str().to_owned();
+    validator_path.push(".validator");
+    PathBuf::from(validator_path)
+}
+
+/// Remove a `.part` file and its validator, so that the download starts over.
+async fn remove_part(part_path: &Path) -> PackResult<()> {
+    let _ = tokio::fs::remove_file(validator_path(part_path)).await;
+    match tokio::fs::remove_file(part_path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(PackError::DeleteFailed(part_path.to_path_buf()))
+        }
+        _ => Ok(()),
     }
 }
 
+/// The validator `If-Range` accepts for a response: its strong `ETag`, or else its `Last-Modified` date.
+fn validator(response: &Response) -> Option<String> {
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    header(ETAG)
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| header(LAST_MODIFIED))
+        .map(str::to_string)
+}
+
+/// The first byte of a `Content-Range: bytes <start>-<end>/<length>` header.
+fn content_range_start(response: &Response) -> Option<u64> {
+    let range = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (start, _) = range.strip_prefix("bytes ")?.split_once('-')?;
+    start.trim().parse().ok()
+}
+
 /// Download a file from the given URL to the specified destination.
-pub fn download_file(url: impl AsRef<str>, dest: impl AsRef<Path>) -> PackResult<()> {
-    // Create a new runtime for executing async code
-    let rt = Runtime::new()?;
+///
+/// The file is written to `<dest>.part`, which is renamed to `dest` once it is complete.
+/// If the `.part` file already exists, for example after a failed attempt, the download
+/// resumes from its end with an HTTP Range request. `If-Range` makes the server send the whole
+/// file instead if it changed since the `.part` file was started, and a response that does not
+/// start at the end of the `.part` file makes the download start over.
+async fn download_file(
+    client: &Client,
+    url: &str,
+    dest: &Path,
+    options: &HttpOptions,
+    bar: &ProgressBar,
+) -> PackResult<()> {
+    let part_path = part_path(dest);
 
-    // Block on the async download function
-    rt.block_on(download_file_async(url, dest))
+    options
+        .retry(url, || download_part(client, url, &part_path, bar))
+        .await?;
+    tokio::fs::rename(&part_path, dest)
+        .await
+        .map_err(|_| PackError::CopyFailed(part_path.clone(), dest.to_path_buf()))?;
+    let _ = tokio::fs::remove_file(validator_path(&part_path)).await;
+    Ok(())
+}
+
+/// Download `url` into `part_path`, continuing after the bytes already in it.
+async fn download_part(
+    client: &Client,
+    url: &str,
+    part_path: &Path,
+    bar: &ProgressBar,
+) -> PackResult<()> {
+    let offset = match tokio::fs::metadata(part_path).await {
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    };
+    let validator_path = validator_path(part_path);
+    let mut request = client.get(url);
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={offset}-"));
+        if let Ok(validator) = tokio::fs::read_to_string(&validator_path).await {
+            request = request.header(IF_RANGE, validator);
+        }
+    }
+    let response = request.send().await?;
+
+    let append = match response.status() {
+        StatusCode::PARTIAL_CONTENT if offset > 0 => {
+            let start = content_range_start(&response);
+            if start != Some(offset) {
+                // Appending would put the bytes at the wrong place, start over
+                log::debug!(
+                    "Cannot resume {url}: asked for byte {offset}, got {}, downloading it again",
+                    start.map_or("no Content-Range".to_string(), |start| format!(
+                        "byte {start}"
+                    ))
+                );
+                remove_part(part_path).await?;
+                return Box::pin(download_part(client, url, part_path, bar)).await;
+            }
+            log::debug!("Resuming {url} from byte {offset}");
+            true
+        }
+        StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => {
+            // The partial file does not belong to the current content, start over
+            log::debug!("Cannot resume {url}, downloading it again");
+            remove_part(part_path).await?;
+            return Box::pin(download_part(client, url, part_path, bar)).await;
+        }
+        status if status.is_success() => false,
+        _ => {
+            return Err(PackError::NetworkError(
+                response.error_for_status().unwrap_err(),
+            ));
+        }
+    };
+    log::debug!("Successfully connected to {url}");
+
+    let offset = if append { offset } else { 0 };
+    if !append {
+        // Remember which version of the file the `.part` file holds, for resuming it
+        match validator(&response) {
+            Some(validator) => tokio::fs::write(&validator_path, validator)
+                .await
+                .map_err(|_| PackError::CreateFailed(validator_path.clone()))?,
+            None => {
+                let _ = tokio::fs::remove_file(&validator_path).await;
+            }
+        }
+    }
+    if let Some(length) = response.content_length() {
+        set_download_length(bar, offset + length);
+    }
+    bar.set_position(offset);
+    bar.reset_eta();
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(part_path)
+        .await
+        .map_err(|_| PackError::CreateFailed(part_path.to_path_buf()))?;
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        file.write_all(&chunk).await?;
+        bar.inc(chunk.len() as u64);
+    }
+    file.flush().await?;
+    Ok(())
 }
 
 /// Download a file and get its content as a String.
+async fn download_file_content(
+    client: &Client,
+    url: &str,
+    options: &HttpOptions,
+) -> PackResult<String> {
+    options
+        .retry(url, || async {
+            let response = client.get(url).send().await?.error_for_status()?;
+            log::debug!("Successfully connected to {url}");
+            Ok(response.text().await?)
+        })
+        .await
+}
+
+/// Size, duration and average rate of the download of `path`, e.g. `12.3 MiB in 4 seconds, 3.1 MiB/s`.
+fn transfer_stats(path: &Path, elapsed: Duration) -> String {
+    let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or_default();
+    let rate = size as f64 / elapsed.as_secs_f64().max(0.001);
+    format!(
+        "{} in {}, {}/s",
+        HumanBytes(size),
+        HumanDuration(elapsed),
+        HumanBytes(rate as u64)
+    )
+}
+
+/// Parse the checksum from the content of a `sha256sum` style file.
+pub(crate) fn parse_sha256sum(content: &str) -> String {
+    content
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+/// Wait for all `downloads` and collect their results in order.
+/// If any of them failed, every failure is returned, as a single [`PackError::DownloadsFailed`] if there are several.
+pub(crate) async fn join_downloads<T>(
+    downloads: impl IntoIterator<Item = impl Future<Output = PackResult<T>>>,
+) -> PackResult<Vec<T>> {
+    let mut values = Vec::new();
+    let mut errors = Vec::new();
+    for result in futures::future::join_all(downloads).await {
+        match result {
+            Ok(value) => values.push(value),
+            Err(PackError::DownloadsFailed(nested)) => errors.extend(nested),
+            Err(e) => errors.push(e),
+        }
+    }
+    match errors.len() {
+        0 => Ok(values),
+        1 => Err(errors.remove(0)),
+        _ => Err(PackError::DownloadsFailed(errors)),
+    }
+}
+
+/// How a downloaded file is verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification<'a> {
+    /// The file is not verified.
+    Nothing,
+    /// The file must have this SHA256 checksum.
+    Sha256(&'a str),
+    /// The file must match the `sha256sum` style file at `url`.
+    /// If the file does not exist and `required` is `false`, the download is not verified.
+    Sha256sum { url: &'a str, required: bool },
+}
+
+/// Number of files requested from and fetched by a [`Downloader`] and its clones.
+#[derive(Debug, Default)]
+struct Progress {
+    requested: AtomicUsize,
+    fetched: AtomicUsize,
+}
+
+/// Fetches the files shared by all targets, through the cache and the network settings of a [`PackJob`].
 ///
-/// Async version of `download_file_content`.
-pub async fn download_file_content_async(url: impl AsRef<str>) -> PackResult<String> {
-    let response = reqwest::get(url.as_ref()).await?;
+/// All downloads share one runtime and one HTTP client, so that several files can be fetched concurrently.
+#[derive(Debug, Clone)]
+pub struct Downloader {
+    cache: Option<Cache>,
+    offline: bool,
+    mirrors: Vec<Mirror>,
+    http: HttpOptions,
+    client: Client,
+    runtime: Arc<Runtime>,
+    progress: Arc<Progress>,
+    show_progress: bool,
+}
+impl Downloader {
+    pub fn new(job: &PackJob) -> PackResult<Self> {
+        Ok(Downloader {
+            cache: job.cache.clone(),
+            offline: job.offline,
+            mirrors: job.mirrors.clone(),
+            http: job.http.clone(),
+            client: job.http.client()?,
+            runtime: Arc::new(Runtime::new()?),
+            progress: Arc::default(),
+            show_progress: job.progress,
+        })
+    }
+
+    /// Run `future` on the runtime of the downloader, blocking the current thread until it completes.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    /// The URLs to try for `url`: the mirrors that apply to it in order, then `url` itself.
+    fn candidates(&self, url: &str) -> Vec<String> {
+        let mut candidates: Vec<String> = Vec::with_capacity(self.mirrors.len() + 1);
+        for candidate in self
+            .mirrors
+            .iter()
+            .filter_map(|mirror| mirror.apply(url))
+            .chain([url.to_string()])
+        {
+            if !candidates.contains(&candidate) {
+                candidates.push(candidate);
+            }
+        }
+        candidates
+    }
+
+    /// Download the content of `url` as a String, from `url` itself and never from a mirror.
+    ///
+    /// This is how checksum files are fetched: the checksum a mirrored file is verified against must not come
+    /// from the same, possibly tampered, mirror as the file.
+    async fn fetch_content(&self, url: &str) -> PackResult<String> {
+        download_file_content(&self.client, url, &self.http).await
+    }
+
+    /// Download `url` to `path`, trying its mirrors in order until one of them succeeds and
+    /// matches `expected` if given. The checksum of the downloaded file is returned.
+    async fn download_verified(
+        &self,
+        url: &str,
+        path: &Path,
+        expected: Option<&str>,
+    ) -> PackResult<String> {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let bar = download_bar(self.show_progress, &file_name);
+        let result = self
+            .download_candidates(url, path, expected, &file_name, &bar)
+            .await;
+        bar.finish_and_clear();
+        result
+    }
 
-    if response.status().is_success() {
-        log::debug!("Successfully connected to {}", url.as_ref());
-        let content = response.text().await?;
-        Ok(content)
-    } else {
-        Err(crate::errors::PackError::NetworkError(
-            response.error_for_status().unwrap_err(),
-        ))
+    async fn download_candidates(
+        &self,
+        url: &str,
+        path: &Path,
+        expected: Option<&str>,
+        file_name: &str,
+        bar: &ProgressBar,
+    ) -> PackResult<String> {
+        let mut last_error = None;
+        for candidate in self.candidates(url) {
+            let started = Instant::now();
+            let error = match download_file(&self.client, &candidate, path, &self.http, bar).await {
+                Ok(()) => {
+                    let sha256 = sha256_file(path)?;
+                    match expected {
+                        Some(expected) if sha256 != expected => {
+                            let _ = std::fs::remove_file(path);
+                            PackError::ChecksumFailed {
+                                expected: expected.to_string(),
+                                got: sha256,
+                            }
+                        }
+                        _ => {
+                            let stats = transfer_stats(path, started.elapsed());
+                            if candidate == url {
+                                log::info!("Downloaded {file_name} ({stats})");
+                            } else {
+                                log::info!(
+                                    "Downloaded {file_name} from mirror {candidate} ({stats})"
+                                );
+                            }
+                            return Ok(sha256);
+                        }
+                    }
+                }
+                // The `.part` file is kept, for the next candidate or run to resume it
+                Err(e @ PackError::NetworkError(_)) => e,
+                Err(e) => return Err(e),
+            };
+            log::warn!("Failed to download {candidate}: {error}");
+            last_error = Some(error);
+        }
+        Err(last_error.expect("there is always at least one candidate"))
+    }
+
+    /// Download a file from `url` to `dest`, verifying it as `verification` says.
+    /// The mirrors are tried in order before `url` itself, moving on to the next one
+    /// on connection errors, non-2xx status or checksum mismatch. A partial download is resumed
+    /// by the next candidate or run, and only dropped once the whole file does not match the checksum.
+    /// A `sha256sum` file is always fetched from its own URL, so a mirror cannot serve both a file and its checksum.
+    ///
+    /// With a cache, a cached copy with the known checksum, or younger than the TTL of the cache, is used
+    /// without any network access. An older one is used if it still matches the upstream checksum.
+    /// Otherwise the file is downloaded and stored in the cache. In offline mode, any cached copy is used
+    /// and nothing is downloaded.
+    pub async fn fetch_file(
+        &self,
+        url: &str,
+        verification: Verification<'_>,
+        dest: &Path,
+    ) -> PackResult<()> {
+        let requested = self.progress.requested.fetch_add(1, Ordering::SeqCst) + 1;
+        log::debug!("Fetching {url} ({requested} files requested)");
+        self.fetch(url, verification, dest).await?;
+
+        let fetched = self.progress.fetched.fetch_add(1, Ordering::SeqCst) + 1;
+        log::info!(
+            "Fetched {} ({fetched}/{} files)",
+            dest.file_name().unwrap().to_string_lossy(),
+            self.progress.requested.load(Ordering::SeqCst)
+        );
+        Ok(())
+    }
+
+    async fn fetch(
+        &self,
+        url: &str,
+        verification: Verification<'_>,
+        dest: &Path,
+    ) -> PackResult<()> {
+        let file_name = dest.file_name().unwrap().to_string_lossy();
+        let cache = self.cache.as_ref();
+
+        // `None` until the expected checksum is known, which costs a request for a `sha256sum` file
+        let mut expected = match verification {
+            Verification::Sha256(sha256) => Some(Some(sha256.to_lowercase())),
+            _ => None,
+        };
+        if let Some(cache) = cache
+            && let Some(entry) = cache.lookup(url)?
+        {
+            let usable = match &expected {
+                Some(Some(sha256)) => *sha256 == entry.sha256,
+                _ if self.offline || cache.is_fresh(&entry) => true,
+                _ => {
+                    let sha256 = self.expected_sha256(verification).await?;
+                    let matches = sha256.as_ref() == Some(&entry.sha256);
+                    if matches {
+                        cache.touch(url)?;
+                    }
+                    expected = Some(sha256);
+                    matches
+                }
+            };
+            if usable {
+                log::info!("Using cached {file_name} from {url}");
+                return cache.copy_to(&entry, dest);
+            }
+        }
+        if self.offline {
+            return Err(PackError::OfflineUnavailable(format!(
+                "{file_name} (no cached copy of {url})"
+            )));
+        }
+
+        let expected = match expected {
+            Some(expected) => expected,
+            None => self.expected_sha256(verification).await?,
+        };
+
+        let download_path = match cache {
+            Some(cache) => cache.temp_path(url)?,
+            None => dest.to_path_buf(),
+        };
+        let sha256 = self
+            .download_verified(url, &download_path, expected.as_deref())
+            .await?;
+        if expected.is_some() {
+            log::info!("Verified {file_name} checksum");
+        }
+
+        if let Some(cache) = cache {
+            let entry = cache.store(url, &download_path, &sha256)?;
+            cache.copy_to(&entry, dest)?;
+        }
+        Ok(())
+    }
+
+    /// The checksum a download must have, or `None` if it is not verified.
+    async fn expected_sha256(&self, verification: Verification<'_>) -> PackResult<Option<String>> {
+        match verification {
+            Verification::Nothing => Ok(None),
+            Verification::Sha256(sha256) => Ok(Some(sha256.to_lowercase())),
+            Verification::Sha256sum {
+                url,
+                required: true,
+            } => Ok(Some(parse_sha256sum(&self.fetch_content(url).await?))),
+            Verification::Sha256sum {
+                url,
+                required: false,
+            } => match self.fetch_content(url).await {
+                Ok(content) => Ok(Some(parse_sha256sum(&content))),
+                Err(PackError::NetworkError(e)) if e.status() == Some(StatusCode::NOT_FOUND) => {
+                    log::warn!("{url} does not exist, the download is not verified");
+                    Ok(None)
+                }
+                Err(e) => Err(e),
+            },
+        }
     }
 }
 
-/// Download a file and get its content as a String.
-pub fn download_file_content(url: impl AsRef<str>) -> PackResult<String> {
-    // Create a new runtime for executing async code
-    let rt = Runtime::new()?;
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::{TcpListener, TcpStream},
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        thread,
+    };
 
-    // Block on the async download function
-    rt.block_on(download_file_content_async(url))
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+    use crate::{checksum::to_hex, target::Core};
+
+    pub(crate) const BODY: &[u8] = b"0123456789abcdefghij";
+
+    /// A request received by [`serve`]: its index, its path and the `Range` and `If-Range` headers if any.
+    pub(crate) struct Request {
+        pub index: usize,
+        pub path: String,
+        pub range: Option<String>,
+        pub if_range: Option<String>,
+    }
+
+    /// Serve HTTP on a local port, answering every request with `respond`.
+    /// Returns the URL of the server and the number of requests received so far.
+    pub(crate) fn serve(
+        respond: impl Fn(&Request, &mut TcpStream) + Send + Sync + 'static,
+    ) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/file", listener.local_addr().unwrap());
+        let count = Arc::new(AtomicUsize::new(0));
+        let respond = Arc::new(respond);
+
+        let server_count = Arc::clone(&count);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let index = server_count.fetch_add(1, Ordering::SeqCst);
+                let respond = Arc::clone(&respond);
+                thread::spawn(move || {
+                    let mut range = None;
+                    let mut if_range = None;
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut request_line = String::new();
+                    reader.read_line(&mut request_line).unwrap();
+                    let path = request_line
+                        .split_whitespace()
+                        .nth(1)
+                        .unwrap_or_default()
+                        .to_string();
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                        if let Some((name, value)) = line.split_once(':') {
+                            if name.eq_ignore_ascii_case("range") {
+                                range = Some(value.trim().to_string());
+                            } else if name.eq_ignore_ascii_case("if-range") {
+                                if_range = Some(value.trim().to_string());
+                            }
+                        }
+                    }
+                    respond(
+                        &Request {
+                            index,
+                            path,
+                            range,
+                            if_range,
+                        },
+                        &mut stream,
+                    );
+                });
+            }
+        });
+
+        (url, count)
+    }
+
+    pub(crate) fn respond(stream: &mut TcpStream, status: &str, headers: &[String], body: &[u8]) {
+        let mut response = format!("HTTP/1.1 {status}\r\nConnection: close\r\n");
+        for header in headers {
+            response.push_str(header);
+            response.push_str("\r\n");
+        }
+        response.push_str("\r\n");
+        stream.write_all(response.as_bytes()).unwrap();
+        let _ = stream.write_all(body);
+    }
+
+    pub(crate) fn ok(stream: &mut TcpStream) {
+        respond(
+            stream,
+            "200 OK",
+            &[format!("Content-Length: {}", BODY.len())],
+            BODY,
+        );
+    }
+
+    pub(crate) fn downloader(http: HttpOptions) -> Downloader {
+        let job = PackJob::builder(Core::Xray)
+            .cache(None)
+            .http(http)
+            .build()
+            .unwrap();
+        Downloader::new(&job).unwrap()
+    }
+
+    fn cached_downloader(http: HttpOptions, cache_dir: &Path) -> Downloader {
+        let job = PackJob::builder(Core::Xray)
+            .cache(Some(Cache::new(cache_dir, Cache::DEFAULT_TTL)))
+            .http(http)
+            .build()
+            .unwrap();
+        Downloader::new(&job).unwrap()
+    }
+
+    /// A downloader trying the server at `mirror_url`, as returned by [`serve`], before every URL.
+    fn mirrored_downloader(mirror_url: &str) -> Downloader {
+        let base = mirror_url.trim_end_matches("/file");
+        let job = PackJob::builder(Core::Xray)
+            .cache(None)
+            .http(options())
+            .mirror(format!("{base}/mirror/{{path}}").parse().unwrap())
+            .build()
+            .unwrap();
+        Downloader::new(&job).unwrap()
+    }
+
+    fn download(url: &str, dest: &Path, http: HttpOptions) -> PackResult<()> {
+        let downloader = downloader(http);
+        downloader.block_on(downloader.fetch_file(url, Verification::Nothing, dest))
+    }
+
+    pub(crate) fn options() -> HttpOptions {
+        HttpOptions {
+            connect_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_secs(5),
+            retries: 3,
+            backoff: Duration::from_millis(1),
+            proxy: Proxy::default(),
+        }
+    }
+
+    #[test]
+    fn retries_server_errors() {
+        let (url, count) = serve(|request, stream| match request.index {
+            0 | 1 => respond(stream, "503 Service Unavailable", &[], b""),
+            _ => ok(stream),
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("file");
+
+        download(&url, &dest, options()).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), BODY);
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+        assert!(!part_path(&dest).exists());
+    }
+
+    #[test]
+    fn gives_up_after_retries() {
+        let (url, count) = serve(|_, stream| respond(stream, "502 Bad Gateway", &[], b""));
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("file");
+
+        let result = download(
+            &url,
+            &dest,
+            HttpOptions {
+                retries: 2,
+                ..options()
+            },
+        );
+        assert!(matches!(result, Err(PackError::NetworkError(_))));
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+        assert!(!dest.exists());
+    }
+
+    #[test]
+    fn does_not_retry_client_errors() {
+        let (url, count) = serve(|_, stream| respond(stream, "404 Not Found", &[], b""));
+
+        let downloader = downloader(options());
+        let result = downloader.block_on(downloader.fetch_content(&url));
+        assert!(matches!(result, Err(PackError::NetworkError(_))));
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn retries_content_downloads() {
+        let (url, count) = serve(|request, stream| match request.index {
+            0 => respond(stream, "429 Too Many Requests", &[], b""),
+            _ => ok(stream),
+        });
+
+        let downloader = downloader(options());
+        let content = downloader.block_on(downloader.fetch_content(&url)).unwrap();
+        assert_eq!(content.as_bytes(), BODY);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn resumes_interrupted_downloads() {
+        let (url, count) = serve(|request, stream| match request.index {
+            // Promise the whole body but hang up after 8 bytes
+            0 => respond(
+                stream,
+                "200 OK",
+                &[format!("Content-Length: {}", BODY.len())],
+                &BODY[..8],
+            ),
+            _ => {
+                assert_eq!(request.range.as_deref(), Some("bytes=8-"));
+                respond(
+                    stream,
+                    "206 Partial Content",
+                    &[
+                        format!("Content-Length: {}", BODY.len() - 8),
+                        format!("Content-Range: bytes 8-{}/{}", BODY.len() - 1, BODY.len()),
+                    ],
+                    &BODY[8..],
+                );
+            }
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("file");
+
+        download(&url, &dest, options()).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), BODY);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        assert!(!part_path(&dest).exists());
+    }
+
+    #[test]
+    fn resumes_from_existing_part_file() {
+        let (url, _) = serve(|request, stream| {
+            assert_eq!(request.range.as_deref(), Some("bytes=15-"));
+            respond(
+                stream,
+                "206 Partial Content",
+                &[
+                    format!("Content-Length: {}", BODY.len() - 15),
+                    format!("Content-Range: bytes 15-{}/{}", BODY.len() - 1, BODY.len()),
+                ],
+                &BODY[15..],
+            );
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("file");
+        std::fs::write(part_path(&dest), &BODY[..15]).unwrap();
+
+        download(&url, &dest, options()).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), BODY);
+    }
+
+    #[test]
+    fn restarts_when_range_is_ignored() {
+        let (url, _) = serve(|_, stream| ok(stream));
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("file");
+        std::fs::write(part_path(&dest), b"stale").unwrap();
+
+        download(&url, &dest, options()).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), BODY);
+    }
+
+    #[test]
+    fn restarts_when_range_is_not_satisfiable() {
+        let (url, count) = serve(|request, stream| match request.range {
+            Some(_) => respond(stream, "416 Range Not Satisfiable", &[], b""),
+            None => ok(stream),
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("file");
+        std::fs::write(part_path(&dest), b"a part longer than the file itself").unwrap();
+
+        download(&url, &dest, options()).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), BODY);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn resumes_with_if_range_of_the_first_response() {
+        let (url, count) = serve(|request, stream| match request.index {
+            0 => respond(
+                stream,
+                "200 OK",
+                &[
+                    format!("Content-Length: {}", BODY.len()),
+                    "ETag: \"v1\"".to_string(),
+                ],
+                &BODY[..8],
+            ),
+            _ => {
+                assert_eq!(request.range.as_deref(), Some("bytes=8-"));
+                assert_eq!(request.if_range.as_deref(), Some("\"v1\""));
+                respond(
+                    stream,
+                    "206 Partial Content",
+                    &[
+                        format!("Content-Length: {}", BODY.len() - 8),
+                        format!("Content-Range: bytes 8-{}/{}", BODY.len() - 1, BODY.len()),
+                    ],
+                    &BODY[8..],
+                );
+            }
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("file");
+
+        download(&url, &dest, options()).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), BODY);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        assert!(!validator_path(&part_path(&dest)).exists());
+    }
+
+    #[test]
+    fn restarts_when_content_range_does_not_match() {
+        let (url, count) = serve(|request, stream| match request.range {
+            // Answer from the start of the file although byte 15 was asked for
+            Some(_) => respond(
+                stream,
+                "206 Partial Content",
+                &[
+                    format!("Content-Length: {}", BODY.len()),
+                    format!("Content-Range: bytes 0-{}/{}", BODY.len() - 1, BODY.len()),
+                ],
+                BODY,
+            ),
+            None => ok(stream),
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("file");
+        std::fs::write(part_path(&dest), &BODY[..15]).unwrap();
+
+        download(&url, &dest, options()).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), BODY);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn restarts_when_content_range_is_missing() {
+        let (url, count) = serve(|request, stream| match request.range {
+            Some(_) => respond(
+                stream,
+                "206 Partial Content",
+                &[format!("Content-Length: {}", BODY.len() - 15)],
+                &BODY[15..],
+            ),
+            None => ok(stream),
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("file");
+        std::fs::write(part_path(&dest), b"stale content..").unwrap();
+
+        download(&url, &dest, options()).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), BODY);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn retries_after_read_timeout() {
+        let (url, count) = serve(|request, stream| match request.index {
+            // Send the headers, then stall longer than the read timeout
+            0 => {
+                respond(
+                    stream,
+                    "200 OK",
+                    &[format!("Content-Length: {}", BODY.len())],
+                    b"",
+                );
+                thread::sleep(Duration::from_secs(2));
+            }
+            _ => ok(stream),
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("file");
+
+        download(
+            &url,
+            &dest,
+            HttpOptions {
+                read_timeout: Duration::from_millis(200),
+                ..options()
+            },
+        )
+        .unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), BODY);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn resumes_through_the_cache_in_a_later_run() {
+        let (url, count) = serve(|request, stream| match request.index {
+            // Hang up after 8 bytes, and there are no retries
+            0 => respond(
+                stream,
+                "200 OK",
+                &[
+                    format!("Content-Length: {}", BODY.len()),
+                    "ETag: \"v1\"".to_string(),
+                ],
+                &BODY[..8],
+            ),
+            _ => {
+                assert_eq!(request.range.as_deref(), Some("bytes=8-"));
+                assert_eq!(request.if_range.as_deref(), Some("\"v1\""));
+                respond(
+                    stream,
+                    "206 Partial Content",
+                    &[
+                        format!("Content-Length: {}", BODY.len() - 8),
+                        format!("Content-Range: bytes 8-{}/{}", BODY.len() - 1, BODY.len()),
+                    ],
+                    &BODY[8..],
+                );
+            }
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let cache_dir = dir.path().join("cache");
+        let dest = dir.path().join("file");
+        let http = HttpOptions {
+            retries: 0,
+            ..options()
+        };
+
+        let downloader = cached_downloader(http.clone(), &cache_dir);
+        let result = downloader.block_on(downloader.fetch_file(&url, Verification::Nothing, &dest));
+        assert!(matches!(result, Err(PackError::NetworkError(_))));
+        let part = part_path(
+            &Cache::new(&cache_dir, Cache::DEFAULT_TTL)
+                .temp_path(&url)
+                .unwrap(),
+        );
+        assert_eq!(std::fs::read(&part).unwrap(), &BODY[..8]);
+
+        let downloader = cached_downloader(http, &cache_dir);
+        downloader
+            .block_on(downloader.fetch_file(&url, Verification::Nothing, &dest))
+            .unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), BODY);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        assert!(!part.exists());
+        assert!(!validator_path(&part).exists());
+        let cache = Cache::new(&cache_dir, Cache::DEFAULT_TTL);
+        assert_eq!(cache.lookup(&url).unwrap().unwrap().size, BODY.len() as u64);
+    }
+
+    #[test]
+    fn falls_back_to_the_next_candidate_on_client_errors() {
+        let (mirror_url, mirror_count) =
+            serve(|_, stream| respond(stream, "404 Not Found", &[], b""));
+        let (url, count) = serve(|_, stream| ok(stream));
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("file");
+
+        let downloader = mirrored_downloader(&mirror_url);
+        downloader
+            .block_on(downloader.fetch_file(&url, Verification::Nothing, &dest))
+            .unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), BODY);
+        assert_eq!(mirror_count.load(Ordering::SeqCst), 1);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn falls_back_to_the_next_candidate_on_checksum_mismatch() {
+        let (mirror_url, mirror_count) = serve(|_, stream| {
+            respond(
+                stream,
+                "200 OK",
+                &["Content-Length: 8".to_string()],
+                b"tampered",
+            )
+        });
+        let (url, count) = serve(|_, stream| ok(stream));
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("file");
+        let sha256 = to_hex(&Sha256::digest(BODY));
+
+        let downloader = mirrored_downloader(&mirror_url);
+        downloader
+            .block_on(downloader.fetch_file(&url, Verification::Sha256(&sha256), &dest))
+            .unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), BODY);
+        assert_eq!(mirror_count.load(Ordering::SeqCst), 1);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert!(!part_path(&dest).exists());
+    }
+
+    #[test]
+    fn fails_when_no_candidate_matches_the_checksum() {
+        let (mirror_url, _) = serve(|_, stream| ok(stream));
+        let (url, _) = serve(|_, stream| ok(stream));
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("file");
+        let sha256 = to_hex(&Sha256::digest(b"something else"));
+
+        let downloader = mirrored_downloader(&mirror_url);
+        let result =
+            downloader.block_on(downloader.fetch_file(&url, Verification::Sha256(&sha256), &dest));
+        assert!(matches!(result, Err(PackError::ChecksumFailed { .. })));
+        assert!(!dest.exists());
+    }
+
+    #[test]
+    fn fetches_checksum_files_from_upstream_only() {
+        let sha256 = to_hex(&Sha256::digest(BODY));
+        let (mirror_url, mirror_count) = serve(|_, stream| ok(stream));
+        let (checksum_url, checksum_count) = serve(move |_, stream| {
+            let content = format!("{sha256}  file\n");
+            respond(
+                stream,
+                "200 OK",
+                &[format!("Content-Length: {}", content.len())],
+                content.as_bytes(),
+            )
+        });
+        let (url, count) = serve(|_, stream| ok(stream));
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("file");
+
+        let downloader = mirrored_downloader(&mirror_url);
+        let verification = Verification::Sha256sum {
+            url: &checksum_url,
+            required: true,
+        };
+        downloader
+            .block_on(downloader.fetch_file(&url, verification, &dest))
+            .unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), BODY);
+        // Only the file itself comes from the mirror
+        assert_eq!(mirror_count.load(Ordering::SeqCst), 1);
+        assert_eq!(checksum_count.load(Ordering::SeqCst), 1);
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn downloads_through_the_proxy_unless_bypassed() {
+        let (proxy_url, proxy_count) = serve(|_, stream| ok(stream));
+        let (url, count) = serve(|_, stream| ok(stream));
+        let (bypassed_url, bypassed_count) = serve(|_, stream| ok(stream));
+        let authority = |url: &str| {
+            url.trim_start_matches("http://")
+                .trim_end_matches("/file")
+                .to_string()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("file");
+        let http = HttpOptions {
+            proxy: Proxy::default()
+                .with_url(proxy_url.trim_end_matches("/file"))
+                .unwrap()
+                .with_no_proxy(vec![authority(&bypassed_url)]),
+            ..options()
+        };
+
+        download(&url, &dest, http.clone()).unwrap();
+        assert_eq!(proxy_count.load(Ordering::SeqCst), 1);
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+
+        download(&bypassed_url, &dest, http).unwrap();
+        assert_eq!(proxy_count.load(Ordering::SeqCst), 1);
+        assert_eq!(bypassed_count.load(Ordering::SeqCst), 1);
+    }
 }
@@ -1,29 +1,29 @@
-use std::fs;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
-use crate::{COLLECTED_FILES, REPOSITORY_DIR, TEMP_DIR, errors::PackResult};
+use crate::errors::PackResult;
+
+/// systemd service files of v2ray-core, packaged for every target.
+pub(crate) const V2RAY_SERVICE_NAMES: [&str; 2] = ["v2ray.service", "v2ray@.service"];
 
 /// Copy v2ray systemd service files from the repository (v2ray.service and v2ray@.service)
-pub fn copy_v2ray_services() -> PackResult<()> {
-    let repo_dir = REPOSITORY_DIR.get().unwrap();
+/// into `dest_dir`. The paths of the copied files are returned.
+pub fn copy_v2ray_services(repo_dir: &Path, dest_dir: &Path) -> PackResult<Vec<PathBuf>> {
     let service_dir = repo_dir.join("release/config/systemd/system");
 
     // Copy v2ray.service
     let src = service_dir.join("v2ray.service");
-    let dest = TEMP_DIR.join("v2ray.service");
-    fs::copy(&src, &dest)?;
+    let service = dest_dir.join("v2ray.service");
+    fs::copy(&src, &service)?;
     log::info!("Copied v2ray.service");
 
-    // Add v2ray.service to collected files
-    COLLECTED_FILES.lock().unwrap().push(dest);
-
     // Copy v2ray@.service
     let src = service_dir.join("v2ray@.service");
-    let dest = TEMP_DIR.join("v2ray@.service");
-    fs::copy(&src, &dest)?;
+    let template_service = dest_dir.join("v2ray@.service");
+    fs::copy(&src, &template_service)?;
     log::info!("Copied v2ray@.service");
 
-    // Add v2ray@.service to collected files
-    COLLECTED_FILES.lock().unwrap().push(dest);
-
-    Ok(())
+    Ok(vec![service, template_service])
 }
@@ -1,8 +1,15 @@
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use goblin::pe::header::{
+    COFF_MACHINE_ARM64, COFF_MACHINE_ARMNT, COFF_MACHINE_X86, COFF_MACHINE_X86_64,
+};
 
 use crate::{
-    COLLECTED_FILES, TEMP_DIR,
-    download::download_file,
+    checksum::sha256_file,
+    download::{Downloader, Verification, authenticode::read_pe_signature},
     errors::{PackError, PackResult},
 };
 
@@ -22,34 +29,222 @@ impl Display for WinPlatform {
         }
     }
 }
-impl From<&str> for WinPlatform {
-    fn from(s: &str) -> Self {
-        match s.to_lowercase().as_str() {
-            "x86" => WinPlatform::X86,
-            "amd64" => WinPlatform::Amd64,
-            "arm" => WinPlatform::Arm,
-            "arm64" => WinPlatform::Arm64,
-            _ => panic!("Invalid platform: {}", s),
+impl WinPlatform {
+    /// `IMAGE_FILE_MACHINE_*` of the PE files of the platform.
+    fn machine(&self) -> u16 {
+        match self {
+            WinPlatform::X86 => COFF_MACHINE_X86,
+            WinPlatform::Amd64 => COFF_MACHINE_X86_64,
+            WinPlatform::Arm => COFF_MACHINE_ARMNT,
+            WinPlatform::Arm64 => COFF_MACHINE_ARM64,
+        }
+    }
+}
+impl TryFrom<&str> for WinPlatform {
+    type Error = PackError;
+
+    /// Convert a `GOARCH` to the platform of wintun.
+    fn try_from(goarch: &str) -> Result<Self, Self::Error> {
+        match goarch.to_lowercase().as_str() {
+            "386" | "x86" => Ok(WinPlatform::X86),
+            "amd64" => Ok(WinPlatform::Amd64),
+            "arm" => Ok(WinPlatform::Arm),
+            "arm64" => Ok(WinPlatform::Arm64),
+            _ => Err(PackError::UnsupportedTarget {
+                target: format!("windows/{goarch}"),
+                hint: "wintun is only available for 386, amd64, arm and arm64.".to_string(),
+            }),
+        }
+    }
+}
+
+/// Release of wintun packaged by default.
+pub const DEFAULT_WINTUN_VERSION: &str = "0.14.1";
+
+/// SHA-1 thumbprints of the certificates WireGuard LLC signs the official wintun releases with.
+pub const WINTUN_SIGNER_THUMBPRINTS: [&str; 1] = ["df98e075a012ed8c86fbcf14854b8f9555cb3d45"];
+
+/// Files extracted from the wintun archive and packaged for Windows targets.
+pub(crate) const WINTUN_FILE_NAMES: [&str; 2] = ["wintun.dll", "LICENSE-wintun.txt"];
+
+/// SHA256 checksums of the wintun releases published at <https://www.wintun.net/>.
+const KNOWN_WINTUN_SHA256: [(&str, &str); 1] = [(
+    "0.14.1",
+    "07c256185d6ee3652e09fa55c0b673e2624b565e02c4b9091c79ca7d2f24ef51",
+)];
+
+/// The known SHA256 checksum of the zip archive of wintun `version`, if any.
+pub fn known_wintun_sha256(version: &str) -> Option<&'static str> {
+    KNOWN_WINTUN_SHA256
+        .iter()
+        .find(|(known, _)| *known == version)
+        .map(|(_, sha256)| *sha256)
+}
+
+/// The wintun release packaged for Windows targets, and where its zip archive comes from.
+///
+/// The archive must always match a SHA256 checksum: the one given with [`Wintun::with_sha256`],
+/// or otherwise the known checksum of the release. The extracted `wintun.dll` must be signed by one of
+/// the signers, given by thumbprint, [`WINTUN_SIGNER_THUMBPRINTS`] by default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wintun {
+    version: String,
+    zip: Option<PathBuf>,
+    sha256: Option<String>,
+    signers: Vec<String>,
+}
+impl Default for Wintun {
+    fn default() -> Self {
+        Wintun::new(DEFAULT_WINTUN_VERSION)
+    }
+}
+impl Wintun {
+    pub fn new(version: impl Into<String>) -> Self {
+        Wintun {
+            version: version.into(),
+            zip: None,
+            sha256: None,
+            signers: WINTUN_SIGNER_THUMBPRINTS.map(ToString::to_string).to_vec(),
+        }
+    }
+
+    /// Use the zip archive at `path` instead of downloading it.
+    pub fn with_zip(mut self, path: impl Into<PathBuf>) -> Self {
+        self.zip = Some(path.into());
+        self
+    }
+
+    /// Expect this SHA256 checksum instead of the known one of the release.
+    pub fn with_sha256(mut self, sha256: impl Into<String>) -> Self {
+        self.sha256 = Some(sha256.into());
+        self
+    }
+
+    /// Accept a `wintun.dll` signed by one of `signers` instead of [`WINTUN_SIGNER_THUMBPRINTS`].
+    /// A signer is given by the SHA-1 or SHA-256 thumbprint of its certificate.
+    pub fn with_signers(mut self, signers: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.signers = signers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn zip(&self) -> Option<&Path> {
+        self.zip.as_deref()
+    }
+
+    pub fn sha256(&self) -> Option<&str> {
+        self.sha256.as_deref()
+    }
+
+    pub fn signers(&self) -> &[String] {
+        &self.signers
+    }
+
+    pub fn url(&self) -> String {
+        format!("https://www.wintun.net/builds/wintun-{}.zip", self.version)
+    }
+
+    /// The checksum the zip archive must match.
+    pub(crate) fn expected_sha256(&self) -> PackResult<String> {
+        match &self.sha256 {
+            Some(sha256) => Ok(sha256.to_lowercase()),
+            None => known_wintun_sha256(&self.version)
+                .map(ToString::to_string)
+                .ok_or_else(|| {
+                    PackError::ConfigError(format!(
+                        "the SHA256 checksum of wintun {} is unknown, give it with --wintun-sha256. Known versions: {}",
+                        self.version,
+                        KNOWN_WINTUN_SHA256
+                            .iter()
+                            .map(|(version, _)| *version)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ))
+                }),
         }
     }
 }
 
-const WINTUN_URL: &str = "https://www.wintun.net/builds/wintun-0.14.1.zip";
+/// Download or copy the wintun zip archive into `dest_dir` and verify its checksum.
+/// The path of the archive is returned.
+pub async fn download_wintun(
+    wintun: &Wintun,
+    dest_dir: &Path,
+    downloader: &Downloader,
+) -> PackResult<PathBuf> {
+    let zip_path = dest_dir.join("wintun.zip");
+    let expected = wintun.expected_sha256()?;
 
-/// Download wintun and extract .dll according to platform. Also extract the LICENSE file.
-pub fn download_and_extract_wintun(platform: WinPlatform) -> PackResult<()> {
-    let zip_path = TEMP_DIR.join("wintun.zip");
+    match &wintun.zip {
+        Some(path) => {
+            std::fs::copy(path, &zip_path)
+                .map_err(|_| PackError::CopyFailed(path.clone(), zip_path.clone()))?;
+            let got = sha256_file(&zip_path)?;
+            if got != expected {
+                return Err(PackError::ChecksumFailed { expected, got });
+            }
+            log::info!("Using {}, checksum verified", path.display());
+        }
+        None => {
+            downloader
+                .fetch_file(&wintun.url(), Verification::Sha256(&expected), &zip_path)
+                .await?;
+        }
+    }
+
+    Ok(zip_path)
+}
+
+/// Check that the PE file at `dll_path` is built for `platform` and signed by one of `signers`.
+fn verify_wintun_dll(
+    dll_path: &Path,
+    platform: &WinPlatform,
+    signers: &[String],
+) -> PackResult<()> {
+    let failed = |reason: String| PackError::SignatureFailed(dll_path.to_path_buf(), reason);
 
-    // Download wintun
-    download_file(WINTUN_URL, &zip_path)?;
-    log::info!("Downloaded wintun");
+    let signature = read_pe_signature(dll_path)?;
+    if signature.machine != platform.machine() {
+        return Err(failed(format!(
+            "built for machine type {:#06x} instead of {platform} ({:#06x})",
+            signature.machine,
+            platform.machine()
+        )));
+    }
+    let signer = signature
+        .signer
+        .ok_or_else(|| failed("no Authenticode signature".to_string()))?;
+    if !signers.iter().any(|entry| signer.matches(entry)) {
+        return Err(failed(format!(
+            "signed by {signer}, which is not one of the allowed signers: {}",
+            signers.join(", ")
+        )));
+    }
+    log::info!("{platform} wintun.dll is signed by {signer}");
+    Ok(())
+}
 
+/// Extract .dll according to platform from the wintun zip archive into `dest_dir`, and check that it is
+/// built for `platform` and signed by one of `signers`.
+/// Also extract the LICENSE file. The paths of the extracted files are returned.
+pub fn extract_wintun(
+    zip_path: &Path,
+    platform: WinPlatform,
+    signers: &[String],
+    dest_dir: &Path,
+) -> PackResult<Vec<PathBuf>> {
     // 1. Extract dll
-    let dll_path = TEMP_DIR.join("wintun.dll");
-    log::debug!("Extracting wintun.dll from {}...", zip_path.display());
+    let dll_path = dest_dir.join(WINTUN_FILE_NAMES[0]);
+    log::debug!(
+        "Extracting {platform} wintun.dll from {}...",
+        zip_path.display()
+    );
 
     let reader =
-        std::fs::File::open(&zip_path).map_err(|_| PackError::ReadFailed(zip_path.clone()))?;
+        std::fs::File::open(zip_path).map_err(|_| PackError::ReadFailed(zip_path.to_path_buf()))?;
     let mut zip = zip::ZipArchive::new(reader)?;
     let mut zip_file = zip.by_path(format!("wintun/bin/{}/wintun.dll", platform))?;
 
@@ -57,14 +252,15 @@ pub fn download_and_extract_wintun(platform: WinPlatform) -> PackResult<()> {
         std::fs::File::create(&dll_path).map_err(|_| PackError::CreateFailed(dll_path.clone()))?;
 
     std::io::copy(&mut zip_file, &mut writer)
-        .map_err(|_| PackError::CopyFailed(zip_path.clone(), dll_path.clone()))?;
+        .map_err(|_| PackError::CopyFailed(zip_path.to_path_buf(), dll_path.clone()))?;
+    verify_wintun_dll(&dll_path, &platform, signers)?;
 
     // 2. Extract license
-    let license_path = TEMP_DIR.join("LICENSE-wintun.txt");
+    let license_path = dest_dir.join(WINTUN_FILE_NAMES[1]);
     log::debug!("Extracting wintun LICENSE...");
 
     let reader =
-        std::fs::File::open(&zip_path).map_err(|_| PackError::ReadFailed(zip_path.clone()))?;
+        std::fs::File::open(zip_path).map_err(|_| PackError::ReadFailed(zip_path.to_path_buf()))?;
     let mut zip = zip::ZipArchive::new(reader)?;
     let mut zip_file = zip.by_path("wintun/LICENSE.txt")?;
 
@@ -72,13 +268,79 @@ pub fn download_and_extract_wintun(platform: WinPlatform) -> PackResult<()> {
         .map_err(|_| PackError::CreateFailed(license_path.clone()))?;
 
     std::io::copy(&mut zip_file, &mut writer)
-        .map_err(|_| PackError::CopyFailed(zip_path.clone(), license_path.clone()))?;
+        .map_err(|_| PackError::CopyFailed(zip_path.to_path_buf(), license_path.clone()))?;
 
-    log::info!("Extracted wintun dll and LICENSE");
+    log::info!("Extracted {platform} wintun dll and LICENSE");
 
-    // Add wintun.dll and LICENSE-wintun.txt to collected files
-    COLLECTED_FILES.lock().unwrap().push(dll_path);
-    COLLECTED_FILES.lock().unwrap().push(license_path);
+    Ok(vec![dll_path, license_path])
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::authenticode::{
+        Signer,
+        tests::{pe_file, signature},
+    };
+
+    fn verify(data: &[u8], platform: WinPlatform, signers: &[String]) -> PackResult<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let dll_path = dir.path().join("wintun.dll");
+        std::fs::write(&dll_path, data).unwrap();
+        verify_wintun_dll(&dll_path, &platform, signers)
+    }
+
+    fn read_signer(dll: &[u8]) -> Signer {
+        let dir = tempfile::tempdir().unwrap();
+        let dll_path = dir.path().join("wintun.dll");
+        std::fs::write(&dll_path, dll).unwrap();
+        read_pe_signature(&dll_path).unwrap().signer.unwrap()
+    }
+
+    #[test]
+    fn accepts_dlls_of_allowed_signers() {
+        let (pkcs7, _) = signature("WireGuard LLC", 7, 7);
+        let dll = pe_file(COFF_MACHINE_ARMNT, true, 16, Some(&pkcs7));
+        let signer = read_signer(&dll);
+        assert!(verify(&dll, WinPlatform::Arm, std::slice::from_ref(&signer.sha256)).is_ok());
+        assert!(verify(&dll, WinPlatform::Arm, &["00".repeat(20), signer.sha1]).is_ok());
+    }
+
+    #[test]
+    fn rejects_dlls_of_other_signers() {
+        // Same names as the official certificate, but another thumbprint
+        let (pkcs7, _) = signature("WireGuard LLC", 7, 7);
+        let dll = pe_file(COFF_MACHINE_X86_64, false, 16, Some(&pkcs7));
+        for signers in [
+            Wintun::default().signers().to_vec(),
+            vec!["WireGuard LLC".to_string()],
+        ] {
+            assert!(matches!(
+                verify(&dll, WinPlatform::Amd64, &signers),
+                Err(PackError::SignatureFailed(_, reason)) if reason.contains("not one of the allowed signers")
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_unsigned_dlls() {
+        let dll = pe_file(COFF_MACHINE_X86_64, false, 16, None);
+        assert!(matches!(
+            verify(&dll, WinPlatform::Amd64, Wintun::default().signers()),
+            Err(PackError::SignatureFailed(_, reason)) if reason == "no Authenticode signature"
+        ));
+    }
+
+    #[test]
+    fn rejects_dlls_of_another_machine() {
+        let (pkcs7, _) = signature("WireGuard LLC", 7, 7);
+        let dll = pe_file(COFF_MACHINE_X86_64, false, 16, Some(&pkcs7));
+        let signers = [read_signer(&dll).sha1];
+        for platform in [WinPlatform::X86, WinPlatform::Arm, WinPlatform::Arm64] {
+            assert!(matches!(
+                verify(&dll, platform, &signers),
+                Err(PackError::SignatureFailed(_, reason)) if reason.starts_with("built for machine type 0x8664")
+            ));
+        }
+    }
 }
@@ -7,12 +7,21 @@ pub enum PackError {
     #[error("Async error: {0}")]
     AsyncError(#[from] tokio::io::Error),
 
+    #[error("Unexpected binary {path}: {reason}")]
+    BinaryMismatch { path: PathBuf, reason: String },
+
     #[error("Failed to build Xray-core: {0}")]
     BuildFailed(String),
 
+    #[error("Invalid Go build info in {0}: {1}")]
+    BuildInfoInvalid(PathBuf, String),
+
     #[error("Checksum failed. Expected {expected}, got {got}")]
     ChecksumFailed { expected: String, got: String },
 
+    #[error("Invalid configuration: {0}")]
+    ConfigError(String),
+
     #[error("Failed to copy from {0} to {1}")]
     CopyFailed(PathBuf, PathBuf),
 
@@ -22,18 +31,33 @@ pub enum PackError {
     #[error("Failed to delete file or directory at {0}")]
     DeleteFailed(PathBuf),
 
+    #[error("{} downloads failed:{}", .0.len(), list_errors(.0))]
+    DownloadsFailed(Vec<PackError>),
+
     #[error("Git operation failed: {0}")]
     GitError(#[from] git2::Error),
 
+    #[error("Invalid package name: {0}")]
+    InvalidPackageName(String),
+
     #[error("{0} required but not found.")]
     MissingDependency(String),
 
     #[error("Network error: {0}")]
     NetworkError(#[from] reqwest::Error),
 
+    #[error("{0} is not available in offline mode")]
+    OfflineUnavailable(String),
+
     #[error("Failed to read file at {0}")]
     ReadFailed(PathBuf),
 
+    #[error("Failed to verify the signature of {0}: {1}")]
+    SignatureFailed(PathBuf, String),
+
+    #[error("{target} is not supported. {hint}")]
+    UnsupportedTarget { target: String, hint: String },
+
     #[error("Unimplemented")]
     #[allow(dead_code)]
     Unimplemented,
@@ -41,3 +65,8 @@ pub enum PackError {
     #[error("Zip error: {0}")]
     ZipError(#[from] zip::result::ZipError),
 }
+
+/// One error per line, as a bullet list.
+fn list_errors(errors: &[PackError]) -> String {
+    errors.iter().map(|e| format!("\n  - {e}")).collect()
+}
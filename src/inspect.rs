@@ -0,0 +1,429 @@
+use std::{fmt::Display, path::Path};
+
+use goblin::{
+    Object,
+    elf::{
+        Elf,
+        header::{
+            EI_OSABI, EM_386, EM_AARCH64, EM_ARM, EM_LOONGARCH, EM_MIPS, EM_PPC64, EM_RISCV,
+            EM_S390, EM_X86_64,
+        },
+    },
+    mach::{
+        Mach,
+        cputype::{CPU_TYPE_ARM64, CPU_TYPE_X86_64},
+    },
+    pe::header::{COFF_MACHINE_ARM64, COFF_MACHINE_ARMNT, COFF_MACHINE_X86, COFF_MACHINE_X86_64},
+};
+
+use crate::{
+    errors::{PackError, PackResult},
+    target::GoTarget,
+};
+
+/// Object file format of a binary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    Elf,
+    Pe,
+    MachO,
+}
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Elf => write!(f, "ELF"),
+            Format::Pe => write!(f, "PE"),
+            Format::MachO => write!(f, "Mach-O"),
+        }
+    }
+}
+
+/// What the header of a compiled binary says about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BinaryInfo {
+    pub format: Format,
+    /// `e_machine` of ELF, `Machine` of PE or `cputype` of Mach-O.
+    pub machine: u32,
+    pub bits: u8,
+    pub little_endian: bool,
+    /// The OS recorded in the `EI_OSABI` byte of ELF, if any.
+    pub os: Option<&'static str>,
+    /// Shared libraries the binary is linked against.
+    pub libraries: Vec<String>,
+    /// Program interpreter (dynamic linker) of ELF.
+    pub interpreter: Option<String>,
+    /// Whether the symbol table was removed, e.g. by `-ldflags=-s`.
+    pub stripped: bool,
+}
+impl BinaryInfo {
+    pub fn is_static(&self) -> bool {
+        self.libraries.is_empty() && self.interpreter.is_none()
+    }
+}
+impl Display for BinaryInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}-bit {}-endian {}",
+            self.format,
+            self.bits,
+            if self.little_endian { "little" } else { "big" },
+            machine_name(self.format, self.machine)
+        )?;
+        if self.is_static() {
+            write!(f, ", statically linked")?;
+        } else {
+            write!(f, ", dynamically linked")?;
+            if let Some(interpreter) = &self.interpreter {
+                write!(f, " (interpreter {interpreter})")?;
+            }
+            if !self.libraries.is_empty() {
+                write!(f, " against {}", self.libraries.join(", "))?;
+            }
+        }
+        write!(
+            f,
+            ", {}",
+            if self.stripped {
+                "stripped"
+            } else {
+                "not stripped"
+            }
+        )
+    }
+}
+
+/// Name of a machine of `format`, with its number.
+fn machine_name(format: Format, machine: u32) -> String {
+    let name = match (format, machine) {
+        (Format::Elf, m) if m == EM_386 as u32 => "x86",
+        (Format::Elf, m) if m == EM_X86_64 as u32 => "x86-64",
+        (Format::Elf, m) if m == EM_ARM as u32 => "ARM",
+        (Format::Elf, m) if m == EM_AARCH64 as u32 => "AArch64",
+        (Format::Elf, m) if m == EM_LOONGARCH as u32 => "LoongArch",
+        (Format::Elf, m) if m == EM_MIPS as u32 => "MIPS",
+        (Format::Elf, m) if m == EM_PPC64 as u32 => "PowerPC64",
+        (Format::Elf, m) if m == EM_RISCV as u32 => "RISC-V",
+        (Format::Elf, m) if m == EM_S390 as u32 => "S/390",
+        (Format::Pe, m) if m == COFF_MACHINE_X86 as u32 => "x86",
+        (Format::Pe, m) if m == COFF_MACHINE_X86_64 as u32 => "x86-64",
+        (Format::Pe, m) if m == COFF_MACHINE_ARMNT as u32 => "ARM",
+        (Format::Pe, m) if m == COFF_MACHINE_ARM64 as u32 => "ARM64",
+        (Format::MachO, CPU_TYPE_X86_64) => "x86-64",
+        (Format::MachO, CPU_TYPE_ARM64) => "ARM64",
+        _ => return format!("{machine:#x}"),
+    };
+    format!("{name} ({machine:#x})")
+}
+
+/// The header fields a binary for a target must have.
+struct Expected {
+    format: Format,
+    machine: u32,
+    bits: u8,
+    little_endian: bool,
+}
+
+/// What a binary built for `target` looks like, or `None` if its format is not inspected,
+/// e.g. for plan9, js/wasm or aix.
+fn expected(target: &GoTarget) -> Option<Expected> {
+    let format = match target.goos.as_str() {
+        "windows" => Format::Pe,
+        "darwin" | "ios" => Format::MachO,
+        "linux" | "android" | "freebsd" | "netbsd" | "openbsd" | "dragonfly" | "solaris"
+        | "illumos" => Format::Elf,
+        _ => return None,
+    };
+    let (elf, pe, macho, bits, little_endian) = match target.goarch.as_str() {
+        "386" => (EM_386, COFF_MACHINE_X86, None, 32, true),
+        "amd64" => (
+            EM_X86_64,
+            COFF_MACHINE_X86_64,
+            Some(CPU_TYPE_X86_64),
+            64,
+            true,
+        ),
+        "arm" => (EM_ARM, COFF_MACHINE_ARMNT, None, 32, true),
+        "arm64" => (
+            EM_AARCH64,
+            COFF_MACHINE_ARM64,
+            Some(CPU_TYPE_ARM64),
+            64,
+            true,
+        ),
+        "loong64" => (EM_LOONGARCH, 0, None, 64, true),
+        "mips" => (EM_MIPS, 0, None, 32, false),
+        "mipsle" => (EM_MIPS, 0, None, 32, true),
+        "mips64" => (EM_MIPS, 0, None, 64, false),
+        "mips64le" => (EM_MIPS, 0, None, 64, true),
+        "ppc64" => (EM_PPC64, 0, None, 64, false),
+        "ppc64le" => (EM_PPC64, 0, None, 64, true),
+        "riscv64" => (EM_RISCV, 0, None, 64, true),
+        "s390x" => (EM_S390, 0, None, 64, false),
+        _ => return None,
+    };
+    let machine = match format {
+        Format::Elf => elf as u32,
+        Format::Pe => pe as u32,
+        Format::MachO => macho?,
+    };
+    Some(Expected {
+        format,
+        machine,
+        bits,
+        little_endian,
+    })
+}
+
+/// The OS recorded in the `EI_OSABI` byte of an ELF header, if it names one.
+fn elf_os(elf: &Elf) -> Option<&'static str> {
+    match elf.header.e_ident[EI_OSABI] {
+        2 => Some("netbsd"),
+        3 => Some("linux"),
+        6 => Some("solaris"),
+        9 => Some("freebsd"),
+        12 => Some("openbsd"),
+        _ => None,
+    }
+}
+
+/// Whether a binary whose ELF header records `os` can run on `goos`.
+/// Go only records the OS for the BSDs, and external linkers may record it for Linux.
+fn elf_os_matches(os: Option<&str>, goos: &str) -> bool {
+    match (os, goos) {
+        (None, "freebsd" | "netbsd" | "openbsd") => false,
+        (None, _) => true,
+        (Some("linux"), "android") | (Some("solaris"), "illumos") => true,
+        (Some(os), goos) => os == goos,
+    }
+}
+
+/// Read the object header of the binary at `path`.
+pub(crate) fn read_binary_info(path: &Path) -> PackResult<BinaryInfo> {
+    let data = std::fs::read(path).map_err(|_| PackError::ReadFailed(path.to_path_buf()))?;
+    let invalid = |reason: String| PackError::BinaryMismatch {
+        path: path.to_path_buf(),
+        reason,
+    };
+    match Object::parse(&data).map_err(|e| invalid(e.to_string()))? {
+        Object::Elf(elf) => Ok(BinaryInfo {
+            format: Format::Elf,
+            machine: elf.header.e_machine as u32,
+            bits: if elf.is_64 { 64 } else { 32 },
+            little_endian: elf.little_endian,
+            os: elf_os(&elf),
+            libraries: elf.libraries.iter().map(ToString::to_string).collect(),
+            interpreter: elf.interpreter.map(ToString::to_string),
+            stripped: !elf
+                .section_headers
+                .iter()
+                .any(|section| elf.shdr_strtab.get_at(section.sh_name) == Some(".symtab")),
+        }),
+        Object::PE(pe) => Ok(BinaryInfo {
+            format: Format::Pe,
+            machine: pe.header.coff_header.machine as u32,
+            bits: if pe.is_64 { 64 } else { 32 },
+            little_endian: true,
+            os: None,
+            libraries: pe.libraries.iter().map(ToString::to_string).collect(),
+            interpreter: None,
+            stripped: pe.header.coff_header.pointer_to_symbol_table == 0,
+        }),
+        Object::Mach(Mach::Binary(macho)) => Ok(BinaryInfo {
+            format: Format::MachO,
+            machine: macho.header.cputype,
+            bits: if macho.is_64 { 64 } else { 32 },
+            little_endian: macho.little_endian,
+            os: None,
+            libraries: macho
+                .libs
+                .iter()
+                .filter(|lib| **lib != "self")
+                .map(ToString::to_string)
+                .collect(),
+            interpreter: None,
+            stripped: macho
+                .symbols
+                .as_ref()
+                .is_none_or(|symbols| symbols.iter().next().is_none()),
+        }),
+        Object::Mach(Mach::Fat(_)) => Err(invalid("universal Mach-O binary".to_string())),
+        _ => Err(invalid("not an ELF, PE or Mach-O binary".to_string())),
+    }
+}
+
+/// Check that the binary at `path` was really built for `target`: object format, machine, bitness,
+/// byte order, and for ELF the OS if the header records one.
+///
+/// The header fields are returned, or `None` if binaries of the target are not inspected.
+pub(crate) fn check_binary(path: &Path, target: &GoTarget) -> PackResult<Option<BinaryInfo>> {
+    let Some(expected) = expected(target) else {
+        log::debug!("[{target}] Not inspecting {}", path.display());
+        return Ok(None);
+    };
+    let info = read_binary_info(path)?;
+    let mismatch = |what: &str, found: String, wanted: String| PackError::BinaryMismatch {
+        path: path.to_path_buf(),
+        reason: format!("{what} is {found} instead of {wanted} for {target}"),
+    };
+
+    if info.format != expected.format {
+        return Err(mismatch(
+            "format",
+            info.format.to_string(),
+            expected.format.to_string(),
+        ));
+    }
+    if info.machine != expected.machine {
+        return Err(mismatch(
+            "machine",
+            machine_name(info.format, info.machine),
+            machine_name(expected.format, expected.machine),
+        ));
+    }
+    if info.bits != expected.bits {
+        return Err(mismatch(
+            "bitness",
+            format!("{}-bit", info.bits),
+            format!("{}-bit", expected.bits),
+        ));
+    }
+    if info.little_endian != expected.little_endian {
+        let endian = |little| {
+            if little {
+                "little-endian"
+            } else {
+                "big-endian"
+            }
+        };
+        return Err(mismatch(
+            "byte order",
+            endian(info.little_endian).to_string(),
+            endian(expected.little_endian).to_string(),
+        ));
+    }
+    if info.format == Format::Elf && !elf_os_matches(info.os, &target.goos) {
+        return Err(mismatch(
+            "OS ABI",
+            info.os.unwrap_or("unspecified").to_string(),
+            target.goos.clone(),
+        ));
+    }
+    Ok(Some(info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expect(target: &str) -> Option<(Format, u32, u8, bool)> {
+        expected(&target.parse().unwrap()).map(|expected| {
+            (
+                expected.format,
+                expected.machine,
+                expected.bits,
+                expected.little_endian,
+            )
+        })
+    }
+
+    #[test]
+    fn expected_headers_of_targets() {
+        for (target, header) in [
+            ("linux/386", (Format::Elf, EM_386 as u32, 32, true)),
+            ("linux/amd64", (Format::Elf, EM_X86_64 as u32, 64, true)),
+            ("linux/arm/7", (Format::Elf, EM_ARM as u32, 32, true)),
+            ("android/arm64", (Format::Elf, EM_AARCH64 as u32, 64, true)),
+            (
+                "linux/loong64",
+                (Format::Elf, EM_LOONGARCH as u32, 64, true),
+            ),
+            ("linux/mips", (Format::Elf, EM_MIPS as u32, 32, false)),
+            ("linux/mipsle", (Format::Elf, EM_MIPS as u32, 32, true)),
+            ("linux/mips64", (Format::Elf, EM_MIPS as u32, 64, false)),
+            ("linux/mips64le", (Format::Elf, EM_MIPS as u32, 64, true)),
+            ("linux/ppc64", (Format::Elf, EM_PPC64 as u32, 64, false)),
+            ("linux/ppc64le", (Format::Elf, EM_PPC64 as u32, 64, true)),
+            ("linux/riscv64", (Format::Elf, EM_RISCV as u32, 64, true)),
+            ("linux/s390x", (Format::Elf, EM_S390 as u32, 64, false)),
+            ("freebsd/amd64", (Format::Elf, EM_X86_64 as u32, 64, true)),
+            ("openbsd/arm64", (Format::Elf, EM_AARCH64 as u32, 64, true)),
+            ("illumos/amd64", (Format::Elf, EM_X86_64 as u32, 64, true)),
+            (
+                "windows/386",
+                (Format::Pe, COFF_MACHINE_X86 as u32, 32, true),
+            ),
+            (
+                "windows/amd64",
+                (Format::Pe, COFF_MACHINE_X86_64 as u32, 64, true),
+            ),
+            (
+                "windows/arm/7",
+                (Format::Pe, COFF_MACHINE_ARMNT as u32, 32, true),
+            ),
+            (
+                "windows/arm64",
+                (Format::Pe, COFF_MACHINE_ARM64 as u32, 64, true),
+            ),
+            ("darwin/amd64", (Format::MachO, CPU_TYPE_X86_64, 64, true)),
+            ("darwin/arm64", (Format::MachO, CPU_TYPE_ARM64, 64, true)),
+        ] {
+            assert_eq!(expect(target), Some(header), "{target}");
+        }
+    }
+
+    #[test]
+    fn targets_without_inspection() {
+        for target in [
+            "js/wasm",
+            "wasip1/wasm",
+            "plan9/amd64",
+            "aix/ppc64",
+            "darwin/386",
+        ] {
+            assert_eq!(expect(target), None, "{target}");
+        }
+    }
+
+    #[test]
+    fn elf_os_of_targets() {
+        for (os, goos, matches) in [
+            (None, "linux", true),
+            (None, "android", true),
+            (None, "illumos", true),
+            (None, "dragonfly", true),
+            // Go records the OS of the BSDs, so a binary without it was not built for them
+            (None, "freebsd", false),
+            (None, "netbsd", false),
+            (None, "openbsd", false),
+            (Some("linux"), "linux", true),
+            (Some("linux"), "android", true),
+            (Some("freebsd"), "freebsd", true),
+            (Some("netbsd"), "netbsd", true),
+            (Some("openbsd"), "openbsd", true),
+            (Some("solaris"), "solaris", true),
+            (Some("solaris"), "illumos", true),
+            (Some("linux"), "freebsd", false),
+            (Some("freebsd"), "linux", false),
+            (Some("freebsd"), "openbsd", false),
+            (Some("netbsd"), "freebsd", false),
+            (Some("openbsd"), "android", false),
+        ] {
+            assert_eq!(elf_os_matches(os, goos), matches, "{os:?} on {goos}");
+        }
+    }
+
+    #[test]
+    fn machine_names() {
+        assert_eq!(machine_name(Format::Elf, EM_X86_64 as u32), "x86-64 (0x3e)");
+        assert_eq!(
+            machine_name(Format::Pe, COFF_MACHINE_ARM64 as u32),
+            "ARM64 (0xaa64)"
+        );
+        assert_eq!(
+            machine_name(Format::MachO, CPU_TYPE_ARM64),
+            "ARM64 (0x100000c)"
+        );
+        assert_eq!(machine_name(Format::Pe, EM_RISCV as u32), "0xf3");
+    }
+}
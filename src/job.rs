@@ -0,0 +1,616 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    buildinfo::BUILD_INFO_FILE_NAME,
+    cache::Cache,
+    download::{
+        HttpOptions,
+        geodat::{GeoFile, GeodataSource, Region, check_extra_names},
+        mirror::Mirror,
+        v2ray_extra::V2RAY_SERVICE_NAMES,
+        wintun::{WINTUN_FILE_NAMES, Wintun},
+    },
+    errors::{PackError, PackResult},
+    naming::Naming,
+    package::ArchiveFormat,
+    sbom::SbomFormat,
+    target::{Core, GoTarget},
+};
+
+/// Name of the Xray-core binary built for the Windows GUI subsystem.
+pub(crate) const WXRAY_BINARY_NAME: &str = "wxray.exe";
+
+/// Where the source code of the core comes from.
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// Clone the official repository into the temporary directory.
+    Clone,
+    /// Open an existing repository at the given path.
+    Path(PathBuf),
+}
+
+/// Everything needed to build and package a core for one or more targets.
+///
+/// Use [`PackJob::builder`] to create one and [`crate::Packager`] to run it.
+#[derive(Debug, Clone)]
+pub struct PackJob {
+    pub(crate) core: Core,
+    pub(crate) version: String,
+    pub(crate) targets: Vec<GoTarget>,
+    pub(crate) region: Region,
+    pub(crate) geodata_source: Option<GeodataSource>,
+    pub(crate) geoip: Option<GeoFile>,
+    pub(crate) geosite: Option<GeoFile>,
+    pub(crate) geodata: bool,
+    pub(crate) extra_geodata: Vec<(String, GeoFile)>,
+    pub(crate) wintun: Wintun,
+    pub(crate) source: Source,
+    pub(crate) output_path: PathBuf,
+    pub(crate) formats: Vec<ArchiveFormat>,
+    pub(crate) naming: Naming,
+    pub(crate) embed_build_info: bool,
+    pub(crate) sbom: Vec<SbomFormat>,
+    pub(crate) source_date_epoch: Option<i64>,
+    pub(crate) temp_dir: PathBuf,
+    pub(crate) cache: Option<Cache>,
+    pub(crate) offline: bool,
+    pub(crate) mirrors: Vec<Mirror>,
+    pub(crate) http: HttpOptions,
+    pub(crate) gcflags: String,
+    pub(crate) ldflags: Option<String>,
+    pub(crate) jobs: usize,
+    pub(crate) wxray: bool,
+    pub(crate) verbose: bool,
+    pub(crate) progress: bool,
+}
+impl PackJob {
+    pub fn builder(core: Core) -> PackJobBuilder {
+        PackJobBuilder::new(core)
+    }
+
+    pub fn core(&self) -> Core {
+        self.core
+    }
+
+    /// Tag or branch to check out.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn targets(&self) -> &[GoTarget] {
+        &self.targets
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Repository of the geoip and geosite files, replacing the one of the region if set.
+    pub fn geodata_source(&self) -> Option<&GeodataSource> {
+        self.geodata_source.as_ref()
+    }
+
+    /// `geoip.dat` to package instead of the one of the region.
+    pub fn geoip(&self) -> Option<&GeoFile> {
+        self.geoip.as_ref()
+    }
+
+    /// `geosite.dat` to package instead of the one of the region.
+    pub fn geosite(&self) -> Option<&GeoFile> {
+        self.geosite.as_ref()
+    }
+
+    /// Whether `geoip.dat` and `geosite.dat` are packaged.
+    pub fn geodata(&self) -> bool {
+        self.geodata
+    }
+
+    /// Additional geo files packaged under their own names.
+    pub fn extra_geodata(&self) -> &[(String, GeoFile)] {
+        &self.extra_geodata
+    }
+
+    /// Wintun release packaged for Windows targets of Xray-core.
+    pub fn wintun(&self) -> &Wintun {
+        &self.wintun
+    }
+
+    pub fn source(&self) -> &Source {
+        &self.source
+    }
+
+    pub fn output_path(&self) -> &Path {
+        &self.output_path
+    }
+
+    /// Archive formats of the packages. Every target is packaged once per format.
+    pub fn formats(&self) -> &[ArchiveFormat] {
+        &self.formats
+    }
+
+    pub fn naming(&self) -> &Naming {
+        &self.naming
+    }
+
+    /// Whether `build-info.json` is also packaged in the archives.
+    pub fn embed_build_info(&self) -> bool {
+        self.embed_build_info
+    }
+
+    /// Formats of the software bill of materials written next to every package.
+    pub fn sbom(&self) -> &[SbomFormat] {
+        &self.sbom
+    }
+
+    /// Timestamp of every file in the packages, in seconds since the Unix epoch.
+    /// `None` means the commit time of the source code.
+    pub fn source_date_epoch(&self) -> Option<i64> {
+        self.source_date_epoch
+    }
+
+    pub fn temp_dir(&self) -> &Path {
+        &self.temp_dir
+    }
+
+    /// Cache of downloaded files. `None` means every file is downloaded.
+    pub fn cache(&self) -> Option<&Cache> {
+        self.cache.as_ref()
+    }
+
+    /// Mirrors tried in order before the original URL of every downloaded file.
+    pub fn mirrors(&self) -> &[Mirror] {
+        &self.mirrors
+    }
+
+    /// Timeouts and retries of every download.
+    pub fn http(&self) -> &HttpOptions {
+        &self.http
+    }
+
+    /// Whether all network access is forbidden.
+    pub fn offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Returns the `-ldflags` passed to the Go compiler.
+    /// If not set explicitly, it is derived from the `git describe` result of the source code.
+    pub fn ldflags(&self, describe: &str) -> String {
+        match (&self.ldflags, self.core) {
+            (Some(ldflags), _) => ldflags.clone(),
+            (None, Core::Xray) => {
+                format!("-X github.com/xtls/xray-core/core.build={describe} -s -w -buildid=")
+            }
+            (None, Core::V2ray) => "-s -w -buildid=".to_string(),
+        }
+    }
+
+    /// Whether `wxray.exe` is built for Windows targets of Xray-core.
+    pub fn wxray(&self) -> bool {
+        self.wxray
+    }
+
+    /// Whether progress bars are shown for downloads and clones.
+    pub fn progress(&self) -> bool {
+        self.progress
+    }
+
+    /// Whether `wxray.exe` is built for the target, in addition to `xray.exe`.
+    pub(crate) fn builds_wxray(&self, target: &GoTarget) -> bool {
+        self.wxray && self.core == Core::Xray && target.is_windows()
+    }
+
+    /// Names of the files the packages contain besides the extra geo files, over all targets.
+    fn packaged_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = [
+            "README.md",
+            "LICENSE",
+            "geoip.dat",
+            "geosite.dat",
+            BUILD_INFO_FILE_NAME,
+        ]
+        .map(String::from)
+        .into();
+        if self.core == Core::V2ray {
+            names.extend(V2RAY_SERVICE_NAMES.map(String::from));
+        }
+        for target in &self.targets {
+            names.extend(self.binary_names(target));
+            if self.core == Core::Xray && target.is_windows() {
+                names.extend(WINTUN_FILE_NAMES.map(String::from));
+            }
+        }
+        names
+    }
+
+    /// Names of the binaries built for the target. They are marked executable in the packages.
+    pub(crate) fn binary_names(&self, target: &GoTarget) -> Vec<String> {
+        let mut names = vec![self.core.binary_name(target)];
+        if self.builds_wxray(target) {
+            names.push(WXRAY_BINARY_NAME.to_string());
+        }
+        names
+    }
+
+    /// Returns the temporary directory of a single target, creating it if necessary.
+    /// Target-specific files (the compiled binary, wintun.dll, the zip) are placed here.
+    pub(crate) fn target_dir(&self, target: &GoTarget) -> PackResult<PathBuf> {
+        let dir = self
+            .temp_dir
+            .join(format!("{}-{}", target.goos, target.arch()));
+        std::fs::create_dir_all(&dir).map_err(|_| PackError::CreateFailed(dir.clone()))?;
+        Ok(dir)
+    }
+}
+
+/// Builder of [`PackJob`]. Every option has the same default value as the command line.
+#[derive(Debug, Clone)]
+pub struct PackJobBuilder {
+    job: PackJob,
+}
+impl PackJobBuilder {
+    pub fn new(core: Core) -> Self {
+        PackJobBuilder {
+            job: PackJob {
+                core,
+                version: core.default_version().to_string(),
+                targets: Vec::new(),
+                region: Region::ChinaMainland,
+                geodata_source: None,
+                geoip: None,
+                geosite: None,
+                geodata: true,
+                extra_geodata: Vec::new(),
+                wintun: Wintun::default(),
+                source: Source::Clone,
+                output_path: PathBuf::from("dist"),
+                formats: Vec::new(),
+                naming: Naming::default(),
+                embed_build_info: false,
+                sbom: Vec::new(),
+                source_date_epoch: std::env::var("SOURCE_DATE_EPOCH")
+                    .ok()
+                    .and_then(|epoch| epoch.trim().parse().ok()),
+                temp_dir: std::env::temp_dir().join("xray-pack-temp"),
+                cache: Cache::default_dir().map(|dir| Cache::new(dir, Cache::DEFAULT_TTL)),
+                offline: false,
+                mirrors: Vec::new(),
+                http: HttpOptions::default(),
+                gcflags: "all:-l=4".to_string(),
+                ldflags: None,
+                jobs: 1,
+                wxray: true,
+                verbose: false,
+                progress: true,
+            },
+        }
+    }
+
+    /// Tag or branch to check out. Defaults to `main` for Xray-core and `master` for v2ray-core.
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.job.version = version.into();
+        self
+    }
+
+    /// Add a target to build. Defaults to `linux/amd64` if no target is added.
+    /// Targets without a microarchitecture level take it from the `GOAMD64`, `GOARM`, etc.
+    /// environment variables if they are set.
+    pub fn target(mut self, target: GoTarget) -> Self {
+        self.job.targets.push(target);
+        self
+    }
+
+    pub fn targets(mut self, targets: impl IntoIterator<Item = GoTarget>) -> Self {
+        self.job.targets.extend(targets);
+        self
+    }
+
+    /// Region of the geoip and geosite files.
+    pub fn region(mut self, region: Region) -> Self {
+        self.job.region = region;
+        self
+    }
+
+    /// Download the geoip and geosite files from `source` instead of the repository of the region.
+    pub fn geodata_source(mut self, source: GeodataSource) -> Self {
+        self.job.geodata_source = Some(source);
+        self
+    }
+
+    /// Take `geoip.dat` from `geoip` instead of the repository of the region.
+    pub fn geoip(mut self, geoip: GeoFile) -> Self {
+        self.job.geoip = Some(geoip);
+        self
+    }
+
+    /// Take `geosite.dat` from `geosite` instead of the repository of the region.
+    pub fn geosite(mut self, geosite: GeoFile) -> Self {
+        self.job.geosite = Some(geosite);
+        self
+    }
+
+    /// Whether to package `geoip.dat` and `geosite.dat`. Defaults to `true`.
+    pub fn geodata(mut self, geodata: bool) -> Self {
+        self.job.geodata = geodata;
+        self
+    }
+
+    /// Package an additional geo file as `name`, e.g. `geosite_IR.dat`.
+    pub fn extra_geodata(mut self, name: impl Into<String>, file: GeoFile) -> Self {
+        self.job.extra_geodata.push((name.into(), file));
+        self
+    }
+
+    /// Wintun release packaged for Windows targets of Xray-core.
+    /// Defaults to [`crate::DEFAULT_WINTUN_VERSION`], downloaded and verified against its known checksum.
+    pub fn wintun(mut self, wintun: Wintun) -> Self {
+        self.job.wintun = wintun;
+        self
+    }
+
+    pub fn source(mut self, source: Source) -> Self {
+        self.job.source = source;
+        self
+    }
+
+    /// Directory where the packages are copied to.
+    pub fn output_path(mut self, output_path: impl Into<PathBuf>) -> Self {
+        self.job.output_path = output_path.into();
+        self
+    }
+
+    /// Add an archive format of the packages. Defaults to zip if no format is added.
+    pub fn format(mut self, format: ArchiveFormat) -> Self {
+        self.job.formats.push(format);
+        self
+    }
+
+    pub fn formats(mut self, formats: impl IntoIterator<Item = ArchiveFormat>) -> Self {
+        self.job.formats.extend(formats);
+        self
+    }
+
+    /// How the packages are named. Defaults to [`Naming::Descriptive`].
+    pub fn naming(mut self, naming: Naming) -> Self {
+        self.job.naming = naming;
+        self
+    }
+
+    /// Also package the `build-info.json` of every target in its archives.
+    /// It is always written next to the archives in the output directory.
+    pub fn embed_build_info(mut self, embed_build_info: bool) -> Self {
+        self.job.embed_build_info = embed_build_info;
+        self
+    }
+
+    /// Add a format of the software bill of materials written next to every package.
+    /// None is written if no format is added.
+    pub fn sbom(mut self, format: SbomFormat) -> Self {
+        if !self.job.sbom.contains(&format) {
+            self.job.sbom.push(format);
+        }
+        self
+    }
+
+    pub fn sboms(mut self, formats: impl IntoIterator<Item = SbomFormat>) -> Self {
+        for format in formats {
+            self = self.sbom(format);
+        }
+        self
+    }
+
+    /// Pin the timestamp of every file in the packages, in seconds since the Unix epoch.
+    /// Defaults to `SOURCE_DATE_EPOCH` if set, otherwise the commit time of the source code.
+    pub fn source_date_epoch(mut self, source_date_epoch: i64) -> Self {
+        self.job.source_date_epoch = Some(source_date_epoch);
+        self
+    }
+
+    /// Working directory for clones, downloads and intermediate files.
+    /// It is removed and recreated when the job starts.
+    pub fn temp_dir(mut self, temp_dir: impl Into<PathBuf>) -> Self {
+        self.job.temp_dir = temp_dir.into();
+        self
+    }
+
+    /// Cache of downloaded files, or `None` to download every file.
+    /// Defaults to [`Cache::default_dir`] with [`Cache::DEFAULT_TTL`].
+    pub fn cache(mut self, cache: Option<Cache>) -> Self {
+        self.job.cache = cache;
+        self
+    }
+
+    /// Add a mirror to try before the original URL of every downloaded file.
+    /// Mirrors are tried in the order they are added.
+    pub fn mirror(mut self, mirror: Mirror) -> Self {
+        self.job.mirrors.push(mirror);
+        self
+    }
+
+    pub fn mirrors(mut self, mirrors: impl IntoIterator<Item = Mirror>) -> Self {
+        self.job.mirrors.extend(mirrors);
+        self
+    }
+
+    /// Timeouts and retries of every download.
+    pub fn http(mut self, http: HttpOptions) -> Self {
+        self.job.http = http;
+        self
+    }
+
+    /// Forbid all network access. The source code must come from [`Source::Path`], downloaded files
+    /// from the cache regardless of their age, and Go modules from the `vendor` directory or the module cache.
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.job.offline = offline;
+        self
+    }
+
+    pub fn gcflags(mut self, gcflags: impl Into<String>) -> Self {
+        self.job.gcflags = gcflags.into();
+        self
+    }
+
+    pub fn ldflags(mut self, ldflags: impl Into<String>) -> Self {
+        self.job.ldflags = Some(ldflags.into());
+        self
+    }
+
+    /// Maximum number of `go build` processes to run concurrently.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.job.jobs = jobs.max(1);
+        self
+    }
+
+    /// Also build `wxray.exe`, Xray-core linked with `-H windowsgui` so that it runs without a console window,
+    /// for Windows targets of Xray-core. Defaults to `true`, like the official releases.
+    pub fn wxray(mut self, wxray: bool) -> Self {
+        self.job.wxray = wxray;
+        self
+    }
+
+    /// Pass `-v` to `go build`.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.job.verbose = verbose;
+        self
+    }
+
+    /// Show progress bars for downloads and clones on stderr. Defaults to `true`.
+    /// They are never shown when stderr is not a terminal.
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.job.progress = progress;
+        self
+    }
+
+    /// Finish the job. Fails if an extra geo file has an invalid name or the name of another file of the packages,
+    /// or if the repository must be cloned through a proxy git does not support.
+    pub fn build(self) -> PackResult<PackJob> {
+        let mut job = self.job;
+        let mut targets: Vec<GoTarget> = Vec::with_capacity(job.targets.len());
+        for mut target in job.targets {
+            if target.variant.is_none()
+                && let Some(var) = target.variant_var()
+                && let Ok(variant) = std::env::var(var)
+                && !variant.is_empty()
+            {
+                match GoTarget::check_variant(&target.goarch, &variant) {
+                    Ok(()) => target.variant = Some(variant),
+                    Err(e) => log::warn!("Ignoring environment variable {var}: {e}"),
+                }
+            }
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+        if targets.is_empty() {
+            targets.push(GoTarget::default());
+        }
+        job.targets = targets;
+
+        let mut formats: Vec<ArchiveFormat> = Vec::with_capacity(job.formats.len());
+        for format in job.formats {
+            if !formats.contains(&format) {
+                formats.push(format);
+            }
+        }
+        if formats.is_empty() {
+            formats.push(ArchiveFormat::Zip);
+        }
+        job.formats = formats;
+
+        check_extra_names(&job.extra_geodata, &job.packaged_names())
+            .map_err(PackError::ConfigError)?;
+        if matches!(job.source, Source::Clone) && !job.offline {
+            job.http
+                .proxy
+                .check_git_url(job.core.repo_url())
+                .map_err(PackError::ConfigError)?;
+        }
+        Ok(job)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{download::geodat::GeoSource, proxy::Proxy};
+
+    fn build_with_extra(core: Core, target: &str, name: &str) -> PackResult<PackJob> {
+        PackJob::builder(core)
+            .target(target.parse().unwrap())
+            .extra_geodata(
+                name,
+                GeoFile::new(GeoSource::Path(PathBuf::from("extra.dat"))),
+            )
+            .build()
+    }
+
+    #[test]
+    fn accepts_extra_geodata_with_new_names() {
+        assert!(build_with_extra(Core::Xray, "windows/amd64", "geosite_IR.dat").is_ok());
+        // Only Windows packages of Xray-core contain wintun
+        assert!(build_with_extra(Core::Xray, "linux/amd64", "wintun.dll").is_ok());
+    }
+
+    #[test]
+    fn rejects_extra_geodata_replacing_packaged_files() {
+        for (core, target, name) in [
+            (Core::Xray, "linux/amd64", "geoip.dat"),
+            (Core::Xray, "linux/amd64", "README.md"),
+            (Core::Xray, "linux/amd64", "LICENSE"),
+            (Core::Xray, "linux/amd64", "xray"),
+            (Core::Xray, "linux/amd64", "build-info.json"),
+            (Core::Xray, "windows/amd64", "xray.exe"),
+            (Core::Xray, "windows/amd64", "wxray.exe"),
+            (Core::Xray, "windows/arm64", "wintun.dll"),
+            (Core::Xray, "windows/arm64", "LICENSE-wintun.txt"),
+            (Core::V2ray, "linux/amd64", "v2ray.service"),
+            (Core::V2ray, "linux/amd64", "v2ray@.service"),
+            (Core::Xray, "linux/amd64", "../geoip.dat"),
+            (Core::Xray, "linux/amd64", ".hidden"),
+            (Core::Xray, "linux/amd64", ""),
+        ] {
+            assert!(
+                matches!(
+                    build_with_extra(core, target, name),
+                    Err(PackError::ConfigError(_))
+                ),
+                "{core} {target} {name}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_duplicate_extra_geodata() {
+        let file = GeoFile::new(GeoSource::Path(PathBuf::from("extra.dat")));
+        let result = PackJob::builder(Core::Xray)
+            .extra_geodata("geosite_IR.dat", file.clone())
+            .extra_geodata("geosite_IR.dat", file)
+            .build();
+        assert!(matches!(result, Err(PackError::ConfigError(_))));
+    }
+
+    #[test]
+    fn rejects_socks_proxies_for_the_clone() {
+        let build = |proxy: &str, no_proxy: &[&str], source: Source| {
+            let proxy = Proxy::default()
+                .with_url(proxy)
+                .unwrap()
+                .with_no_proxy(no_proxy.iter().map(ToString::to_string).collect());
+            PackJob::builder(Core::Xray)
+                .source(source)
+                .http(HttpOptions {
+                    proxy,
+                    ..HttpOptions::default()
+                })
+                .build()
+        };
+        for proxy in ["socks5://127.0.0.1:1080", "socks5h://127.0.0.1:1080"] {
+            assert!(matches!(
+                build(proxy, &[], Source::Clone),
+                Err(PackError::ConfigError(_))
+            ));
+            assert!(build(proxy, &["github.com"], Source::Clone).is_ok());
+            assert!(build(proxy, &[], Source::Path(PathBuf::from("Xray-core"))).is_ok());
+        }
+        assert!(build("http://127.0.0.1:8080", &[], Source::Clone).is_ok());
+    }
+}
@@ -0,0 +1,52 @@
+//! Build and package Xray-core and v2ray-core.
+//!
+//! ```no_run
+//! use xray_pack::{Core, GoTarget, PackJob, Packager, Region};
+//!
+//! let job = PackJob::builder(Core::Xray)
+//!     .version("main")
+//!     .target(GoTarget::new("linux", "amd64"))
+//!     .target(GoTarget::new("windows", "amd64"))
+//!     .region(Region::ChinaMainland)
+//!     .output_path("dist")
+//!     .build()?;
+//! let output = Packager::new(job).run()?;
+//! for artifact in &output.artifacts {
+//!     println!("{} {}", artifact.sha256, artifact.path.display());
+//! }
+//! # Ok::<(), xray_pack::PackError>(())
+//! ```
+
+mod buildinfo;
+mod cache;
+mod checksum;
+mod compile;
+mod download;
+mod errors;
+mod inspect;
+mod job;
+mod naming;
+mod package;
+mod packager;
+mod progress;
+mod proxy;
+mod repo;
+mod sbom;
+mod target;
+mod toolchain;
+
+pub use cache::{Cache, CacheEntry};
+pub use download::geodat::{GeoFile, GeoSource, GeodataSource, Region};
+pub use download::wintun::{
+    DEFAULT_WINTUN_VERSION, WINTUN_SIGNER_THUMBPRINTS, Wintun, known_wintun_sha256,
+};
+pub use download::{HttpOptions, mirror::Mirror};
+pub use errors::{PackError, PackResult};
+pub use job::{PackJob, PackJobBuilder, Source};
+pub use naming::Naming;
+pub use package::ArchiveFormat;
+pub use packager::{Artifact, PackOutput, Packager};
+pub use progress::ProgressLogger;
+pub use proxy::Proxy;
+pub use sbom::SbomFormat;
+pub use target::{Core, GoTarget};
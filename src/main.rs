@@ -1,108 +1,94 @@
-use std::sync::{Mutex, OnceLock};
-use std::{path::PathBuf, sync::LazyLock};
+use clap::{CommandFactory, FromArgMatches};
 
-use clap::Parser;
+use xray_pack::{Cache, PackError, PackResult, Packager, ProgressLogger};
 
-use crate::cli::CompileTarget;
-use crate::download::geodat::download_geodat;
-use crate::download::v2ray_extra::copy_v2ray_services;
-use crate::download::wintun::{WinPlatform, download_and_extract_wintun};
-use crate::errors::{PackError, PackResult};
-use crate::package::package_all;
+use crate::cli::{CacheCommand, Command, ConfigCommand};
+use crate::config::Settings;
 
 mod cli;
-mod compile;
-mod download;
-mod errors;
-mod package;
-mod repo;
-
-static TEMP_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
-    let temp_dir = std::env::temp_dir().join("xray-pack-temp");
-    if temp_dir.exists() {
-        log::debug!(
-            "Removing existing temporary directory at {}",
-            temp_dir.display()
-        );
-        std::fs::remove_dir_all(&temp_dir)
-            .map_err(|_| PackError::DeleteFailed(temp_dir.clone()))
-            .unwrap();
-    }
-    log::debug!("Creating temporary directory at {}", temp_dir.display());
-    std::fs::create_dir(&temp_dir).unwrap_or_else(|_| {
-        panic!(
-            "Failed to create temporary directory at {}",
-            temp_dir.display()
-        )
-    });
-    temp_dir
-});
-
-static REPOSITORY_DIR: OnceLock<PathBuf> = OnceLock::new();
-
-static ARGS: OnceLock<cli::Args> = OnceLock::new();
-
-/// A global collection of file paths that have been downloaded or compiled.
-/// These files will be packaged together at the end.
-pub static COLLECTED_FILES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
-
-// check prerequisites
-fn check_prerequisites() -> PackResult<()> {
-    // Currently only Go compiler is required.
-    let prerequisites = vec!["go"];
-
-    // check if prerequisites are in PATH
-    for prerequisite in prerequisites {
-        if which::which(prerequisite).is_err() {
-            return Err(PackError::MissingDependency(prerequisite.to_string()));
-        }
-    }
-
-    Ok(())
-}
+mod config;
 
 fn main() -> PackResult<()> {
-    let args = ARGS.get_or_init(cli::Args::parse);
+    let matches = cli::Args::command().get_matches();
+    let mut args = cli::Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
 
     // Initialize logging.
-    // If `RUST_LOG` is not set, set it to `debug` if verbose is true, otherwise `info`,
-    match (std::env::var("RUST_LOG"), args.verbose) {
-        (Err(_), true) => unsafe { std::env::set_var("RUST_LOG", "debug") },
-        (Err(_), false) => unsafe { std::env::set_var("RUST_LOG", "info") },
+    // If `RUST_LOG` is not set, set it to `debug` if verbose is true, `warn` if quiet is true, otherwise `info`,
+    match (std::env::var("RUST_LOG"), args.verbose, args.quiet) {
+        (Err(_), true, _) => unsafe { std::env::set_var("RUST_LOG", "debug") },
+        (Err(_), _, true) => unsafe { std::env::set_var("RUST_LOG", "warn") },
+        (Err(_), false, false) => unsafe { std::env::set_var("RUST_LOG", "info") },
         _ => {}
     };
 
-    env_logger::init();
+    // Log lines are written around the progress bars of downloads and clones
+    let logger = env_logger::Builder::from_default_env().build();
+    log::set_max_level(logger.filter());
+    log::set_boxed_logger(Box::new(ProgressLogger::new(logger)))
+        .expect("the logger is only initialized once");
 
-    // check prerequisites
-    check_prerequisites()?;
+    // Options not given on the command line are taken from the configuration files
+    let settings = config::load(&args)?;
+    settings.apply(&mut args, &matches)?;
 
-    let commid = repo::setup_repository()?;
+    if let Command::Config { command } = &args.command {
+        match command {
+            ConfigCommand::Show => print!("{}", Settings::effective(&args, &settings).to_toml()?),
+        }
+        return Ok(());
+    }
+    if let Command::Cache { command } = &args.command {
+        let cache = args.download_options.cache().ok_or_else(|| {
+            PackError::ConfigError("cannot find the cache directory, use --cache-dir".to_string())
+        })?;
+        return run_cache_command(command, &cache);
+    }
 
-    // Build Xray-core or v2ray-core
-    compile::build_xray(&commid)?;
+    let output = Packager::new(args.to_job()?).run()?;
+    for artifact in &output.artifacts {
+        log::info!(
+            "{}: {} (sha256 {})",
+            artifact.target,
+            artifact.path.display(),
+            artifact.sha256
+        );
+    }
+    if let Some(sha256sums) = &output.sha256sums {
+        log::info!("Checksums: {}", sha256sums.display());
+    }
 
-    download_geodat(args.download_options.region)?;
+    Ok(())
+}
 
-    // Download target-specific files
-    match &args.target {
-        CompileTarget::Xray { .. } => {
-            if args.go_target.goos.to_lowercase() == "windows" {
-                download_and_extract_wintun(WinPlatform::from(args.go_target.goarch.as_str()))?;
+fn run_cache_command(command: &CacheCommand, cache: &Cache) -> PackResult<()> {
+    match command {
+        CacheCommand::List => {
+            let entries = cache.entries()?;
+            if entries.is_empty() {
+                println!("The cache at {} is empty", cache.dir().display());
+            }
+            for entry in entries {
+                println!(
+                    "{}  {:>10}  {:>4}h{}  {}",
+                    entry.sha256.get(..16).unwrap_or(&entry.sha256),
+                    entry.size,
+                    entry.age().as_secs() / 3600,
+                    if cache.is_fresh(&entry) { " " } else { "*" },
+                    entry.url
+                );
+            }
+        }
+        CacheCommand::Prune => {
+            let removed = cache.prune()?;
+            for entry in &removed {
+                log::info!("Removed {}", entry.url);
             }
+            log::info!("Removed {} expired files from the cache", removed.len());
         }
-        CompileTarget::V2ray { .. } => {
-            copy_v2ray_services()?;
+        CacheCommand::Clear => {
+            cache.clear()?;
+            log::info!("Cleared the cache at {}", cache.dir().display());
         }
     }
-
-    package_all()?;
-
-    // Clean
-    // log::debug!("Cleaning temporary directory at {}", TEMP_DIR.display());
-    // std::fs::remove_dir_all(TEMP_DIR.deref()).expect(&format!(
-    //     "Failed to remove temporary directory at {}",
-    //     TEMP_DIR.display()
-    // ));
     Ok(())
 }
@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    errors::{PackError, PackResult},
+    job::PackJob,
+    repo::Checkout,
+    target::{Core, GoTarget},
+};
+
+/// How the packages are named. The archive extension is appended to the name.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Naming {
+    /// Asset names of the official releases, e.g. `Xray-linux-64` or `v2ray-linux-arm32-v7a`,
+    /// as expected by `install-release.sh` and other updaters.
+    /// Only the ARM level and MIPS softfloat are part of these names, other microarchitecture levels are not.
+    Official,
+    /// `{core}-{version}-{arch}-{goos}`, e.g. `xray-main-amd64-linux` or `xray-main-amd64-v3-linux`.
+    #[default]
+    Descriptive,
+    /// A format string with `{target}`, `{version}`, `{describe}`, `{goos}`, `{goarch}` and `{arch}` placeholders.
+    /// `{target}` is the target part of the official name, e.g. `linux-64`,
+    /// and `{arch}` is `goarch` with the microarchitecture level, e.g. `amd64-v3`.
+    /// Use `{{` and `}}` for literal braces.
+    Template(String),
+}
+
+/// `friendly-filenames.json` of Xray-core and v2ray-core. Keys are `{GOOS}-{GOARCH}{GOARM}{GOMIPS}`,
+/// where `GOMIPS` only appears if it is `softfloat`.
+const FRIENDLY_NAMES: &[(&str, &str)] = &[
+    ("android-arm64", "android-arm64-v8a"),
+    ("darwin-amd64", "macos-64"),
+    ("darwin-arm64", "macos-arm64-v8a"),
+    ("freebsd-386", "freebsd-32"),
+    ("freebsd-amd64", "freebsd-64"),
+    ("freebsd-arm64", "freebsd-arm64-v8a"),
+    ("freebsd-arm7", "freebsd-arm32-v7a"),
+    ("linux-386", "linux-32"),
+    ("linux-amd64", "linux-64"),
+    ("linux-arm5", "linux-arm32-v5"),
+    ("linux-arm64", "linux-arm64-v8a"),
+    ("linux-arm6", "linux-arm32-v6"),
+    ("linux-arm7", "linux-arm32-v7a"),
+    ("linux-loong64", "linux-loong64"),
+    ("linux-mips", "linux-mips32"),
+    ("linux-mips64", "linux-mips64"),
+    ("linux-mips64le", "linux-mips64le"),
+    ("linux-mipsle", "linux-mips32le"),
+    ("linux-mipslesoftfloat", "linux-mips32le-softfloat"),
+    ("linux-mipssoftfloat", "linux-mips32-softfloat"),
+    ("linux-ppc64", "linux-ppc64"),
+    ("linux-ppc64le", "linux-ppc64le"),
+    ("linux-riscv64", "linux-riscv64"),
+    ("linux-s390x", "linux-s390x"),
+    ("openbsd-386", "openbsd-32"),
+    ("openbsd-amd64", "openbsd-64"),
+    ("openbsd-arm64", "openbsd-arm64-v8a"),
+    ("openbsd-arm7", "openbsd-arm32-v7a"),
+    ("windows-386", "windows-32"),
+    ("windows-amd64", "windows-64"),
+    ("windows-arm64", "windows-arm64-v8a"),
+    ("windows-arm7", "windows-arm32-v7a"),
+];
+
+/// Locations of `friendly-filenames.json` in the Xray-core and v2ray-core repositories.
+const FRIENDLY_NAMES_FILES: &[&str] = &[
+    ".github/build/friendly-filenames.json",
+    "release/friendly-filenames.json",
+];
+
+/// Key of the target in [`FRIENDLY_NAMES`].
+/// `GOARM` defaults to 7, like the Go toolchain does when cross compiling.
+fn friendly_key(target: &GoTarget) -> String {
+    let mut key = format!("{}-{}", target.goos, target.goarch);
+    let variant = target.variant.as_deref().unwrap_or_default();
+    // `7,softfloat` and the like only count with their level
+    let level = variant.split(',').next().unwrap_or_default();
+    match target.goarch.as_str() {
+        "arm" if level.is_empty() => key.push('7'),
+        "arm" => key.push_str(level),
+        "mips" | "mipsle" if level == "softfloat" => key.push_str("softfloat"),
+        _ => {}
+    }
+    key
+}
+
+/// Target part of the official release name, e.g. `linux-arm32-v7a`.
+/// Targets without an official release fall back to `{goos}-{goarch}`.
+pub(crate) fn friendly_name(target: &GoTarget) -> String {
+    let key = friendly_key(target);
+    match FRIENDLY_NAMES.iter().find(|(k, _)| *k == key) {
+        Some((_, name)) => name.to_string(),
+        None => format!("{}-{}", target.goos, target.goarch),
+    }
+}
+
+/// Warn about targets that the checked out version does not release,
+/// according to the `friendly-filenames.json` of its repository.
+/// Nothing is checked if the repository has no such file.
+pub(crate) fn warn_unreleased_targets(job: &PackJob, checkout: &Checkout) {
+    let Some(path) = FRIENDLY_NAMES_FILES
+        .iter()
+        .map(|file| checkout.dir.join(file))
+        .find(|path| path.is_file())
+    else {
+        log::debug!("No friendly-filenames.json in the repository, skip checking targets");
+        return;
+    };
+    let released: BTreeMap<String, serde_json::Value> = match std::fs::read(&path)
+        .ok()
+        .and_then(|content| serde_json::from_slice(&content).ok())
+    {
+        Some(released) => released,
+        None => {
+            log::warn!("Failed to read {}, skip checking targets", path.display());
+            return;
+        }
+    };
+
+    for target in &job.targets {
+        if !released.contains_key(&friendly_key(target)) {
+            log::warn!(
+                "{target} is not released by {} {}, it may fail to build or run",
+                job.core,
+                job.version
+            );
+        }
+    }
+}
+
+/// Replace the placeholders of a template. `{{` and `}}` are literal braces.
+fn render(template: &str, value: impl Fn(&str) -> Option<String>) -> PackResult<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        rendered.push_str(&rest[..start]);
+        let brace = &rest[start..];
+        if brace.starts_with("{{") || brace.starts_with("}}") {
+            rendered.push_str(&brace[..1]);
+            rest = &brace[2..];
+        } else if brace.starts_with('}') {
+            return Err(PackError::InvalidPackageName(format!(
+                "unmatched `}}` in `{template}`"
+            )));
+        } else {
+            let end = brace.find('}').ok_or_else(|| {
+                PackError::InvalidPackageName(format!("unclosed `{{` in `{template}`"))
+            })?;
+            let placeholder = &brace[1..end];
+            rendered.push_str(&value(placeholder).ok_or_else(|| {
+                PackError::InvalidPackageName(format!(
+                    "unknown placeholder `{{{placeholder}}}` in `{template}`. \
+                     Available placeholders: {{target}}, {{version}}, {{describe}}, {{goos}}, {{goarch}}, {{arch}}"
+                ))
+            })?);
+            rest = &brace[end + 1..];
+        }
+    }
+    rendered.push_str(rest);
+
+    if rendered.is_empty() || rendered.contains(['/', '\\']) {
+        return Err(PackError::InvalidPackageName(format!(
+            "`{template}` does not give a valid file name: `{rendered}`"
+        )));
+    }
+    Ok(rendered)
+}
+
+impl Naming {
+    /// Name of the package of a target, without extension.
+    pub fn package_name(
+        &self,
+        core: Core,
+        version: &str,
+        describe: &str,
+        target: &GoTarget,
+    ) -> PackResult<String> {
+        match self {
+            Naming::Official => {
+                let prefix = match core {
+                    Core::Xray => "Xray",
+                    Core::V2ray => "v2ray",
+                };
+                Ok(format!("{prefix}-{}", friendly_name(target)))
+            }
+            Naming::Descriptive => Ok(format!(
+                "{}-{}-{}-{}",
+                core.name(),
+                version,
+                target.arch(),
+                target.goos
+            )),
+            Naming::Template(template) => render(template, |placeholder| match placeholder {
+                "target" => Some(friendly_name(target)),
+                "version" => Some(version.to_string()),
+                "describe" => Some(describe.to_string()),
+                "goos" => Some(target.goos.clone()),
+                "goarch" => Some(target.goarch.clone()),
+                "arch" => Some(target.arch()),
+                _ => None,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::package::package_names;
+
+    fn target(s: &str) -> GoTarget {
+        s.parse().unwrap()
+    }
+
+    fn name(naming: Naming, core: Core, target_str: &str) -> PackResult<String> {
+        naming.package_name(core, "v1.2.3", "v1.2.3-4-gabcdef0", &target(target_str))
+    }
+
+    #[test]
+    fn friendly_names() {
+        for (target_str, expected) in [
+            ("linux/amd64", "linux-64"),
+            ("linux/amd64/v3", "linux-64"),
+            ("linux/arm", "linux-arm32-v7a"),
+            ("linux/arm/6", "linux-arm32-v6"),
+            ("linux/arm/7,softfloat", "linux-arm32-v7a"),
+            ("linux/mips/softfloat", "linux-mips32-softfloat"),
+            ("linux/mipsle/hardfloat", "linux-mips32le"),
+            ("darwin/arm64", "macos-arm64-v8a"),
+            ("windows/386", "windows-32"),
+            ("plan9/amd64", "plan9-amd64"),
+        ] {
+            assert_eq!(friendly_name(&target(target_str)), expected, "{target_str}");
+        }
+    }
+
+    #[test]
+    fn package_names_of_every_scheme() {
+        for (naming, core, target_str, expected) in [
+            (Naming::Official, Core::Xray, "linux/amd64", "Xray-linux-64"),
+            (
+                Naming::Official,
+                Core::V2ray,
+                "linux/arm/5",
+                "v2ray-linux-arm32-v5",
+            ),
+            (
+                Naming::Descriptive,
+                Core::Xray,
+                "linux/amd64/v3",
+                "xray-v1.2.3-amd64-v3-linux",
+            ),
+            (
+                Naming::Descriptive,
+                Core::V2ray,
+                "windows/arm64",
+                "v2ray-v1.2.3-arm64-windows",
+            ),
+            (
+                Naming::Template("Xray-{version}-{target}".to_string()),
+                Core::Xray,
+                "linux/arm64",
+                "Xray-v1.2.3-linux-arm64-v8a",
+            ),
+            (
+                Naming::Template("{goos}_{goarch}_{arch}_{describe}".to_string()),
+                Core::Xray,
+                "linux/amd64/v2",
+                "linux_amd64_amd64-v2_v1.2.3-4-gabcdef0",
+            ),
+            (
+                Naming::Template("{{{target}}}".to_string()),
+                Core::Xray,
+                "linux/amd64",
+                "{linux-64}",
+            ),
+        ] {
+            assert_eq!(name(naming, core, target_str).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn invalid_templates() {
+        for template in ["{unknown}", "{target", "target}", "{goos}/{goarch}", ""] {
+            let naming = Naming::Template(template.to_string());
+            assert!(
+                matches!(
+                    name(naming, Core::Xray, "linux/amd64"),
+                    Err(PackError::InvalidPackageName(_))
+                ),
+                "{template}"
+            );
+        }
+    }
+
+    #[test]
+    fn official_names_collide_across_amd64_levels() {
+        let job = PackJob::builder(Core::Xray)
+            .target(target("linux/amd64/v1"))
+            .target(target("linux/amd64/v3"))
+            .naming(Naming::Official)
+            .build()
+            .unwrap();
+        let checkout = Checkout {
+            dir: PathBuf::new(),
+            describe: "v1.2.3".to_string(),
+            commit_time: 0,
+        };
+        assert!(matches!(
+            package_names(&job, &checkout),
+            Err(PackError::InvalidPackageName(_))
+        ));
+
+        let job = PackJob::builder(Core::Xray)
+            .target(target("linux/amd64/v1"))
+            .target(target("linux/amd64/v3"))
+            .build()
+            .unwrap();
+        assert_eq!(
+            package_names(&job, &checkout).unwrap(),
+            ["xray-main-amd64-v1-linux", "xray-main-amd64-v3-linux"]
+        );
+    }
+}
@@ -1,150 +1,420 @@
-use std::path::PathBuf;
+use std::{
+    fmt::Display,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    ARGS, COLLECTED_FILES, REPOSITORY_DIR, TEMP_DIR, cli::CompileTarget, errors::PackResult,
+    errors::{PackError, PackResult},
+    job::PackJob,
+    repo::Checkout,
+    target::GoTarget,
 };
 
-/// Copy all necessary files to a directory. The path of this directory is returned.
-#[deprecated(note = "Use compress_zip instead.")]
-#[allow(dead_code)]
-fn copy_to_dir() -> PackResult<PathBuf> {
-    let args = ARGS.get().unwrap();
-    let repo_dir = REPOSITORY_DIR.get().unwrap();
-
-    let dir_name = match &args.target {
-        CompileTarget::V2ray {
-            compile_options: _,
-            v2ray_version,
-        } => {
-            format! {
-                "v2ray-{}-{}-{}",
-                v2ray_version, args.go_target.goarch, args.go_target.goos,
-            }
-        }
-        CompileTarget::Xray {
-            compile_options: _,
-            xray_version,
-        } => {
-            format!(
-                "xray-{}-{}-{}",
-                xray_version, args.go_target.goarch, args.go_target.goos
-            )
+/// Name of the package without extension, e.g. `xray-main-amd64-linux`.
+fn package_name(job: &PackJob, checkout: &Checkout, target: &GoTarget) -> PackResult<String> {
+    job.naming
+        .package_name(job.core, &job.version, &checkout.describe, target)
+}
+
+/// Names of the packages of all targets, in the order of [`PackJob::targets`].
+/// Fails if the naming scheme gives several targets the same name.
+pub(crate) fn package_names(job: &PackJob, checkout: &Checkout) -> PackResult<Vec<String>> {
+    let mut names: Vec<String> = Vec::with_capacity(job.targets.len());
+    for target in &job.targets {
+        let name = package_name(job, checkout, target)?;
+        if names.contains(&name) {
+            return Err(PackError::InvalidPackageName(format!(
+                "several targets are packaged as `{name}`, use a naming scheme that tells them apart"
+            )));
         }
-    };
-    let dir = TEMP_DIR.join(dir_name);
-    std::fs::create_dir(&dir)?;
-    log::debug!(
-        "Created directory: {}. All files will be copied to it.",
-        dir.display()
-    );
+        names.push(name);
+    }
+    Ok(names)
+}
 
-    let mut files = Vec::new();
-    files.push(repo_dir.join({
-        let mut s: String;
-        match args.target {
-            CompileTarget::V2ray {
-                compile_options: _,
-                v2ray_version: _,
-            } => s = String::from("v2ray"),
-            CompileTarget::Xray {
-                compile_options: _,
-                xray_version: _,
-            } => s = String::from("xray"),
-        };
-        if args.go_target.goos == "windows" {
-            s.push_str(".exe");
+/// Archive formats of the package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum ArchiveFormat {
+    #[value(name = "zip")]
+    #[serde(rename = "zip")]
+    Zip,
+    #[value(name = "tar.gz")]
+    #[serde(rename = "tar.gz")]
+    TarGz,
+    #[value(name = "tar.xz")]
+    #[serde(rename = "tar.xz")]
+    TarXz,
+    #[value(name = "tar.zst")]
+    #[serde(rename = "tar.zst")]
+    TarZst,
+}
+impl ArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::TarXz => "tar.xz",
+            ArchiveFormat::TarZst => "tar.zst",
         }
-        s
-    }));
-    files.push(repo_dir.join("README.md"));
-    files.push(repo_dir.join("LICENSE"));
-    files.push(TEMP_DIR.join("geoip.dat"));
-    files.push(TEMP_DIR.join("geosite.dat"));
-    if args.go_target.goos == "windows" {
-        files.push(TEMP_DIR.join("wintun.dll"));
-        files.push(TEMP_DIR.join("LICENSE-wintun.txt"));
     }
+}
+impl Display for ArchiveFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
 
-    log::debug!("Files that will be packaged: {files:#?}");
-    for ref file in files {
-        let dest = dir.join(file.file_name().unwrap());
-        log::debug!("Copying {} to {}", file.display(), dest.display());
-        std::fs::copy(file, &dest)?;
+/// A file to be packaged.
+pub(crate) struct PackageFile {
+    pub path: PathBuf,
+    /// Name of the file inside the archive.
+    pub name: String,
+    pub executable: bool,
+}
+impl PackageFile {
+    /// Unix permissions of the file inside the archive.
+    fn mode(&self) -> u32 {
+        if self.executable { 0o755 } else { 0o644 }
     }
-    Ok(dir)
 }
 
-fn compress_zip() -> PackResult<PathBuf> {
-    let args = ARGS.get().unwrap();
-    let repo_dir = REPOSITORY_DIR.get().unwrap();
-    let name = match &args.target {
-        CompileTarget::V2ray {
-            compile_options: _,
-            v2ray_version,
-        } => {
-            format! {
-                "v2ray-{}-{}-{}.zip",
-                v2ray_version, args.go_target.goarch, args.go_target.goos,
-            }
-        }
-        CompileTarget::Xray {
-            compile_options: _,
-            xray_version,
-        } => {
-            format!(
-                "xray-{}-{}-{}.zip",
-                xray_version, args.go_target.goarch, args.go_target.goos
-            )
-        }
-    };
+/// Get target files and shared files, and add README.md and LICENSE from repo.
+/// Files are sorted by name so that the order of entries in the archives is stable.
+pub(crate) fn collect_files(
+    job: &PackJob,
+    checkout: &Checkout,
+    target: &GoTarget,
+    shared_files: &[PathBuf],
+    target_files: Vec<PathBuf>,
+) -> PackResult<Vec<PackageFile>> {
+    let binary_names = job.binary_names(target);
+
+    let mut files = target_files;
+    files.extend_from_slice(shared_files);
+    files.push(checkout.dir.join("README.md"));
+    files.push(checkout.dir.join("LICENSE"));
+
+    let mut files: Vec<PackageFile> = files
+        .into_iter()
+        .map(|path| {
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                return Err(PackError::InvalidPackageName(format!(
+                    "cannot package {}, its file name is not valid UTF-8",
+                    path.display()
+                )));
+            };
+            let name = name.to_string();
+            Ok(PackageFile {
+                executable: binary_names.contains(&name),
+                path,
+                name,
+            })
+        })
+        .collect::<PackResult<_>>()?;
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    if let Some(pair) = files.windows(2).find(|pair| pair[0].name == pair[1].name) {
+        return Err(PackError::InvalidPackageName(format!(
+            "{} and {} would both be packaged as `{}`",
+            pair[0].path.display(),
+            pair[1].path.display(),
+            pair[0].name
+        )));
+    }
+    Ok(files)
+}
+
+/// Convert a Unix timestamp to a zip timestamp,
+/// clamped to the range from 1980-01-01 to 2107-12-31 that zip can represent.
+fn zip_datetime(timestamp: i64) -> zip::DateTime {
+    const MIN: i64 = 315_532_800; // 1980-01-01T00:00:00Z
+    const MAX: i64 = 4_354_819_198; // 2107-12-31T23:59:58Z
+    time::OffsetDateTime::from_unix_timestamp(timestamp.clamp(MIN, MAX))
+        .ok()
+        .and_then(|datetime| {
+            zip::DateTime::try_from(time::PrimitiveDateTime::new(
+                datetime.date(),
+                datetime.time(),
+            ))
+            .ok()
+        })
+        .unwrap_or_default()
+}
 
-    let zip_path = TEMP_DIR.join(name);
-    let file_writer = std::fs::File::create(&zip_path).unwrap();
+/// Write a zip archive with the permissions of [`PackageFile::mode`] and every entry timestamped at `mtime`.
+fn compress_zip(archive_path: &Path, files: &[PackageFile], mtime: i64) -> PackResult<()> {
+    let file_writer = std::fs::File::create(archive_path)
+        .map_err(|_| PackError::CreateFailed(archive_path.to_path_buf()))?;
     let mut zip_writer = zip::ZipWriter::new(file_writer);
     let options = zip::write::SimpleFileOptions::default()
-        .compression_method(zip::CompressionMethod::Deflated);
+        .compression_method(zip::CompressionMethod::Deflated)
+        .last_modified_time(zip_datetime(mtime));
     log::debug!(
         "Created zip file: {}. All files will be compressed into it.",
-        zip_path.display()
+        archive_path.display()
     );
 
-    // Get collected files and add README.md and LICENSE from repo
-    let mut files = COLLECTED_FILES.lock().unwrap().clone();
-    files.push(repo_dir.join("README.md"));
-    files.push(repo_dir.join("LICENSE"));
+    for file in files {
+        log::debug!("Compressing {}", file.path.display());
+        let mut file_reader = std::fs::File::open(&file.path)
+            .map_err(|_| PackError::ReadFailed(file.path.clone()))?;
 
-    for ref file in files {
-        log::debug!("Compressing {}", file.display());
-        let mut file_reader = std::fs::File::open(file)
-            .unwrap_or_else(|_| panic!("Failed to open {}", file.display()));
-
-        let dest = file.file_name().unwrap().to_str().unwrap();
-        zip_writer.start_file(dest, options)?;
+        zip_writer.start_file(file.name.as_str(), options.unix_permissions(file.mode()))?;
         std::io::copy(&mut file_reader, &mut zip_writer)?;
     }
+    zip_writer.finish()?;
 
-    log::info!("All files packaged.");
-    Ok(zip_path)
+    Ok(())
 }
 
-pub fn package_all() -> PackResult<()> {
-    let zip_path = compress_zip()?;
-
-    // copy to target directory
-    let args = ARGS.get().unwrap();
-    std::fs::create_dir_all(&args.path_options.output_path).unwrap();
-    let release_path = args
-        .path_options
-        .output_path
-        .join(zip_path.file_name().unwrap());
-    std::fs::copy(&zip_path, &release_path).unwrap_or_else(|_| {
-        panic!(
-            "Failed to copy {} to {}",
-            zip_path.display(),
-            release_path.display()
-        )
-    });
-
-    log::info!("Copied the package to {}", release_path.display());
+/// Write a tar archive, owned by root, with the permissions of [`PackageFile::mode`]
+/// and every entry timestamped at `mtime`.
+fn write_tar<W: Write>(writer: W, files: &[PackageFile], mtime: i64) -> PackResult<W> {
+    let mut tar_builder = tar::Builder::new(writer);
+    for file in files {
+        log::debug!("Archiving {}", file.path.display());
+        let mut file_reader = std::fs::File::open(&file.path)
+            .map_err(|_| PackError::ReadFailed(file.path.clone()))?;
+        let metadata = file_reader.metadata()?;
+
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata_in_mode(&metadata, tar::HeaderMode::Deterministic);
+        header.set_mode(file.mode());
+        header.set_mtime(mtime.max(0) as u64);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_username("root")?;
+        header.set_groupname("root")?;
+        tar_builder.append_data(&mut header, &file.name, &mut file_reader)?;
+    }
+    Ok(tar_builder.into_inner()?)
+}
+
+fn compress_tar(
+    archive_path: &Path,
+    format: ArchiveFormat,
+    files: &[PackageFile],
+    mtime: i64,
+) -> PackResult<()> {
+    let file_writer = std::fs::File::create(archive_path)
+        .map_err(|_| PackError::CreateFailed(archive_path.to_path_buf()))?;
+    log::debug!(
+        "Created {format} file: {}. All files will be compressed into it.",
+        archive_path.display()
+    );
+
+    let file_writer = match format {
+        ArchiveFormat::TarGz => write_tar(
+            flate2::write::GzEncoder::new(file_writer, flate2::Compression::best()),
+            files,
+            mtime,
+        )?
+        .finish()?,
+        ArchiveFormat::TarXz => write_tar(
+            lzma_rust2::XzWriter::new(file_writer, lzma_rust2::XzOptions::with_preset(9))?,
+            files,
+            mtime,
+        )?
+        .finish()?,
+        ArchiveFormat::TarZst => {
+            write_tar(zstd::Encoder::new(file_writer, 19)?, files, mtime)?.finish()?
+        }
+        ArchiveFormat::Zip => unreachable!("zip is not a tar format"),
+    };
+    file_writer.sync_all()?;
+
     Ok(())
 }
+
+/// Package `files`, as given by [`collect_files`], in every requested archive format as `name`,
+/// and copy the packages to the output directory.
+/// The paths of the copied packages are returned in the order of [`PackJob::formats`].
+pub(crate) fn package_all(
+    job: &PackJob,
+    checkout: &Checkout,
+    target: &GoTarget,
+    name: &str,
+    files: &[PackageFile],
+) -> PackResult<Vec<PathBuf>> {
+    let mtime = job.source_date_epoch.unwrap_or(checkout.commit_time);
+    let target_dir = job.target_dir(target)?;
+    std::fs::create_dir_all(&job.output_path)
+        .map_err(|_| PackError::CreateFailed(job.output_path.clone()))?;
+
+    let mut release_paths = Vec::with_capacity(job.formats.len());
+    for &format in &job.formats {
+        let file_name = format!("{name}.{}", format.extension());
+        let archive_path = target_dir.join(&file_name);
+        match format {
+            ArchiveFormat::Zip => compress_zip(&archive_path, files, mtime)?,
+            _ => compress_tar(&archive_path, format, files, mtime)?,
+        }
+        log::info!("All files for {target} packaged as {format}.");
+
+        // copy to target directory
+        let release_path = job.output_path.join(&file_name);
+        std::fs::copy(&archive_path, &release_path)
+            .map_err(|_| PackError::CopyFailed(archive_path.clone(), release_path.clone()))?;
+
+        log::info!("Copied the package to {}", release_path.display());
+        release_paths.push(release_path);
+    }
+
+    Ok(release_paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    const FORMATS: [ArchiveFormat; 4] = [
+        ArchiveFormat::Zip,
+        ArchiveFormat::TarGz,
+        ArchiveFormat::TarXz,
+        ArchiveFormat::TarZst,
+    ];
+
+    /// Write a binary and a README to `dir`, as packaged files.
+    fn package_files(dir: &Path) -> Vec<PackageFile> {
+        let binary = dir.join("xray");
+        let readme = dir.join("README.md");
+        std::fs::write(&binary, b"binary").unwrap();
+        std::fs::write(&readme, b"readme").unwrap();
+        // The permissions of the packaged files do not depend on these
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let permissions = |mode| std::fs::Permissions::from_mode(mode);
+            std::fs::set_permissions(&binary, permissions(0o700)).unwrap();
+            std::fs::set_permissions(&readme, permissions(0o666)).unwrap();
+        }
+        vec![
+            PackageFile {
+                path: readme,
+                name: "README.md".to_string(),
+                executable: false,
+            },
+            PackageFile {
+                path: binary,
+                name: "xray".to_string(),
+                executable: true,
+            },
+        ]
+    }
+
+    fn compress(archive_path: &Path, format: ArchiveFormat, files: &[PackageFile], mtime: i64) {
+        match format {
+            ArchiveFormat::Zip => compress_zip(archive_path, files, mtime).unwrap(),
+            _ => compress_tar(archive_path, format, files, mtime).unwrap(),
+        }
+    }
+
+    /// Name, permissions and content of every entry of an archive.
+    fn read_entries(archive_path: &Path, format: ArchiveFormat) -> Vec<(String, u32, Vec<u8>)> {
+        let file = std::fs::File::open(archive_path).unwrap();
+        let reader: Box<dyn Read> = match format {
+            ArchiveFormat::Zip => {
+                let mut archive = zip::ZipArchive::new(file).unwrap();
+                return (0..archive.len())
+                    .map(|i| {
+                        let mut entry = archive.by_index(i).unwrap();
+                        let mut content = Vec::new();
+                        entry.read_to_end(&mut content).unwrap();
+                        let mode = entry.unix_mode().unwrap() & 0o7777;
+                        (entry.name().to_string(), mode, content)
+                    })
+                    .collect();
+            }
+            ArchiveFormat::TarGz => Box::new(flate2::read::GzDecoder::new(file)),
+            ArchiveFormat::TarXz => Box::new(lzma_rust2::XzReader::new(file, false)),
+            ArchiveFormat::TarZst => Box::new(zstd::Decoder::new(file).unwrap()),
+        };
+        tar::Archive::new(reader)
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let header = entry.header();
+                assert_eq!(header.uid().unwrap(), 0);
+                assert_eq!(header.gid().unwrap(), 0);
+                assert_eq!(header.username().unwrap(), Some("root"));
+                let name = entry.path().unwrap().to_string_lossy().into_owned();
+                let mode = header.mode().unwrap();
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content).unwrap();
+                (name, mode, content)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn archives_have_fixed_permissions() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = package_files(dir.path());
+        for format in FORMATS {
+            let archive_path = dir.path().join(format!("package.{}", format.extension()));
+            compress(&archive_path, format, &files, 1_700_000_000);
+
+            assert_eq!(
+                read_entries(&archive_path, format),
+                [
+                    ("README.md".to_string(), 0o644, b"readme".to_vec()),
+                    ("xray".to_string(), 0o755, b"binary".to_vec()),
+                ],
+                "{format}"
+            );
+        }
+    }
+
+    #[test]
+    fn archives_are_reproducible() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        let first_files = package_files(first.path());
+        let second_files = package_files(second.path());
+        for file in &second_files {
+            let handle = std::fs::File::options()
+                .write(true)
+                .open(&file.path)
+                .unwrap();
+            handle
+                .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(86_400))
+                .unwrap();
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                handle
+                    .set_permissions(std::fs::Permissions::from_mode(0o600))
+                    .unwrap();
+                // Changing the owner needs root, the archives must not depend on it either way
+                let _ = std::os::unix::fs::chown(&file.path, Some(1234), Some(1234));
+            }
+        }
+
+        for format in FORMATS {
+            let file_name = format!("package.{}", format.extension());
+            compress(
+                &first.path().join(&file_name),
+                format,
+                &first_files,
+                1_700_000_000,
+            );
+            compress(
+                &second.path().join(&file_name),
+                format,
+                &second_files,
+                1_700_000_000,
+            );
+            assert_eq!(
+                std::fs::read(first.path().join(&file_name)).unwrap(),
+                std::fs::read(second.path().join(&file_name)).unwrap(),
+                "{format}"
+            );
+        }
+    }
+}
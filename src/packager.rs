@@ -0,0 +1,383 @@
+use std::path::PathBuf;
+
+use futures::{FutureExt, future::LocalBoxFuture};
+
+use crate::{
+    buildinfo::{read_build_info, write_build_info},
+    checksum::{write_dgst, write_sha256sums},
+    compile,
+    download::{
+        Downloader,
+        geodat::{download_extra_geodat, download_geodat},
+        join_downloads,
+        v2ray_extra::copy_v2ray_services,
+        wintun::{WinPlatform, download_wintun, extract_wintun},
+    },
+    errors::{PackError, PackResult},
+    job::{PackJob, Source},
+    naming::warn_unreleased_targets,
+    package::{ArchiveFormat, collect_files, package_all, package_names},
+    repo,
+    sbom::Sbom,
+    target::{Core, GoTarget},
+    toolchain,
+};
+
+/// A package produced for a single target in a single archive format.
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    pub target: GoTarget,
+    pub format: ArchiveFormat,
+    /// Path of the package in the output directory.
+    pub path: PathBuf,
+    /// SHA256 checksum of the package as a lowercase hex string.
+    pub sha256: String,
+    /// Path of the `.dgst` file next to the package, listing its MD5, SHA1, SHA2-256 and SHA2-512 digests.
+    pub dgst: PathBuf,
+    /// Path of the `<name>.build-info.json` file in the output directory, with the Go version, modules
+    /// and build settings read from the binary. `None` if the binary has no Go build information.
+    pub build_info: Option<PathBuf>,
+    /// Paths of the software bills of materials next to the package, in the order of [`PackJob::sbom`].
+    pub sboms: Vec<PathBuf>,
+}
+
+/// Result of a [`Packager::run`].
+#[derive(Debug, Clone)]
+pub struct PackOutput {
+    /// `git describe` result of the source code that was built.
+    pub describe: String,
+    /// One package per target and archive format, ordered by [`PackJob::targets`]
+    /// and then by [`PackJob::formats`].
+    pub artifacts: Vec<Artifact>,
+    /// Path of the `SHA256SUMS` file in the output directory.
+    /// Only written if more than one package is produced.
+    pub sha256sums: Option<PathBuf>,
+}
+
+/// Runs a [`PackJob`]: checks out the source code, downloads the shared files once,
+/// builds every target and packages them.
+#[derive(Debug, Clone)]
+pub struct Packager {
+    job: PackJob,
+}
+impl Packager {
+    pub fn new(job: PackJob) -> Self {
+        Packager { job }
+    }
+
+    pub fn job(&self) -> &PackJob {
+        &self.job
+    }
+
+    pub fn run(&self) -> PackResult<PackOutput> {
+        let job = &self.job;
+
+        // Reject jobs that cannot succeed before cloning anything
+        check_job(job)?;
+
+        // check prerequisites
+        check_prerequisites()?;
+        toolchain::check_targets(&job.targets)?;
+
+        prepare_temp_dir(job)?;
+        log::debug!("Proxy: {}", job.http.proxy);
+
+        log::info!(
+            "Targets to build: {}",
+            job.targets
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        let checkout = repo::setup_repository(job)?;
+        warn_unreleased_targets(job, &checkout);
+        let names = package_names(job, &checkout)?;
+        if job.offline {
+            compile::check_offline_modules(job, &checkout)?;
+        }
+
+        let mut shared_files = match job.core {
+            Core::Xray => Vec::new(),
+            Core::V2ray => copy_v2ray_services(&checkout.dir, &job.temp_dir)?,
+        };
+
+        // Download the files shared by all targets while building Xray-core or v2ray-core for all targets
+        let downloader = Downloader::new(job)?;
+        log::info!("Downloading the shared files while building");
+        let (downloads, binaries) = std::thread::scope(|scope| {
+            let downloads =
+                scope.spawn(|| downloader.block_on(download_shared_files(job, &downloader)));
+            let binaries = compile::build_all(job, &checkout);
+            let downloads = downloads.join().unwrap_or_else(|_| {
+                Err(PackError::AsyncError(std::io::Error::other(
+                    "the download thread panicked",
+                )))
+            });
+            (downloads, binaries)
+        });
+        if let (Err(_), Err(download_error)) = (&binaries, &downloads) {
+            // Only the build error is returned
+            log::error!("{download_error}");
+        }
+        let binaries = binaries?;
+        let (downloaded_files, wintun_zip) = downloads?;
+        shared_files.extend(downloaded_files);
+
+        let mut artifacts = Vec::with_capacity(job.targets.len() * job.formats.len());
+        for ((target, name), mut files) in job.targets.iter().zip(&names).zip(binaries) {
+            // Read the build information of the console binary, which comes first
+            let build_info = read_build_info(&files[0])?;
+            let build_info_path = match &build_info {
+                Some(build_info) => Some(write_build_info(
+                    target,
+                    &files[0],
+                    build_info,
+                    &job.target_dir(target)?,
+                )?),
+                None => {
+                    log::warn!(
+                        "[{target}] No Go build info found in {}",
+                        files[0].display()
+                    );
+                    None
+                }
+            };
+            if job.embed_build_info
+                && let Some(path) = &build_info_path
+            {
+                files.push(path.clone());
+            }
+
+            // Extract target-specific files
+            if let Some(zip_path) = &wintun_zip
+                && target.is_windows()
+            {
+                files.extend(extract_wintun(
+                    zip_path,
+                    WinPlatform::try_from(target.goarch.as_str())?,
+                    job.wintun.signers(),
+                    &job.target_dir(target)?,
+                )?);
+            }
+
+            let files = collect_files(job, &checkout, target, &shared_files, files)?;
+            let paths = package_all(job, &checkout, target, name, &files)?;
+            let sbom = if job.sbom.is_empty() {
+                None
+            } else {
+                Some(Sbom::new(job, &checkout, &files, build_info.as_ref())?)
+            };
+            let build_info = match build_info_path {
+                Some(path) => {
+                    let release_path = job.output_path.join(format!("{name}.build-info.json"));
+                    std::fs::copy(&path, &release_path)
+                        .map_err(|_| PackError::CopyFailed(path, release_path.clone()))?;
+                    log::info!("Wrote build info to {}", release_path.display());
+                    Some(release_path)
+                }
+                None => None,
+            };
+            for (&format, path) in job.formats.iter().zip(paths) {
+                let (digests, dgst) = write_dgst(&path)?;
+                let sboms = match &sbom {
+                    Some(sbom) => job
+                        .sbom
+                        .iter()
+                        .map(|&format| sbom.write(format, &path, &digests))
+                        .collect::<PackResult<_>>()?,
+                    None => Vec::new(),
+                };
+                artifacts.push(Artifact {
+                    target: target.clone(),
+                    format,
+                    sha256: digests.sha256,
+                    path,
+                    dgst,
+                    build_info: build_info.clone(),
+                    sboms,
+                });
+            }
+        }
+
+        let sha256sums = if artifacts.len() > 1 {
+            let archives: Vec<_> = artifacts
+                .iter()
+                .map(|artifact| (artifact.path.as_path(), artifact.sha256.as_str()))
+                .collect();
+            Some(write_sha256sums(&job.output_path, &archives)?)
+        } else {
+            None
+        };
+
+        Ok(PackOutput {
+            describe: checkout.describe,
+            artifacts,
+            sha256sums,
+        })
+    }
+}
+
+/// Download geoip, geosite, the extra geo files and wintun concurrently.
+/// The paths of the files to package for every target are returned, together with the wintun archive if needed.
+async fn download_shared_files(
+    job: &PackJob,
+    downloader: &Downloader,
+) -> PackResult<(Vec<PathBuf>, Option<PathBuf>)> {
+    let source = job
+        .geodata_source
+        .clone()
+        .unwrap_or_else(|| job.region.into());
+    let geodat: LocalBoxFuture<PackResult<Vec<PathBuf>>> = if job.geodata {
+        download_geodat(
+            &source,
+            job.geoip.as_ref(),
+            job.geosite.as_ref(),
+            &job.temp_dir,
+            downloader,
+        )
+        .boxed_local()
+    } else {
+        log::info!("Packaging without geoip.dat and geosite.dat");
+        async { Ok(Vec::new()) }.boxed_local()
+    };
+    let extra_geodat =
+        download_extra_geodat(&job.extra_geodata, &job.temp_dir, downloader).boxed_local();
+    let wintun = async {
+        if job.core == Core::Xray && job.targets.iter().any(GoTarget::is_windows) {
+            Ok(vec![
+                download_wintun(&job.wintun, &job.temp_dir, downloader).await?,
+            ])
+        } else {
+            Ok(Vec::new())
+        }
+    }
+    .boxed_local();
+
+    let mut downloads = join_downloads([geodat, extra_geodat, wintun]).await?;
+    let wintun_zip = downloads.pop().and_then(|mut paths| paths.pop());
+    Ok((downloads.concat(), wintun_zip))
+}
+
+/// Check the parts of `job` that need neither the Go toolchain nor the source code.
+fn check_job(job: &PackJob) -> PackResult<()> {
+    if job.core == Core::Xray {
+        for target in job.targets.iter().filter(|target| target.is_windows()) {
+            WinPlatform::try_from(target.goarch.as_str())?;
+        }
+        if job.targets.iter().any(GoTarget::is_windows) {
+            // The wintun archive must have a checksum to be verified against
+            job.wintun.expected_sha256()?;
+        }
+    }
+    if job.offline && matches!(job.source, Source::Clone) {
+        return Err(PackError::OfflineUnavailable(format!(
+            "{} (cloning {} needs the network, use --source-path instead)",
+            job.core,
+            job.core.repo_url()
+        )));
+    }
+    Ok(())
+}
+
+// check prerequisites
+fn check_prerequisites() -> PackResult<()> {
+    // Currently only Go compiler is required.
+    let prerequisites = vec!["go"];
+
+    // check if prerequisites are in PATH
+    for prerequisite in prerequisites {
+        if which::which(prerequisite).is_err() {
+            return Err(PackError::MissingDependency(prerequisite.to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove the temporary directory left by a previous run and create an empty one.
+fn prepare_temp_dir(job: &PackJob) -> PackResult<()> {
+    let temp_dir = &job.temp_dir;
+    if temp_dir.exists() {
+        log::debug!(
+            "Removing existing temporary directory at {}",
+            temp_dir.display()
+        );
+        std::fs::remove_dir_all(temp_dir).map_err(|_| PackError::DeleteFailed(temp_dir.clone()))?;
+    }
+    log::debug!("Creating temporary directory at {}", temp_dir.display());
+    std::fs::create_dir_all(temp_dir).map_err(|_| PackError::CreateFailed(temp_dir.clone()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{download::wintun::Wintun, job::PackJobBuilder};
+
+    /// A job building the not yet checked out source code in `dir`. A file is left in its temporary
+    /// directory, which is only removed once the checks have passed.
+    fn job(dir: &Path, core: Core, target: &str) -> PackJobBuilder {
+        let temp_dir = dir.join("temp");
+        std::fs::create_dir_all(&temp_dir).unwrap();
+        std::fs::write(temp_dir.join("marker"), b"").unwrap();
+        PackJob::builder(core)
+            .source(Source::Path(dir.join("source")))
+            .temp_dir(temp_dir)
+            .output_path(dir.join("dist"))
+            .cache(None)
+            .target(target.parse().unwrap())
+    }
+
+    /// Run `job`, checking that it failed before touching the temporary directory.
+    fn run(dir: &Path, job: PackJobBuilder) -> PackError {
+        let error = Packager::new(job.build().unwrap()).run().unwrap_err();
+        assert!(dir.join("temp/marker").exists(), "{error}");
+        error
+    }
+
+    #[test]
+    fn rejects_wintun_platforms_before_the_checkout() {
+        let dir = tempfile::tempdir().unwrap();
+        let error = run(dir.path(), job(dir.path(), Core::Xray, "windows/riscv64"));
+        assert!(
+            matches!(&error, PackError::UnsupportedTarget { target, .. } if target == "windows/riscv64"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_wintun_checksums_before_the_checkout() {
+        let dir = tempfile::tempdir().unwrap();
+        let job = job(dir.path(), Core::Xray, "windows/amd64").wintun(Wintun::new("9.9.9"));
+        let error = run(dir.path(), job);
+        assert!(matches!(error, PackError::ConfigError(_)), "{error}");
+    }
+
+    #[test]
+    fn rejects_offline_clones_before_the_checkout() {
+        let dir = tempfile::tempdir().unwrap();
+        let job = job(dir.path(), Core::V2ray, "linux/amd64")
+            .source(Source::Clone)
+            .offline(true);
+        let error = run(dir.path(), job);
+        assert!(matches!(error, PackError::OfflineUnavailable(_)), "{error}");
+    }
+
+    #[test]
+    fn checks_the_toolchain_before_the_checkout() {
+        let dir = tempfile::tempdir().unwrap();
+        let error = run(dir.path(), job(dir.path(), Core::Xray, "linux/amd46"));
+        // Depending on whether Go is installed
+        assert!(
+            matches!(
+                error,
+                PackError::MissingDependency(_) | PackError::UnsupportedTarget { .. }
+            ),
+            "{error}"
+        );
+    }
+}
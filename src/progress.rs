@@ -0,0 +1,98 @@
+use std::{io::IsTerminal, sync::LazyLock, time::Duration};
+
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+/// Every progress bar is drawn on stderr, below the log lines.
+static BARS: LazyLock<MultiProgress> =
+    LazyLock::new(|| MultiProgress::with_draw_target(ProgressDrawTarget::stderr()));
+
+/// A [`log::Log`] that hides the progress bars while a record is written, so that log lines
+/// do not tear them apart.
+pub struct ProgressLogger<L> {
+    inner: L,
+}
+impl<L: log::Log> ProgressLogger<L> {
+    pub fn new(inner: L) -> Self {
+        ProgressLogger { inner }
+    }
+}
+impl<L: log::Log> log::Log for ProgressLogger<L> {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.inner.enabled(record.metadata()) {
+            BARS.suspend(|| self.inner.log(record));
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Add a progress bar labelled `name` for a transfer, or a hidden one if `enabled` is `false`
+/// or stderr is not a terminal.
+fn add_bar(enabled: bool, name: &str, style: ProgressStyle) -> ProgressBar {
+    if !enabled || !std::io::stderr().is_terminal() {
+        return ProgressBar::hidden();
+    }
+    let bar = BARS.add(ProgressBar::no_length().with_style(style));
+    bar.set_prefix(name.to_string());
+    bar.enable_steady_tick(Duration::from_millis(100));
+    bar
+}
+
+/// A progress bar of the bytes downloaded for `name`.
+/// Its length is set from the `Content-Length` of the response, if the server sends one.
+pub(crate) fn download_bar(enabled: bool, name: &str) -> ProgressBar {
+    add_bar(
+        enabled,
+        name,
+        ProgressStyle::with_template(
+            "{prefix:>16.cyan} {spinner} {bytes} {binary_bytes_per_sec} {elapsed}",
+        )
+        .expect("valid template"),
+    )
+}
+
+/// Switch a download bar to show the percentage and ETA, once the total size is known.
+pub(crate) fn set_download_length(bar: &ProgressBar, length: u64) {
+    bar.set_style(
+        ProgressStyle::with_template(
+            "{prefix:>16.cyan} [{bar:30}] {bytes}/{total_bytes} {binary_bytes_per_sec} ETA {eta}",
+        )
+        .expect("valid template")
+        .progress_chars("=> "),
+    );
+    bar.set_length(length);
+}
+
+/// A progress bar of the objects received by a git clone.
+/// Its message shows the received bytes and the stage of the clone.
+pub(crate) fn clone_bar(enabled: bool, name: &str) -> ProgressBar {
+    add_bar(
+        enabled,
+        name,
+        ProgressStyle::with_template("{prefix:>16.cyan} [{bar:30}] {pos}/{len} {msg} {elapsed}")
+            .expect("valid template")
+            .progress_chars("=> "),
+    )
+}
+
+/// Update a clone bar from the transfer statistics of git.
+pub(crate) fn update_clone_bar(bar: &ProgressBar, stats: &git2::Progress) {
+    if stats.received_objects() < stats.total_objects() {
+        bar.set_length(stats.total_objects() as u64);
+        bar.set_position(stats.received_objects() as u64);
+        bar.set_message(format!(
+            "objects, {}",
+            HumanBytes(stats.received_bytes() as u64)
+        ));
+    } else {
+        bar.set_length(stats.total_deltas() as u64);
+        bar.set_position(stats.indexed_deltas() as u64);
+        bar.set_message("deltas resolved");
+    }
+}
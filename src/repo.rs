@@ -1,92 +1,113 @@
-use git2::{FetchOptions, ProxyOptions, Repository, build::RepoBuilder};
+use std::{cell::Cell, path::PathBuf, time::Instant};
 
-use crate::{ARGS, REPOSITORY_DIR, TEMP_DIR, errors::PackResult};
+use git2::{FetchOptions, ProxyOptions, RemoteCallbacks, Repository, build::RepoBuilder};
+use indicatif::{HumanBytes, HumanDuration};
 
-fn get_https_proxy() -> Option<String> {
-    if let Ok(proxy) = std::env::var("HTTPS_PROXY") {
-        Some(proxy)
-    } else if let Ok(proxy) = std::env::var("https_proxy") {
-        Some(proxy)
-    } else if let Ok(proxy) = std::env::var("ALL_PROXY") {
-        Some(proxy)
-    } else {
-        std::env::var("all_proxy").ok()
-    }
+use crate::{
+    errors::PackResult,
+    job::{PackJob, Source},
+    progress::{clone_bar, update_clone_bar},
+};
+
+/// A checked out repository of Xray-core or v2ray-core.
+#[derive(Debug, Clone)]
+pub(crate) struct Checkout {
+    /// Root directory of the working tree.
+    pub dir: PathBuf,
+    /// `git describe` result of the checked out version.
+    pub describe: String,
+    /// Committer time of the checked out commit, in seconds since the Unix epoch.
+    pub commit_time: i64,
 }
 
-/// This function returns `git describe` result
-pub fn setup_repository() -> PackResult<String> {
+/// Open or clone the repository and check out the requested version.
+pub(crate) fn setup_repository(job: &PackJob) -> PackResult<Checkout> {
     // Open or clone Xray-core repository
-    let args = ARGS.get().unwrap();
-    let repo: Repository = if args.path_options.from_source {
-        // Clone Xray-core source code (via proxy if available)
-        let dest = TEMP_DIR.join(args.target.to_string());
+    let repo: Repository = match &job.source {
+        Source::Clone => {
+            // Clone Xray-core source code (via proxy if available)
+            let dest = job.temp_dir.join(job.core.to_string());
 
-        match get_https_proxy() {
-            Some(ref proxy) => {
-                log::debug!(
-                    "Cloning {} repository to {} with proxy {}",
-                    args.target,
-                    dest.display(),
-                    proxy
-                );
-                let mut proxy_opts = ProxyOptions::new();
-                proxy_opts.url(proxy);
-                let mut fetch_opts = FetchOptions::new();
-                fetch_opts.proxy_options(proxy_opts);
+            // Report the transfer progress, and keep the final statistics for the log
+            let bar = clone_bar(job.progress, &job.core.to_string());
+            let stats = Cell::new((0, 0, 0));
 
-                RepoBuilder::new()
-                    .fetch_options(fetch_opts)
-                    .clone(args.target.repo_url(), &dest)?
-            }
-            None => {
-                log::debug!("Cloning {} repository to {}", args.target, dest.display());
-                RepoBuilder::new().clone(args.target.repo_url(), &dest)?
+            let mut fetch_opts = FetchOptions::new();
+            match job.http.proxy.for_url(job.core.repo_url()) {
+                Some(proxy) => {
+                    log::debug!(
+                        "Cloning {} repository to {} with proxy {}",
+                        job.core,
+                        dest.display(),
+                        proxy
+                    );
+                    let mut proxy_opts = ProxyOptions::new();
+                    proxy_opts.url(proxy);
+                    fetch_opts.proxy_options(proxy_opts);
+                }
+                None => log::debug!("Cloning {} repository to {}", job.core, dest.display()),
             }
+
+            let mut callbacks = RemoteCallbacks::new();
+            callbacks.transfer_progress(|progress| {
+                update_clone_bar(&bar, &progress);
+                stats.set((
+                    progress.received_objects(),
+                    progress.received_bytes(),
+                    progress.total_deltas(),
+                ));
+                true
+            });
+            fetch_opts.remote_callbacks(callbacks);
+
+            let started = Instant::now();
+            let repo = RepoBuilder::new()
+                .fetch_options(fetch_opts)
+                .clone(job.core.repo_url(), &dest);
+            bar.finish_and_clear();
+            let repo = repo?;
+
+            let (objects, bytes, deltas) = stats.get();
+            log::info!(
+                "Cloned {} repository: {objects} objects, {deltas} deltas, {} in {}",
+                job.core,
+                HumanBytes(bytes as u64),
+                HumanDuration(started.elapsed())
+            );
+            repo
+        }
+        Source::Path(source_path) => {
+            log::debug!("Open {} repository at {}", job.core, source_path.display());
+            Repository::open(source_path)?
         }
-    } else {
-        log::debug!(
-            "Open {} repository at {}",
-            args.target,
-            args.path_options.source_path.display()
-        );
-        Repository::open(&args.path_options.source_path)?
     };
-    REPOSITORY_DIR
-        .set(repo.path().join("../").to_path_buf())
-        .unwrap();
-    log::info!(
-        "{} repository locates at {}",
-        args.target,
-        REPOSITORY_DIR.get().unwrap().display()
-    );
+    let dir = repo.path().join("../").to_path_buf();
+    log::info!("{} repository locates at {}", job.core, dir.display());
 
     // Checkout Xray-core version
-    log::debug!(
-        "Checking out {} version {}",
-        args.target,
-        args.target.repo_version()
-    );
-    let (object, reference) = repo.revparse_ext(&args.target.repo_version())?;
+    log::debug!("Checking out {} version {}", job.core, job.version);
+    let (object, reference) = repo.revparse_ext(&job.version)?;
     repo.checkout_tree(&object, None)?;
     if let Some(reference) = reference {
         repo.set_head(reference.name().unwrap())?;
     } else {
         repo.set_head_detached(object.id())?;
     }
-    log::info!(
-        "Switch to {} version {}",
-        args.target,
-        args.target.repo_version()
-    );
+    log::info!("Switch to {} version {}", job.core, job.version);
 
     // Get result of (git describe --always --dirty)
-    let describe_result = repo
+    let describe = repo
         .describe(git2::DescribeOptions::new().describe_tags())
         .and_then(|describe| describe.format(None))
         .unwrap_or_else(|_| object.id().to_string());
 
-    log::info!("Current commit id: {}", describe_result);
+    log::info!("Current commit id: {}", describe);
+
+    let commit_time = object.peel_to_commit()?.time().seconds();
 
-    Ok(describe_result)
+    Ok(Checkout {
+        dir,
+        describe,
+        commit_time,
+    })
 }
@@ -0,0 +1,855 @@
+//! Software bills of materials of the packages, as CycloneDX or SPDX JSON documents.
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    buildinfo::{BuildInfo, Module, read_go_mod},
+    checksum::{Digests, sha256_file, to_hex},
+    download::geodat::GeoSource,
+    errors::{PackError, PackResult},
+    job::{PackJob, Source},
+    package::PackageFile,
+    repo::Checkout,
+    target::Core,
+};
+
+const CYCLONEDX_SPEC_VERSION: &str = "1.5";
+const SPDX_VERSION: &str = "SPDX-2.3";
+
+/// Format of the software bill of materials written next to every package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum SbomFormat {
+    /// CycloneDX 1.5 JSON, written as `<package>.cdx.json`.
+    #[value(name = "cyclonedx")]
+    #[serde(rename = "cyclonedx")]
+    CycloneDx,
+    /// SPDX 2.3 JSON, written as `<package>.spdx.json`.
+    #[value(name = "spdx")]
+    #[serde(rename = "spdx")]
+    Spdx,
+}
+impl SbomFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SbomFormat::CycloneDx => "cdx.json",
+            SbomFormat::Spdx => "spdx.json",
+        }
+    }
+}
+impl Display for SbomFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SbomFormat::CycloneDx => write!(f, "CycloneDX"),
+            SbomFormat::Spdx => write!(f, "SPDX"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Archive,
+    Application,
+    Library,
+    File,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HashAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+impl HashAlgorithm {
+    fn cyclonedx(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Md5 => "MD5",
+            HashAlgorithm::Sha1 => "SHA-1",
+            HashAlgorithm::Sha256 => "SHA-256",
+            HashAlgorithm::Sha512 => "SHA-512",
+        }
+    }
+
+    fn spdx(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Md5 => "MD5",
+            HashAlgorithm::Sha1 => "SHA1",
+            HashAlgorithm::Sha256 => "SHA256",
+            HashAlgorithm::Sha512 => "SHA512",
+        }
+    }
+}
+
+/// Where a component comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Origin {
+    /// Downloaded from, or copied from, this URL.
+    Distribution(String),
+    /// Taken from a revision of a git repository.
+    Vcs { url: String, revision: String },
+}
+
+/// A component of a package, or the package itself.
+#[derive(Debug, Clone)]
+struct Component {
+    /// Unique within the document.
+    id: String,
+    kind: Kind,
+    name: String,
+    version: Option<String>,
+    purl: Option<String>,
+    hashes: Vec<(HashAlgorithm, String)>,
+    origin: Option<Origin>,
+    /// SPDX license identifier.
+    license: Option<&'static str>,
+    properties: Vec<(String, String)>,
+}
+impl Component {
+    fn new(id: String, kind: Kind, name: impl Into<String>) -> Self {
+        Component {
+            id,
+            kind,
+            name: name.into(),
+            version: None,
+            purl: None,
+            hashes: Vec::new(),
+            origin: None,
+            license: None,
+            properties: Vec::new(),
+        }
+    }
+
+    fn cyclonedx(&self) -> Value {
+        let kind = match self.kind {
+            Kind::Application => "application",
+            Kind::Library => "library",
+            Kind::Archive | Kind::File => "file",
+        };
+        let hashes: Vec<Value> = self
+            .hashes
+            .iter()
+            .map(|(algorithm, hash)| json!({"alg": algorithm.cyclonedx(), "content": hash}))
+            .collect();
+        let external_references: Vec<Value> = match &self.origin {
+            Some(Origin::Distribution(url)) => vec![json!({"type": "distribution", "url": url})],
+            Some(Origin::Vcs { url, .. }) => vec![json!({"type": "vcs", "url": url})],
+            None => Vec::new(),
+        };
+        let properties: Vec<Value> = self
+            .properties
+            .iter()
+            .map(|(name, value)| json!({"name": name, "value": value}))
+            .collect();
+        json!({
+            "type": kind,
+            "bom-ref": self.id,
+            "name": self.name,
+            "version": self.version,
+            "hashes": non_empty(hashes),
+            "licenses": self.license.map(|id| json!([{"license": {"id": id}}])),
+            "purl": self.purl,
+            "externalReferences": non_empty(external_references),
+            "properties": non_empty(properties),
+        })
+    }
+
+    /// `SPDXRef-` followed by the id with the characters SPDX does not allow replaced, and a hash of
+    /// the id, since different ids may be the same once replaced.
+    fn spdx_id(&self) -> String {
+        let id: String = self
+            .id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                    c
+                } else {
+                    '-'
+                }
+            })
+            .collect();
+        let hash = to_hex(&Sha256::digest(self.id.as_bytes()));
+        format!("SPDXRef-{id}-{}", &hash[..8])
+    }
+
+    fn spdx(&self) -> Value {
+        let purpose = match self.kind {
+            Kind::Archive => "ARCHIVE",
+            Kind::Application => "APPLICATION",
+            Kind::Library => "LIBRARY",
+            Kind::File => "FILE",
+        };
+        let download_location = match &self.origin {
+            Some(Origin::Distribution(url)) => url.clone(),
+            Some(Origin::Vcs { url, revision }) => format!("git+{url}@{revision}"),
+            None => "NOASSERTION".to_string(),
+        };
+        let checksums: Vec<Value> = self
+            .hashes
+            .iter()
+            .map(|(algorithm, hash)| json!({"algorithm": algorithm.spdx(), "checksumValue": hash}))
+            .collect();
+        let external_refs: Vec<Value> = self
+            .purl
+            .iter()
+            .map(|purl| {
+                json!({
+                    "referenceCategory": "PACKAGE-MANAGER",
+                    "referenceType": "purl",
+                    "referenceLocator": purl,
+                })
+            })
+            .collect();
+        json!({
+            "SPDXID": self.spdx_id(),
+            "name": self.name,
+            "versionInfo": self.version,
+            "downloadLocation": download_location,
+            "filesAnalyzed": false,
+            "checksums": non_empty(checksums),
+            "licenseDeclared": self.license,
+            "externalRefs": non_empty(external_refs),
+            "primaryPackagePurpose": purpose,
+        })
+    }
+}
+
+/// What every package of a target contains, to be described in its software bills of materials.
+#[derive(Debug, Clone)]
+pub(crate) struct Sbom {
+    /// The files of the package.
+    contents: Vec<Component>,
+    /// The Go modules the binaries are built from, including the standard library.
+    modules: Vec<Component>,
+    version: String,
+    /// Creation time of the documents, in seconds since the Unix epoch.
+    timestamp: i64,
+}
+impl Sbom {
+    /// Describe `files`, as given by [`crate::package::collect_files`]. The Go modules are taken from
+    /// `build_info` of the binary, or else from `go.mod` and `go.sum` of the checked out source code.
+    pub fn new(
+        job: &PackJob,
+        checkout: &Checkout,
+        files: &[PackageFile],
+        build_info: Option<&BuildInfo>,
+    ) -> PackResult<Self> {
+        let go_mod;
+        let build_info = match build_info {
+            Some(build_info) => build_info,
+            None => {
+                log::debug!("Listing the Go modules of the SBOM from go.mod");
+                go_mod = read_go_mod(&checkout.dir)?;
+                &go_mod
+            }
+        };
+
+        let mut modules: Vec<Component> = build_info.deps.iter().map(module_component).collect();
+        // The version may be followed by the experiments, e.g. `go1.24.2 X:nodwarf5`
+        if let Some(go_version) = build_info.go_version.split_whitespace().next() {
+            let mut stdlib = Component::new(
+                format!("pkg:golang/stdlib@{go_version}"),
+                Kind::Library,
+                "stdlib",
+            );
+            stdlib.version = Some(go_version.to_string());
+            stdlib.purl = Some(stdlib.id.clone());
+            stdlib.license = Some("BSD-3-Clause");
+            modules.push(stdlib);
+        }
+
+        let repository = match &job.source {
+            Source::Clone => job.core.repo_url().to_string(),
+            Source::Path(path) => file_url(path),
+        };
+        let vcs = Origin::Vcs {
+            url: repository,
+            revision: checkout.describe.clone(),
+        };
+        // Binaries report `(devel)` unless they are built from a tagged module
+        let version = build_info
+            .main
+            .as_ref()
+            .map(|main| main.version.as_str())
+            .filter(|version| !version.is_empty() && *version != "(devel)")
+            .unwrap_or(&checkout.describe);
+
+        let mut contents = Vec::with_capacity(files.len());
+        for file in files {
+            let mut component =
+                Component::new(format!("file:{}", file.name), Kind::File, &file.name);
+            if file.executable {
+                component.kind = Kind::Application;
+                component.version = Some(version.to_string());
+                component.purl = Some(purl(&build_info.path, version));
+                component.origin = Some(vcs.clone());
+                component.license = Some(match job.core {
+                    Core::Xray => "MPL-2.0",
+                    Core::V2ray => "MIT",
+                });
+                component.properties = build_info
+                    .settings
+                    .iter()
+                    .map(|(key, value)| (format!("golang:build:{key}"), value.clone()))
+                    .collect();
+            } else if file.name == "wintun.dll" {
+                component.kind = Kind::Library;
+                component.name = "wintun".to_string();
+                component.version = Some(job.wintun.version().to_string());
+                component.origin = Some(wintun_origin(job));
+            } else {
+                component.origin = file_origin(job, &file.name, &vcs);
+            }
+            component.hashes = vec![(HashAlgorithm::Sha256, sha256_file(&file.path)?)];
+            contents.push(component);
+        }
+        log::debug!(
+            "SBOM lists {} files and {} Go modules",
+            contents.len(),
+            modules.len()
+        );
+
+        Ok(Sbom {
+            contents,
+            modules,
+            version: checkout.describe.clone(),
+            timestamp: job.source_date_epoch.unwrap_or(checkout.commit_time),
+        })
+    }
+
+    /// Write the software bill of materials of the package at `archive_path` in `format` next to it.
+    /// The path of the document is returned.
+    pub fn write(
+        &self,
+        format: SbomFormat,
+        archive_path: &Path,
+        digests: &Digests,
+    ) -> PackResult<PathBuf> {
+        let archive_name = archive_path.file_name().unwrap().to_string_lossy();
+        let mut archive = Component::new(
+            format!("archive:{archive_name}"),
+            Kind::Archive,
+            &*archive_name,
+        );
+        archive.version = Some(self.version.clone());
+        archive.hashes = vec![
+            (HashAlgorithm::Md5, digests.md5.clone()),
+            (HashAlgorithm::Sha1, digests.sha1.clone()),
+            (HashAlgorithm::Sha256, digests.sha256.clone()),
+            (HashAlgorithm::Sha512, digests.sha512.clone()),
+        ];
+        let uuid = uuid_from_sha256(&digests.sha256);
+
+        let document = match format {
+            SbomFormat::CycloneDx => self.cyclonedx(&archive, &uuid),
+            SbomFormat::Spdx => self.spdx(&archive, &uuid),
+        };
+        let mut document_name = archive_path.file_name().unwrap().to_os_string();
+        document_name.push(".");
+        document_name.push(format.extension());
+        let document_path = archive_path.with_file_name(document_name);
+        let json = serde_json::to_string_pretty(&without_nulls(document))
+            .map_err(|_| PackError::CreateFailed(document_path.clone()))?;
+        std::fs::write(&document_path, json + "\n")
+            .map_err(|_| PackError::CreateFailed(document_path.clone()))?;
+        log::info!("Wrote {format} SBOM to {}", document_path.display());
+        Ok(document_path)
+    }
+
+    fn applications(&self) -> impl Iterator<Item = &Component> {
+        self.contents
+            .iter()
+            .filter(|component| component.kind == Kind::Application)
+    }
+
+    fn cyclonedx(&self, archive: &Component, uuid: &str) -> Value {
+        let mut dependencies = vec![json!({
+            "ref": archive.id,
+            "dependsOn": self.contents.iter().map(|c| &c.id).collect::<Vec<_>>(),
+        })];
+        dependencies.extend(self.applications().map(|application| {
+            json!({
+                "ref": application.id,
+                "dependsOn": self.modules.iter().map(|m| &m.id).collect::<Vec<_>>(),
+            })
+        }));
+        json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": CYCLONEDX_SPEC_VERSION,
+            "serialNumber": format!("urn:uuid:{uuid}"),
+            "version": 1,
+            "metadata": {
+                "timestamp": rfc3339(self.timestamp),
+                "tools": {
+                    "components": [{
+                        "type": "application",
+                        "name": env!("CARGO_PKG_NAME"),
+                        "version": env!("CARGO_PKG_VERSION"),
+                    }],
+                },
+                "component": archive.cyclonedx(),
+            },
+            "components": self
+                .contents
+                .iter()
+                .chain(&self.modules)
+                .map(Component::cyclonedx)
+                .collect::<Vec<_>>(),
+            "dependencies": dependencies,
+        })
+    }
+
+    fn spdx(&self, archive: &Component, uuid: &str) -> Value {
+        let relationship = |element: &Component, kind: &str, related: &Component| {
+            json!({
+                "spdxElementId": element.spdx_id(),
+                "relationshipType": kind,
+                "relatedSpdxElement": related.spdx_id(),
+            })
+        };
+        let mut relationships = vec![json!({
+            "spdxElementId": "SPDXRef-DOCUMENT",
+            "relationshipType": "DESCRIBES",
+            "relatedSpdxElement": archive.spdx_id(),
+        })];
+        relationships.extend(
+            self.contents
+                .iter()
+                .map(|content| relationship(archive, "CONTAINS", content)),
+        );
+        for application in self.applications() {
+            relationships.extend(
+                self.modules
+                    .iter()
+                    .map(|module| relationship(application, "DEPENDS_ON", module)),
+            );
+        }
+        json!({
+            "spdxVersion": SPDX_VERSION,
+            "dataLicense": "CC0-1.0",
+            "SPDXID": "SPDXRef-DOCUMENT",
+            "name": archive.name,
+            "documentNamespace": format!("https://spdx.org/spdxdocs/{}-{uuid}", archive.name),
+            "creationInfo": {
+                "created": rfc3339(self.timestamp),
+                "creators": [format!("Tool: {}-{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))],
+            },
+            "packages": std::iter::once(archive)
+                .chain(&self.contents)
+                .chain(&self.modules)
+                .map(Component::spdx)
+                .collect::<Vec<_>>(),
+            "relationships": relationships,
+        })
+    }
+}
+
+/// The component of a Go module, or of the module replacing it if it is replaced by another version.
+fn module_component(module: &Module) -> Component {
+    let (actual, replaced) = match &module.replace {
+        Some(replace) if !replace.version.is_empty() => (replace.as_ref(), Some(module)),
+        _ => (module, None),
+    };
+    let purl = purl(&actual.path, &actual.version);
+    let mut component = Component::new(purl.clone(), Kind::Library, &actual.path);
+    component.version = Some(actual.version.clone());
+    component.purl = Some(purl);
+    // The `h1:` checksum of go.sum hashes the list of files of the module rather than the module
+    // itself, so it is not a hash of the component
+    if let Some(sum) = &actual.sum {
+        component
+            .properties
+            .push(("golang:h1".to_string(), sum.clone()));
+    }
+    if let Some(replaced) = replaced {
+        component.properties.push((
+            "golang:replaces".to_string(),
+            format!("{} {}", replaced.path, replaced.version),
+        ));
+    } else if let Some(replace) = &module.replace {
+        // Replaced by a directory
+        component
+            .properties
+            .push(("golang:replace".to_string(), replace.path.clone()));
+    }
+    component
+}
+
+/// Package URL of a Go module.
+fn purl(path: &str, version: &str) -> String {
+    format!("pkg:golang/{path}@{}", version.replace('+', "%2B"))
+}
+
+/// Where the packaged file `name` comes from, if it is not generated.
+/// `vcs` is the origin of the files taken from the source code.
+fn file_origin(job: &PackJob, name: &str, vcs: &Origin) -> Option<Origin> {
+    match name {
+        "geoip.dat" | "geosite.dat" => {
+            let geo_file = if name == "geoip.dat" {
+                job.geoip.as_ref()
+            } else {
+                job.geosite.as_ref()
+            };
+            Some(match geo_file {
+                Some(geo_file) => geo_origin(&geo_file.source),
+                None => {
+                    let source = job
+                        .geodata_source
+                        .clone()
+                        .unwrap_or_else(|| job.region.into());
+                    let file_name = if name == "geoip.dat" {
+                        &source.geoip
+                    } else {
+                        &source.geosite
+                    };
+                    Origin::Distribution(format!("{}{file_name}", source.base_url))
+                }
+            })
+        }
+        "LICENSE-wintun.txt" => Some(wintun_origin(job)),
+        "README.md" | "LICENSE" | "v2ray.service" | "v2ray@.service" => Some(vcs.clone()),
+        _ => job
+            .extra_geodata
+            .iter()
+            .find(|(extra_name, _)| extra_name == name)
+            .map(|(_, geo_file)| geo_origin(&geo_file.source)),
+    }
+}
+
+fn geo_origin(source: &GeoSource) -> Origin {
+    match source {
+        GeoSource::Url(url) => Origin::Distribution(url.clone()),
+        GeoSource::Path(path) => Origin::Distribution(file_url(path)),
+    }
+}
+
+fn wintun_origin(job: &PackJob) -> Origin {
+    match job.wintun.zip() {
+        Some(path) => Origin::Distribution(file_url(path)),
+        None => Origin::Distribution(job.wintun.url()),
+    }
+}
+
+fn file_url(path: &Path) -> String {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    format!("file://{}", path.display())
+}
+
+/// A UUID derived from the SHA-256 of a package, so that its documents are reproducible.
+/// The version is 8, the one for custom UUIDs.
+fn uuid_from_sha256(sha256: &str) -> String {
+    let variant = u8::from_str_radix(&sha256[16..17], 16).unwrap_or_default() & 0x3 | 0x8;
+    format!(
+        "{}-{}-8{}-{variant:x}{}-{}",
+        &sha256[..8],
+        &sha256[8..12],
+        &sha256[13..16],
+        &sha256[17..20],
+        &sha256[20..32]
+    )
+}
+
+/// Format a Unix timestamp as an RFC 3339 UTC date and time, e.g. `2025-01-31T12:00:00Z`.
+fn rfc3339(timestamp: i64) -> String {
+    let datetime = time::OffsetDateTime::from_unix_timestamp(timestamp)
+        .unwrap_or(time::OffsetDateTime::UNIX_EPOCH);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        datetime.year(),
+        datetime.month() as u8,
+        datetime.day(),
+        datetime.hour(),
+        datetime.minute(),
+        datetime.second()
+    )
+}
+
+fn non_empty(values: Vec<Value>) -> Option<Vec<Value>> {
+    (!values.is_empty()).then_some(values)
+}
+
+/// Remove the fields set to `null`, which stand for missing optional fields.
+fn without_nulls(value: Value) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key, without_nulls(value)))
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.into_iter().map(without_nulls).collect()),
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashSet};
+
+    use super::*;
+    use crate::download::{geodat::GeoFile, wintun::Wintun};
+
+    fn module(path: &str, version: &str, sum: Option<&str>) -> Module {
+        Module {
+            path: path.to_string(),
+            version: version.to_string(),
+            sum: sum.map(ToString::to_string),
+            replace: None,
+        }
+    }
+
+    /// Write the SBOM of a package with every kind of file in `format`, and read it back.
+    fn document(dir: &Path, format: SbomFormat) -> (Value, Digests) {
+        let job = PackJob::builder(Core::Xray)
+            .source(Source::Path(dir.to_path_buf()))
+            .geoip(GeoFile::new(GeoSource::Url(
+                "https://example.com/geoip.dat".to_string(),
+            )))
+            .build()
+            .unwrap();
+        let checkout = Checkout {
+            dir: dir.to_path_buf(),
+            describe: "v1.2.3".to_string(),
+            commit_time: 1709210096,
+        };
+        let files: Vec<PackageFile> = [
+            "xray.exe",
+            "README.md",
+            "geoip.dat",
+            "geosite.dat",
+            "wintun.dll",
+            "LICENSE-wintun.txt",
+            "v2ray.service",
+        ]
+        .into_iter()
+        .map(|name| {
+            let path = dir.join(name);
+            std::fs::write(&path, name).unwrap();
+            PackageFile {
+                path,
+                name: name.to_string(),
+                executable: name == "xray.exe",
+            }
+        })
+        .collect();
+        let build_info = BuildInfo {
+            go_version: "go1.24.2 X:nodwarf5".to_string(),
+            path: "github.com/xtls/xray-core/main".to_string(),
+            main: Some(module("github.com/xtls/xray-core", "(devel)", None)),
+            deps: vec![
+                module("example.com/a_b", "v1.0.0", Some("h1:first=")),
+                module("example.com/a~b", "v1.0.0", Some("h1:second=")),
+            ],
+            settings: BTreeMap::from([("GOOS".to_string(), "windows".to_string())]),
+        };
+        let sbom = Sbom::new(&job, &checkout, &files, Some(&build_info)).unwrap();
+
+        let archive_path = dir.join("xray-v1.2.3-windows-amd64.zip");
+        std::fs::write(&archive_path, "archive").unwrap();
+        let digests = Digests::of_file(&archive_path).unwrap();
+        let path = sbom.write(format, &archive_path, &digests).unwrap();
+        let content = std::fs::read_to_string(path).unwrap();
+        (serde_json::from_str(&content).unwrap(), digests)
+    }
+
+    fn find<'a>(values: &'a Value, key: &str, name: &str) -> &'a Value {
+        values
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|value| value[key] == name)
+            .unwrap_or_else(|| panic!("no {name}"))
+    }
+
+    #[test]
+    fn uuids_from_digests() {
+        // SHA-256 of the empty string
+        assert_eq!(
+            uuid_from_sha256("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+            "e3b0c442-98fc-8c14-9afb-f4c8996fb924"
+        );
+        // The version is always 8 and the variant always 10xx
+        assert_eq!(
+            uuid_from_sha256(&"0".repeat(64)),
+            "00000000-0000-8000-8000-000000000000"
+        );
+        assert_eq!(
+            uuid_from_sha256(&"f".repeat(64)),
+            "ffffffff-ffff-8fff-bfff-ffffffffffff"
+        );
+    }
+
+    #[test]
+    fn rfc3339_dates() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(1709210096), "2024-02-29T12:34:56Z");
+        assert_eq!(rfc3339(978307199), "2000-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn go_sum_checksums_are_properties() {
+        let component = module_component(&module("example.com/a", "v1.0.0", Some("h1:abc=")));
+        assert!(component.hashes.is_empty());
+        assert_eq!(
+            component.properties,
+            [("golang:h1".to_string(), "h1:abc=".to_string())]
+        );
+
+        let replaced = Module {
+            replace: Some(Box::new(module(
+                "example.com/fork",
+                "v1.1.0",
+                Some("h1:fork="),
+            ))),
+            ..module("example.com/a", "v1.0.0", Some("h1:abc="))
+        };
+        let component = module_component(&replaced);
+        assert_eq!(
+            component.purl.as_deref(),
+            Some("pkg:golang/example.com/fork@v1.1.0")
+        );
+        assert!(component.hashes.is_empty());
+        assert_eq!(
+            component.properties,
+            [
+                ("golang:h1".to_string(), "h1:fork=".to_string()),
+                (
+                    "golang:replaces".to_string(),
+                    "example.com/a v1.0.0".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn spdx_ids_are_unique() {
+        let first = module_component(&module("example.com/a_b", "v1.0.0", None));
+        let second = module_component(&module("example.com/a~b", "v1.0.0", None));
+        assert!(
+            first
+                .spdx_id()
+                .starts_with("SPDXRef-pkg-golang-example.com-a-b-v1.0.0-")
+        );
+        assert_ne!(first.spdx_id(), second.spdx_id());
+        assert_eq!(first.spdx_id(), first.clone().spdx_id());
+    }
+
+    #[test]
+    fn cyclonedx_documents() {
+        let dir = tempfile::tempdir().unwrap();
+        let (document, digests) = document(dir.path(), SbomFormat::CycloneDx);
+        assert_eq!(document["bomFormat"], "CycloneDX");
+        assert_eq!(document["specVersion"], "1.5");
+        assert_eq!(
+            document["serialNumber"],
+            format!("urn:uuid:{}", uuid_from_sha256(&digests.sha256))
+        );
+        assert_eq!(document["metadata"]["timestamp"], "2024-02-29T12:34:56Z");
+        assert_eq!(document["metadata"]["component"]["type"], "file");
+
+        let components = &document["components"];
+        let binary = find(components, "name", "xray.exe");
+        assert_eq!(binary["type"], "application");
+        assert_eq!(
+            binary["purl"],
+            "pkg:golang/github.com/xtls/xray-core/main@v1.2.3"
+        );
+        let distribution = |name| find(components, "name", name)["externalReferences"][0].clone();
+        assert_eq!(
+            distribution("geoip.dat"),
+            json!({"type": "distribution", "url": "https://example.com/geoip.dat"})
+        );
+        assert_eq!(distribution("geosite.dat")["type"], "distribution");
+        assert_eq!(
+            distribution("LICENSE-wintun.txt")["url"],
+            Wintun::default().url()
+        );
+        assert_eq!(distribution("v2ray.service")["type"], "vcs");
+        assert_eq!(distribution("README.md")["type"], "vcs");
+        let wintun = find(components, "name", "wintun");
+        assert_eq!(wintun["type"], "library");
+        assert_eq!(wintun["version"], Wintun::default().version());
+
+        let module = find(components, "name", "example.com/a_b");
+        assert_eq!(module.get("hashes"), None);
+        assert_eq!(
+            module["properties"],
+            json!([{"name": "golang:h1", "value": "h1:first="}])
+        );
+        assert_eq!(find(components, "name", "stdlib")["version"], "go1.24.2");
+    }
+
+    #[test]
+    fn spdx_documents() {
+        let dir = tempfile::tempdir().unwrap();
+        let (document, _) = document(dir.path(), SbomFormat::Spdx);
+        assert_eq!(document["spdxVersion"], "SPDX-2.3");
+        assert_eq!(document["SPDXID"], "SPDXRef-DOCUMENT");
+        assert_eq!(document["creationInfo"]["created"], "2024-02-29T12:34:56Z");
+
+        let packages = &document["packages"];
+        let ids: HashSet<&str> = packages
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|package| package["SPDXID"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids.len(), packages.as_array().unwrap().len());
+        for relationship in document["relationships"].as_array().unwrap() {
+            let related = relationship["relatedSpdxElement"].as_str().unwrap();
+            assert!(ids.contains(related), "{related}");
+        }
+
+        let id = |name| find(packages, "name", name)["SPDXID"].clone();
+        let relationships = |kind: &str, element: Value| {
+            document["relationships"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter(|r| r["relationshipType"] == kind && r["spdxElementId"] == element)
+                .map(|r| r["relatedSpdxElement"].clone())
+                .collect::<Vec<_>>()
+        };
+        let archive = id("xray-v1.2.3-windows-amd64.zip");
+        assert_eq!(
+            relationships("DESCRIBES", json!("SPDXRef-DOCUMENT")),
+            std::slice::from_ref(&archive)
+        );
+        let contained = relationships("CONTAINS", archive);
+        for name in [
+            "xray.exe",
+            "README.md",
+            "geoip.dat",
+            "geosite.dat",
+            "wintun",
+            "LICENSE-wintun.txt",
+            "v2ray.service",
+        ] {
+            assert!(contained.contains(&id(name)), "{name}");
+        }
+        assert_eq!(
+            relationships("DEPENDS_ON", id("xray.exe")),
+            [id("example.com/a_b"), id("example.com/a~b"), id("stdlib")]
+        );
+        assert_eq!(
+            find(packages, "name", "geoip.dat")["downloadLocation"],
+            "https://example.com/geoip.dat"
+        );
+        assert_eq!(
+            find(packages, "name", "v2ray.service")["downloadLocation"],
+            format!("git+{}@v1.2.3", file_url(dir.path()))
+        );
+        assert_eq!(
+            find(packages, "name", "example.com/a_b").get("checksums"),
+            None
+        );
+    }
+}
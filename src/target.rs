@@ -0,0 +1,283 @@
+use std::{fmt::Display, str::FromStr};
+
+/// The core to build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Core {
+    Xray,
+    V2ray,
+}
+impl Display for Core {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Core::Xray => write!(f, "Xray-core"),
+            Core::V2ray => write!(f, "v2ray-core"),
+        }
+    }
+}
+impl Core {
+    const XRAY_CORE_REPO: &'static str = "https://github.com/XTLS/Xray-core.git";
+    const V2RAY_CORE_REPO: &'static str = "https://github.com/v2fly/v2ray-core.git";
+
+    pub fn repo_url(&self) -> &'static str {
+        match self {
+            Core::Xray => Self::XRAY_CORE_REPO,
+            Core::V2ray => Self::V2RAY_CORE_REPO,
+        }
+    }
+
+    /// The branch checked out if no version is specified.
+    pub fn default_version(&self) -> &'static str {
+        match self {
+            Core::Xray => "main",
+            Core::V2ray => "master",
+        }
+    }
+
+    /// Lowercase name, used for the binary and the package.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Core::Xray => "xray",
+            Core::V2ray => "v2ray",
+        }
+    }
+
+    /// Name of the compiled binary for the given target.
+    pub fn binary_name(&self, target: &GoTarget) -> String {
+        if target.is_windows() {
+            format!("{}.exe", self.name())
+        } else {
+            self.name().to_string()
+        }
+    }
+}
+
+/// A single `GOOS`/`GOARCH` pair, optionally with a microarchitecture level.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GoTarget {
+    pub goos: String,
+    pub goarch: String,
+    /// Value of the microarchitecture variable of `goarch`, e.g. `v3` for `GOAMD64` or `7` for `GOARM`.
+    /// See [`GoTarget::variant_var`].
+    pub variant: Option<String>,
+}
+impl GoTarget {
+    pub fn new(goos: impl Into<String>, goarch: impl Into<String>) -> Self {
+        GoTarget {
+            goos: goos.into(),
+            goarch: goarch.into(),
+            variant: None,
+        }
+    }
+
+    /// Set the microarchitecture level, e.g. `v3` for amd64. The value is validated against `goarch`.
+    pub fn with_variant(mut self, variant: impl Into<String>) -> Result<Self, String> {
+        let variant = variant.into();
+        Self::check_variant(&self.goarch, &variant)?;
+        self.variant = Some(variant);
+        Ok(self)
+    }
+
+    pub fn is_windows(&self) -> bool {
+        self.goos.eq_ignore_ascii_case("windows")
+    }
+
+    /// The environment variable of the Go toolchain selecting the microarchitecture of `goarch`,
+    /// e.g. `GOAMD64` for amd64. `None` if the architecture has none this programme knows about.
+    pub fn variant_var(&self) -> Option<&'static str> {
+        variant_var(&self.goarch)
+    }
+
+    /// `goarch` followed by the microarchitecture level if there is one, e.g. `amd64-v3` or `arm-7`.
+    pub fn arch(&self) -> String {
+        match &self.variant {
+            Some(variant) => format!("{}-{}", self.goarch, variant.replace(',', "-")),
+            None => self.goarch.clone(),
+        }
+    }
+
+    /// Check that `variant` is a valid value of the microarchitecture variable of `goarch`:
+    ///
+    /// + `GOAMD64`: `v1` to `v4`
+    /// + `GOARM`: `5`, `6` or `7`, optionally followed by `,softfloat` or `,hardfloat`
+    /// + `GOARM64`: `v8.0` to `v8.9` or `v9.0` to `v9.5`, optionally followed by `,lse` and `,crypto`
+    /// + `GO386`: `sse2` or `softfloat`
+    /// + `GOMIPS` and `GOMIPS64`: `hardfloat` or `softfloat`
+    pub fn check_variant(goarch: &str, variant: &str) -> Result<(), String> {
+        let Some(var) = variant_var(goarch) else {
+            return Err(format!(
+                "GOARCH `{goarch}` has no microarchitecture level, but `{variant}` was given"
+            ));
+        };
+        let (level, options) = match variant.split_once(',') {
+            Some((level, options)) => (level, options.split(',').collect()),
+            None => (variant, Vec::new()),
+        };
+        let (levels, allowed_options): (&[&str], &[&str]) = match var {
+            "GOAMD64" => (&["v1", "v2", "v3", "v4"], &[]),
+            "GOARM" => (&["5", "6", "7"], &["softfloat", "hardfloat"]),
+            "GOARM64" => (
+                &[
+                    "v8.0", "v8.1", "v8.2", "v8.3", "v8.4", "v8.5", "v8.6", "v8.7", "v8.8", "v8.9",
+                    "v9.0", "v9.1", "v9.2", "v9.3", "v9.4", "v9.5",
+                ],
+                &["lse", "crypto"],
+            ),
+            "GO386" => (&["sse2", "softfloat"], &[]),
+            "GOMIPS" | "GOMIPS64" => (&["hardfloat", "softfloat"], &[]),
+            _ => unreachable!("unknown microarchitecture variable {var}"),
+        };
+
+        let valid_options = match var {
+            // `softfloat` and `hardfloat` exclude each other
+            "GOARM" => options.len() <= 1 && options.iter().all(|o| allowed_options.contains(o)),
+            _ => {
+                options.iter().all(|o| allowed_options.contains(o))
+                    && (1..options.len()).all(|i| !options[..i].contains(&options[i]))
+            }
+        };
+        if levels.contains(&level) && valid_options {
+            Ok(())
+        } else {
+            let mut expected = levels.join(", ");
+            if !allowed_options.is_empty() {
+                expected.push_str(&format!(
+                    ", optionally followed by {}",
+                    allowed_options
+                        .iter()
+                        .map(|o| format!("`,{o}`"))
+                        .collect::<Vec<_>>()
+                        .join(" or ")
+                ));
+            }
+            Err(format!(
+                "invalid {var} `{variant}` for GOARCH `{goarch}`, expected one of {expected}"
+            ))
+        }
+    }
+}
+
+/// See [`GoTarget::variant_var`].
+fn variant_var(goarch: &str) -> Option<&'static str> {
+    match goarch {
+        "amd64" => Some("GOAMD64"),
+        "arm" => Some("GOARM"),
+        "arm64" => Some("GOARM64"),
+        "386" => Some("GO386"),
+        "mips" | "mipsle" => Some("GOMIPS"),
+        "mips64" | "mips64le" => Some("GOMIPS64"),
+        _ => None,
+    }
+}
+
+impl Default for GoTarget {
+    fn default() -> Self {
+        GoTarget::new("linux", "amd64")
+    }
+}
+impl Display for GoTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.goos, self.goarch)?;
+        if let Some(variant) = &self.variant {
+            write!(f, "/{variant}")?;
+        }
+        Ok(())
+    }
+}
+impl FromStr for GoTarget {
+    type Err = String;
+
+    /// Parse `GOOS/GOARCH` or `GOOS/GOARCH/LEVEL`, e.g. `linux/amd64/v3`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.trim().split('/').collect();
+        match parts[..] {
+            [goos, goarch] if !goos.is_empty() && !goarch.is_empty() => {
+                Ok(GoTarget::new(goos.to_lowercase(), goarch.to_lowercase()))
+            }
+            [goos, goarch, variant]
+                if !goos.is_empty() && !goarch.is_empty() && !variant.is_empty() =>
+            {
+                GoTarget::new(goos.to_lowercase(), goarch.to_lowercase())
+                    .with_variant(variant.to_lowercase())
+            }
+            _ => Err(format!(
+                "invalid target `{s}`, expected `GOOS/GOARCH` or `GOOS/GOARCH/LEVEL`"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_valid_variants() {
+        for (goarch, variant) in [
+            ("amd64", "v1"),
+            ("amd64", "v4"),
+            ("arm", "5"),
+            ("arm", "7"),
+            ("arm", "7,softfloat"),
+            ("arm", "6,hardfloat"),
+            ("arm64", "v8.0"),
+            ("arm64", "v9.5,lse,crypto"),
+            ("386", "sse2"),
+            ("386", "softfloat"),
+            ("mips", "softfloat"),
+            ("mipsle", "hardfloat"),
+            ("mips64le", "softfloat"),
+        ] {
+            assert_eq!(
+                GoTarget::check_variant(goarch, variant),
+                Ok(()),
+                "{goarch}/{variant}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_variants() {
+        for (goarch, variant) in [
+            ("amd64", "v5"),
+            ("amd64", "3"),
+            ("amd64", "v3,lse"),
+            ("arm", "8"),
+            ("arm", "7,softfloat,hardfloat"),
+            ("arm", "7,lse"),
+            ("arm64", "v9.6"),
+            ("arm64", "v8.2,lse,lse"),
+            ("386", "sse4"),
+            ("mips", "v1"),
+            ("riscv64", "rva20u64"),
+        ] {
+            assert!(
+                GoTarget::check_variant(goarch, variant).is_err(),
+                "{goarch}/{variant}"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_targets_with_variants() {
+        let target: GoTarget = "Linux/ARM/7,SoftFloat".parse().unwrap();
+        assert_eq!(target.goos, "linux");
+        assert_eq!(target.goarch, "arm");
+        assert_eq!(target.variant.as_deref(), Some("7,softfloat"));
+        assert_eq!(target.arch(), "arm-7-softfloat");
+        assert_eq!(target.to_string(), "linux/arm/7,softfloat");
+
+        let target: GoTarget = "linux/mips/softfloat".parse().unwrap();
+        assert_eq!(target.variant_var(), Some("GOMIPS"));
+        assert_eq!(target.arch(), "mips-softfloat");
+
+        for invalid in [
+            "linux/amd64/v5",
+            "linux/arm/8",
+            "linux",
+            "linux/",
+            "linux/amd64/v3/x",
+        ] {
+            assert!(invalid.parse::<GoTarget>().is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::{
+    errors::{PackError, PackResult},
+    target::GoTarget,
+};
+
+/// An entry of `go tool dist list -json`.
+#[derive(Debug, Deserialize)]
+struct DistTarget {
+    #[serde(rename = "GOOS")]
+    goos: String,
+    #[serde(rename = "GOARCH")]
+    goarch: String,
+}
+
+/// Query the `GOOS`/`GOARCH` pairs supported by the installed Go toolchain.
+fn dist_list() -> PackResult<Vec<DistTarget>> {
+    let output = Command::new("go")
+        .args(["tool", "dist", "list", "-json"])
+        .output()
+        .map_err(|e| PackError::BuildFailed(format!("failed to run `go tool dist list`: {e}")))?;
+    if !output.status.success() {
+        return Err(PackError::BuildFailed(format!(
+            "`go tool dist list` exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    parse_dist_list(&output.stdout)
+}
+
+/// Parse the output of `go tool dist list -json`.
+fn parse_dist_list(json: &[u8]) -> PackResult<Vec<DistTarget>> {
+    serde_json::from_slice(json).map_err(|e| {
+        PackError::BuildFailed(format!("failed to parse `go tool dist list -json`: {e}"))
+    })
+}
+
+/// Check that the installed Go toolchain supports every target.
+/// The first unsupported target is returned as an error, together with the closest supported pairs.
+pub(crate) fn check_targets(targets: &[GoTarget]) -> PackResult<()> {
+    let supported = dist_list()?;
+    log::debug!(
+        "The installed Go toolchain supports {} targets",
+        supported.len()
+    );
+    check_supported(targets, &supported)
+}
+
+/// Check that every target is one of the `supported` pairs.
+fn check_supported(targets: &[GoTarget], supported: &[DistTarget]) -> PackResult<()> {
+    for target in targets {
+        if !supported
+            .iter()
+            .any(|dist| dist.goos == target.goos && dist.goarch == target.goarch)
+        {
+            let pair = format!("{}/{}", target.goos, target.goarch);
+            return Err(PackError::UnsupportedTarget {
+                hint: format!(
+                    "The installed Go toolchain does not support it. {}",
+                    close_matches(&pair, supported)
+                ),
+                target: pair,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Suggest up to three supported pairs that are close to `pair`.
+fn close_matches(pair: &str, supported: &[DistTarget]) -> String {
+    let mut candidates: Vec<(usize, String)> = supported
+        .iter()
+        .map(|dist| format!("{}/{}", dist.goos, dist.goarch))
+        .map(|candidate| (strsim::damerau_levenshtein(pair, &candidate), candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .collect();
+    candidates.sort();
+    candidates.truncate(3);
+
+    if candidates.is_empty() {
+        "Run `go tool dist list` to see all supported targets.".to_string()
+    } else {
+        format!(
+            "Did you mean {}?",
+            candidates
+                .into_iter()
+                .map(|(_, candidate)| candidate)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIST_LIST: &str = r#"[
+        {"GOOS": "android", "GOARCH": "arm64", "CgoSupported": true, "FirstClass": false},
+        {"GOOS": "darwin", "GOARCH": "amd64", "CgoSupported": true, "FirstClass": true},
+        {"GOOS": "linux", "GOARCH": "386", "CgoSupported": true, "FirstClass": true},
+        {"GOOS": "linux", "GOARCH": "amd64", "CgoSupported": true, "FirstClass": true},
+        {"GOOS": "linux", "GOARCH": "arm64", "CgoSupported": true, "FirstClass": true},
+        {"GOOS": "windows", "GOARCH": "amd64", "CgoSupported": true, "FirstClass": true}
+    ]"#;
+
+    fn supported() -> Vec<DistTarget> {
+        parse_dist_list(DIST_LIST.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn parses_dist_list() {
+        let supported = supported();
+        assert_eq!(supported.len(), 6);
+        assert_eq!(supported[0].goos, "android");
+        assert_eq!(supported[0].goarch, "arm64");
+        assert!(matches!(
+            parse_dist_list(b"linux/amd64"),
+            Err(PackError::BuildFailed(_))
+        ));
+    }
+
+    #[test]
+    fn accepts_supported_targets() {
+        let targets = [
+            GoTarget::new("linux", "amd64"),
+            GoTarget::new("windows", "amd64"),
+        ];
+        assert!(check_supported(&targets, &supported()).is_ok());
+        assert!(check_supported(&[], &[]).is_ok());
+    }
+
+    #[test]
+    fn suggests_close_matches() {
+        let targets = [
+            GoTarget::new("linux", "amd64"),
+            GoTarget::new("linux", "amd46"),
+        ];
+        let Err(PackError::UnsupportedTarget { target, hint }) =
+            check_supported(&targets, &supported())
+        else {
+            panic!("linux/amd46 is not supported");
+        };
+        assert_eq!(target, "linux/amd46");
+        assert!(hint.ends_with("Did you mean linux/amd64?"), "{hint}");
+    }
+
+    #[test]
+    fn suggests_the_three_closest_matches() {
+        let supported = ["arm", "armv5", "armv6", "armv7"].map(|goarch| DistTarget {
+            goos: "linux".to_string(),
+            goarch: goarch.to_string(),
+        });
+        assert_eq!(
+            close_matches("linux/armv9", &supported),
+            "Did you mean linux/armv5, linux/armv6, linux/armv7?"
+        );
+    }
+
+    #[test]
+    fn points_to_dist_list_without_close_matches() {
+        let Err(PackError::UnsupportedTarget { target, hint }) =
+            check_supported(&[GoTarget::new("plan9", "amd64")], &supported())
+        else {
+            panic!("plan9/amd64 is not supported");
+        };
+        assert_eq!(target, "plan9/amd64");
+        assert!(hint.ends_with("Run `go tool dist list` to see all supported targets."));
+        assert_eq!(
+            close_matches("linux/amd64", &[]),
+            "Run `go tool dist list` to see all supported targets."
+        );
+    }
+}